@@ -0,0 +1,615 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{error, io, thread};
+
+use synodl::syno::{self, Limits, SynoClient, Task};
+
+use crate::duplicate::find_link;
+
+/* Everything the user interface needs from a DownloadStation */
+pub trait DownloadBackend {
+    fn list(&mut self) -> Result<Vec<Task>, Box<dyn error::Error>>;
+    fn add(&mut self, uri: &str) -> Result<(), Box<dyn error::Error>>;
+    fn upload(&mut self, name: &str, data: &[u8]) -> Result<(), Box<dyn error::Error>>;
+    fn delete(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>>;
+    fn pause(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>>;
+    fn resume(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>>;
+    fn info(&mut self, task: &Task) -> Result<Task, Box<dyn error::Error>>;
+    fn limits(&mut self) -> Result<Limits, Box<dyn error::Error>>;
+    fn set_limits(&mut self, limits: &Limits) -> Result<(), Box<dyn error::Error>>;
+    fn logout(&mut self) -> Result<(), Box<dyn error::Error>>;
+
+    /* the devices left out of the last list, with the reason */
+    fn failures(&self) -> &[(String, String)] {
+        &[]
+    }
+
+    /* adds several tasks, with one result per URI */
+    fn add_many(&mut self, uris: &[&str]) -> Vec<Result<(), Box<dyn error::Error>>> {
+        uris.iter().map(|uri| self.add(uri)).collect()
+    }
+}
+
+impl DownloadBackend for SynoClient {
+    fn list(&mut self) -> Result<Vec<Task>, Box<dyn error::Error>> {
+        Ok(SynoClient::list(self)?)
+    }
+
+    fn add(&mut self, uri: &str) -> Result<(), Box<dyn error::Error>> {
+        Ok(self.download(uri)?)
+    }
+
+    fn upload(&mut self, name: &str, data: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        Ok(SynoClient::upload(self, name, data)?)
+    }
+
+    fn delete(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>> {
+        Ok(SynoClient::delete(self, &task.id)?)
+    }
+
+    fn pause(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>> {
+        Ok(SynoClient::pause(self, &task.id)?)
+    }
+
+    fn resume(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>> {
+        Ok(SynoClient::resume(self, &task.id)?)
+    }
+
+    fn info(&mut self, task: &Task) -> Result<Task, Box<dyn error::Error>> {
+        Ok(SynoClient::info(self, &task.id)?)
+    }
+
+    fn limits(&mut self) -> Result<Limits, Box<dyn error::Error>> {
+        Ok(SynoClient::limits(self)?)
+    }
+
+    fn set_limits(&mut self, limits: &Limits) -> Result<(), Box<dyn error::Error>> {
+        Ok(SynoClient::set_limits(self, limits)?)
+    }
+
+    fn logout(&mut self) -> Result<(), Box<dyn error::Error>> {
+        Ok(SynoClient::logout(self)?)
+    }
+
+    /* one request for all of them; when one of the URIs is rejected the others are
+       added one by one, leaving out those a request before it already added */
+    fn add_many(&mut self, uris: &[&str]) -> Vec<Result<(), Box<dyn error::Error>>> {
+        let failed = |e: &dyn error::Error| uris.iter().map(|_| Err(e.to_string().into())).collect();
+        if uris.len() < 2 {
+            return uris.iter().map(|uri| self.add(uri)).collect();
+        }
+
+        /* only tasks that were not there before can have come from this call */
+        let before: Vec<String> = match SynoClient::list(self) {
+            Ok(tasks) => tasks.into_iter().map(|t| t.id).collect(),
+            Err(e) => return failed(&e)
+        };
+        let e = match self.download_many(uris) {
+            Ok(()) => return uris.iter().map(|_| Ok(())).collect(),
+            Err(e) => e
+        };
+        let tasks = match e.code() {
+            Some(101) => SynoClient::list(self),
+            _ => return failed(&e)
+        };
+        match tasks {
+            Ok(mut tasks) => {
+                tasks.retain(|t| !before.contains(&t.id));
+                uris.iter().map(|uri| match find_link(uri, &tasks) {
+                    Some(_) => Ok(()),
+                    None => self.add(uri)
+                }).collect()
+            },
+            Err(e) => failed(&e)
+        }
+    }
+}
+
+/* whether logging in again might help */
+pub fn is_session_error(e: &(dyn error::Error + 'static)) -> bool {
+    let code = e.downcast_ref::<syno::Error>().and_then(syno::Error::code);
+    matches!(code, Some(106) | Some(107) | Some(119))
+}
+
+/* Task ids of a MultiBackend carry the name of the owning NAS */
+pub const SEPARATOR: char = '/';
+
+/* the name of the NAS a task from a MultiBackend belongs to */
+pub fn owner(task: &Task) -> Option<&str> {
+    task.id.split_once(SEPARATOR).map(|(nas, _)| nas)
+}
+
+pub type Member = (String, Box<dyn DownloadBackend + Send>);
+
+/* logs in to the named member again */
+pub type Login = Box<dyn FnMut(&str) -> Result<Box<dyn DownloadBackend + Send>, Box<dyn error::Error>>>;
+
+/* Several DownloadStations behind one task list */
+pub struct MultiBackend {
+    members: Vec<Member>,
+    primary: usize,
+    failures: Vec<(String, String)>,
+    login: Option<Login>
+}
+
+impl MultiBackend {
+    /* new tasks go to the member at index `primary` */
+    pub fn new(members: Vec<Member>, primary: usize)
+            -> MultiBackend {
+        MultiBackend { members, primary, failures: vec![], login: None }
+    }
+
+    /* lets members whose session ran out log in again */
+    pub fn with_login(self, login: Login) -> MultiBackend {
+        MultiBackend { login: Some(login), ..self }
+    }
+
+    /* the member owning a task and the task as that member knows it */
+    fn member(&mut self, task: &Task) -> Result<(&mut Member, Task), Box<dyn error::Error>> {
+        let not_found = || -> Box<dyn error::Error> {
+            Box::new(io::Error::other(format!("No NAS found for task {}", task.id)))
+        };
+        let (nas, id) = task.id.split_once(SEPARATOR).ok_or_else(not_found)?;
+        let member = self.members.iter_mut()
+            .find(|(name, _)| name == nas)
+            .ok_or_else(not_found)?;
+
+        let local = Task { id: String::from(id), ..task.clone() };
+        Ok((member, local))
+    }
+}
+
+fn no_primary() -> Box<dyn error::Error> {
+    Box::new(io::Error::other("No NAS to add tasks to"))
+}
+
+fn tag(nas: &str, task: Task) -> Task {
+    Task { id: format!("{}{}{}", nas, SEPARATOR, task.id), ..task }
+}
+
+impl DownloadBackend for MultiBackend {
+    /* ask all devices at once so that a slow one does not hold up the rest,
+       only fail when none of them answers */
+    fn list(&mut self) -> Result<Vec<Task>, Box<dyn error::Error>> {
+        let count = self.members.len();
+        /* errors are turned into text to leave the thread, remembering whether
+           logging in again might help */
+        let results: Vec<Result<Vec<Task>, (String, bool)>> = thread::scope(|s| {
+            let handles: Vec<_> = self.members.iter_mut().map(|(_, backend)| {
+                s.spawn(move || backend.list()
+                    .map_err(|e| (e.to_string(), is_session_error(e.as_ref()))))
+            }).collect();
+
+            handles.into_iter().map(|h| {
+                h.join().unwrap_or_else(|_| Err((String::from("Thread panicked"), false)))
+            }).collect()
+        });
+
+        let mut tasks = vec![];
+        let mut failures = vec![];
+        for (i, res) in results.into_iter().enumerate() {
+            let res = match (res, &mut self.login) {
+                (Err((_, true)), Some(login)) => {
+                    let (name, backend) = &mut self.members[i];
+                    login(name).and_then(|b| {
+                        *backend = b;
+                        backend.list()
+                    }).map_err(|e| e.to_string())
+                },
+                (res, _) => res.map_err(|(e, _)| e)
+            };
+            let name = &self.members[i].0;
+            match res {
+                Ok(list) => tasks.extend(list.into_iter().map(|t| tag(name, t))),
+                Err(e) => failures.push((name.clone(), e))
+            }
+        }
+
+        if count > 0 && failures.len() == count {
+            self.failures.clear();
+            let errors: Vec<String> = failures.iter()
+                .map(|(name, e)| format!("{}: {}", name, e))
+                .collect();
+            return Err(Box::new(io::Error::other(errors.join("\n"))));
+        }
+        self.failures = failures;
+        Ok(tasks)
+    }
+
+    fn failures(&self) -> &[(String, String)] {
+        &self.failures
+    }
+
+    fn add(&mut self, uri: &str) -> Result<(), Box<dyn error::Error>> {
+        match self.members.get_mut(self.primary) {
+            Some((_, backend)) => backend.add(uri),
+            None => Err(no_primary())
+        }
+    }
+
+    fn upload(&mut self, name: &str, data: &[u8]) -> Result<(), Box<dyn error::Error>> {
+        match self.members.get_mut(self.primary) {
+            Some((_, backend)) => backend.upload(name, data),
+            None => Err(no_primary())
+        }
+    }
+
+    fn add_many(&mut self, uris: &[&str]) -> Vec<Result<(), Box<dyn error::Error>>> {
+        match self.members.get_mut(self.primary) {
+            Some((_, backend)) => backend.add_many(uris),
+            None => uris.iter().map(|_| Err(no_primary())).collect()
+        }
+    }
+
+    fn delete(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>> {
+        let ((_, backend), local) = self.member(task)?;
+        backend.delete(&local)
+    }
+
+    fn pause(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>> {
+        let ((_, backend), local) = self.member(task)?;
+        backend.pause(&local)
+    }
+
+    fn resume(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>> {
+        let ((_, backend), local) = self.member(task)?;
+        backend.resume(&local)
+    }
+
+    fn info(&mut self, task: &Task) -> Result<Task, Box<dyn error::Error>> {
+        let ((name, backend), local) = self.member(task)?;
+        Ok(tag(name, backend.info(&local)?))
+    }
+
+    /* limits are per device, these are the ones new tasks would get */
+    fn limits(&mut self) -> Result<Limits, Box<dyn error::Error>> {
+        match self.members.get_mut(self.primary) {
+            Some((_, backend)) => backend.limits(),
+            None => Err(no_primary())
+        }
+    }
+
+    fn set_limits(&mut self, limits: &Limits) -> Result<(), Box<dyn error::Error>> {
+        match self.members.get_mut(self.primary) {
+            Some((_, backend)) => backend.set_limits(limits),
+            None => Err(no_primary())
+        }
+    }
+
+    fn logout(&mut self) -> Result<(), Box<dyn error::Error>> {
+        let mut res = Ok(());
+        for (_, backend) in self.members.iter_mut() {
+            if let Err(e) = backend.logout() {
+                res = Err(e);
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+pub mod memory {
+    use std::{error, io};
+
+    use synodl::syno::{self, Limits, Task};
+
+    use super::DownloadBackend;
+
+    /* A DownloadStation that only lives in memory, for testing */
+    #[derive(Default)]
+    pub struct MemoryBackend {
+        pub tasks: Vec<Task>,
+        pub limits: Limits,
+        pub logged_out: bool,
+        /* the device cannot be reached, listing fails */
+        pub offline: bool,
+        /* the session ran out, listing fails until logged in again */
+        pub expired: bool,
+        /* this many lists fail before it works again */
+        pub hiccups: usize,
+        next_id: usize
+    }
+
+    fn not_found() -> Box<dyn error::Error> {
+        Box::new(io::Error::other("Invalid task id"))
+    }
+
+    /* a half done task, everything the tests do not care about left at the default */
+    pub fn make_task(id: &str, title: &str, status: &str) -> Task {
+        Task {
+            id: String::from(id),
+            title: String::from(title),
+            status: String::from(status),
+            size: 1024000,
+            size_downloaded: 512000,
+            speed_download: 100,
+            percent_dn: 0.50,
+            ..Task::default()
+        }
+    }
+
+    /* for the connect closure of long-running modes, when logging in again must not work */
+    pub fn no_login() -> Result<MemoryBackend, Box<dyn error::Error>> {
+        Err(Box::new(io::Error::other("Connection refused")))
+    }
+
+    impl MemoryBackend {
+        pub fn new(tasks: Vec<Task>) -> MemoryBackend {
+            let next_id = tasks.len();
+            MemoryBackend { tasks, limits: Limits::default(), logged_out: false, offline: false,
+                            expired: false, hiccups: 0, next_id }
+        }
+
+        fn set_status(&mut self, task: &Task, status: &str)
+                -> Result<(), Box<dyn error::Error>> {
+            match self.tasks.iter_mut().find(|t| t.id == task.id) {
+                Some(t) => {
+                    t.status = String::from(status);
+                    Ok(())
+                },
+                None => Err(not_found())
+            }
+        }
+    }
+
+    impl DownloadBackend for MemoryBackend {
+        fn list(&mut self) -> Result<Vec<Task>, Box<dyn error::Error>> {
+            if self.offline {
+                return Err(Box::new(io::Error::other("Connection refused")));
+            }
+            if self.hiccups > 0 {
+                self.hiccups -= 1;
+                return Err(Box::new(io::Error::other("Connection reset")));
+            }
+            if self.expired {
+                let api = String::from("SYNO.DownloadStation2.Task");
+                return Err(Box::new(syno::Error::Api { api, code: 106 }));
+            }
+            Ok(self.tasks.clone())
+        }
+
+        fn add(&mut self, uri: &str) -> Result<(), Box<dyn error::Error>> {
+            if uri.is_empty() {
+                return Err(Box::new(io::Error::other("Invalid parameter")));
+            }
+            self.next_id += 1;
+            self.tasks.push(Task {
+                id: format!("dbid_{}", self.next_id),
+                title: String::from(uri),
+                status: String::from("waiting"),
+                uri: String::from(uri),
+                ..Task::default()
+            });
+            Ok(())
+        }
+
+        fn upload(&mut self, name: &str, data: &[u8]) -> Result<(), Box<dyn error::Error>> {
+            match data.is_empty() {
+                true => Err(Box::new(io::Error::other("File upload failed"))),
+                false => self.add(name)
+            }
+        }
+
+        fn delete(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>> {
+            let len = self.tasks.len();
+            self.tasks.retain(|t| t.id != task.id);
+            match self.tasks.len() < len {
+                true => Ok(()),
+                false => Err(not_found())
+            }
+        }
+
+        fn pause(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>> {
+            self.set_status(task, "paused")
+        }
+
+        fn resume(&mut self, task: &Task) -> Result<(), Box<dyn error::Error>> {
+            self.set_status(task, "downloading")
+        }
+
+        fn info(&mut self, task: &Task) -> Result<Task, Box<dyn error::Error>> {
+            match self.tasks.iter().find(|t| t.id == task.id) {
+                Some(t) => Ok(t.clone()),
+                None => Err(not_found())
+            }
+        }
+
+        fn limits(&mut self) -> Result<Limits, Box<dyn error::Error>> {
+            Ok(self.limits.clone())
+        }
+
+        fn set_limits(&mut self, limits: &Limits) -> Result<(), Box<dyn error::Error>> {
+            self.limits = limits.clone();
+            Ok(())
+        }
+
+        fn logout(&mut self) -> Result<(), Box<dyn error::Error>> {
+            self.logged_out = true;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use synodl::{fake::{FakeOptions, FakeServer}, syno::{ClientConfig, SynoClient}};
+
+    use super::{memory::{make_task, MemoryBackend}, owner, DownloadBackend, MultiBackend};
+
+    fn get_multi_backend() -> MultiBackend {
+        let home = MemoryBackend::new(vec![
+            make_task("dbid_1", "debian.iso", "downloading")
+        ]);
+        let office = MemoryBackend::new(vec![
+            make_task("dbid_1", "slackware.iso", "downloading"),
+            make_task("dbid_2", "archbang.iso", "paused")
+        ]);
+        MultiBackend::new(vec![
+            (String::from("home"), Box::new(home)),
+            (String::from("office"), Box::new(office))
+        ], 1)
+    }
+
+    #[test]
+    fn list_tasks_of_all_members() {
+        let mut multi = get_multi_backend();
+        let tasks = multi.list().unwrap();
+
+        let ids: Vec<&str> = tasks.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["home/dbid_1", "office/dbid_1", "office/dbid_2"]);
+        assert_eq!(owner(&tasks[1]), Some("office"));
+    }
+
+    #[test]
+    fn list_without_failing_member() {
+        let mut home = MemoryBackend::default();
+        home.offline = true;
+        let office = MemoryBackend::new(vec![make_task("dbid_1", "slackware.iso", "downloading")]);
+        let mut multi = MultiBackend::new(vec![
+            (String::from("home"), Box::new(home)),
+            (String::from("office"), Box::new(office))
+        ], 1);
+
+        let tasks = multi.list().unwrap();
+        let ids: Vec<&str> = tasks.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["office/dbid_1"]);
+        assert_eq!(multi.failures(),
+                   [(String::from("home"), String::from("Connection refused"))]);
+
+        /* the next list starts over */
+        let mut multi = get_multi_backend();
+        multi.list().unwrap();
+        assert!(multi.failures().is_empty());
+    }
+
+    #[test]
+    fn expired_members_log_in_again() {
+        let expired = || {
+            let mut home = MemoryBackend::new(vec![make_task("dbid_1", "debian.iso", "paused")]);
+            home.expired = true;
+            home
+        };
+        let office = || MemoryBackend::new(vec![make_task("dbid_1", "slackware.iso", "paused")]);
+
+        let mut multi = MultiBackend::new(vec![
+            (String::from("home"), Box::new(expired())),
+            (String::from("office"), Box::new(office()))
+        ], 1);
+        assert_eq!(multi.list().unwrap().len(), 1);
+        assert_eq!(multi.failures()[0].0, "home");
+
+        let mut multi = MultiBackend::new(vec![
+            (String::from("home"), Box::new(expired())),
+            (String::from("office"), Box::new(office()))
+        ], 1).with_login(Box::new(|name| {
+            assert_eq!(name, "home");
+            Ok(Box::new(MemoryBackend::new(vec![make_task("dbid_1", "debian.iso", "paused")])))
+        }));
+        let ids: Vec<String> = multi.list().unwrap().into_iter().map(|t| t.id).collect();
+        assert_eq!(ids, ["home/dbid_1", "office/dbid_1"]);
+        assert!(multi.failures().is_empty());
+    }
+
+    #[test]
+    fn list_fails_without_any_member() {
+        let offline = || {
+            let mut backend = MemoryBackend::default();
+            backend.offline = true;
+            backend
+        };
+        let mut multi = MultiBackend::new(vec![
+            (String::from("home"), Box::new(offline())),
+            (String::from("office"), Box::new(offline()))
+        ], 1);
+
+        let err = multi.list().unwrap_err();
+        assert_eq!(err.to_string(), "home: Connection refused\noffice: Connection refused");
+    }
+
+    #[test]
+    fn actions_go_to_owning_member() {
+        let mut multi = get_multi_backend();
+        let tasks = multi.list().unwrap();
+
+        multi.pause(&tasks[1]).unwrap();
+        multi.delete(&tasks[2]).unwrap();
+
+        let tasks = multi.list().unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].status, "downloading");
+        assert_eq!(tasks[1].status, "paused");
+
+        let info = multi.info(&tasks[1]).unwrap();
+        assert_eq!(info.id, "office/dbid_1");
+        assert_eq!(info.title, "slackware.iso");
+    }
+
+    #[test]
+    fn add_goes_to_primary_member() {
+        let mut multi = get_multi_backend();
+        multi.add("magnet:?xt=1").unwrap();
+
+        let tasks = multi.list().unwrap();
+        assert_eq!(tasks.len(), 4);
+        assert_eq!(tasks[3].id, "office/dbid_3");
+
+        let res = multi.add_many(&["magnet:?xt=2", "", "magnet:?xt=3"]);
+        assert!(res[0].is_ok() && res[1].is_err() && res[2].is_ok());
+        assert_eq!(multi.list().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn add_many_to_synology() {
+        for ds2 in [false, true] {
+            let options = FakeOptions { ds2, ..FakeOptions::default() };
+            let server = FakeServer::start_with(options, "127.0.0.1:0").unwrap();
+            let cfg = ClientConfig::new(server.url(), "admin", "secret");
+            let mut client = SynoClient::login(&cfg).unwrap();
+
+            let res = client.add_many(&["https://example.com/a.iso", "", "magnet:?xt=b"]);
+            assert!(res[0].is_ok() && res[1].is_err() && res[2].is_ok());
+            let uris: Vec<String> = server.tasks().into_iter().skip(5).map(|t| t.uri).collect();
+            assert_eq!(uris, ["https://example.com/a.iso", "magnet:?xt=b"]);
+
+            /* a copy of a task that was there before is still added */
+            let debian = "https://example.com/debian-12.5.0-amd64-netinst.iso";
+            let res = client.add_many(&[debian, ""]);
+            assert!(res[0].is_ok() && res[1].is_err());
+            assert_eq!(server.tasks().iter().filter(|t| t.uri == debian).count(), 2);
+
+            /* only a bad URI is worth trying the others one by one */
+            DownloadBackend::logout(&mut client).unwrap();
+            let res = client.add_many(&["https://example.com/c.iso", "magnet:?xt=d"]);
+            assert!(res.iter().all(|r| r.is_err()));
+            assert_eq!(server.tasks().len(), 8);
+        }
+    }
+
+    #[test]
+    fn unknown_member() {
+        let mut multi = get_multi_backend();
+        let task = make_task("garage/dbid_1", "freebsd.iso", "paused");
+        assert_eq!(multi.resume(&task).err().unwrap().to_string(),
+                   "No NAS found for task garage/dbid_1");
+
+        let task = make_task("dbid_1", "freebsd.iso", "paused");
+        assert!(multi.resume(&task).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{env, error, process, thread, time::Duration};
+
+use getopts::Options;
+use synodl::fake::{FakeOptions, FakeServer};
+
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} [options]\n
+Runs a fake DownloadStation for testing synodl without a NAS.", program);
+    print!("{}", opts.usage(&brief));
+}
+
+fn main() -> Result<(), Box<dyn error::Error>> {
+    let args: Vec<String> = env::args().collect();
+
+    let program = args[0].clone();
+    let mut opts = Options::new();
+
+    opts.optflag("h", "help", "Print help");
+    opts.optopt("l", "listen", "Address to listen on (default: 127.0.0.1:8888)", "ADDR");
+    opts.optopt("u", "user", "Account name to accept (default: admin)", "USER");
+    opts.optopt("p", "password", "Password to accept (default: secret)", "PASSWORD");
+    opts.optflag("", "legacy", "Only offer the legacy DSM 6 task API");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m }
+        Err(f) => {
+            eprintln!("{}", f);
+            print_usage(&program, opts);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&program, opts);
+        return Ok(());
+    }
+
+    let mut options = FakeOptions::default();
+    if let Some(user) = matches.opt_str("u") {
+        options.user = user;
+    }
+    if let Some(password) = matches.opt_str("p") {
+        options.password = password;
+    }
+    options.ds2 = !matches.opt_present("legacy");
+
+    let addr = matches.opt_str("l").unwrap_or_else(|| String::from("127.0.0.1:8888"));
+    let server = FakeServer::start_with(options, &addr)?;
+    println!("Fake DownloadStation listening on {}", server.url());
+
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        server.tick();
+    }
+}
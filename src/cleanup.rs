@@ -0,0 +1,198 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{error, time::{SystemTime, UNIX_EPOCH}};
+
+use synodl::syno::Task;
+
+use crate::{backend::DownloadBackend, config::CleanupRules};
+
+const DAY: u64 = 86400;
+
+fn days(secs: u64) -> String {
+    match secs / DAY {
+        1 => String::from("1 day"),
+        n => format!("{} days", n)
+    }
+}
+
+/* why the rules want the task gone, None if it may stay */
+fn reason(rules: &CleanupRules, task: &Task, now: u64) -> Option<String> {
+    match task.status.as_str() {
+        "finished" => {
+            /* older DSM versions only know when the task was created */
+            let since = match task.completed_time {
+                0 => task.create_time,
+                t => t
+            };
+            let age = now.saturating_sub(since);
+            match rules.finished_after {
+                Some(d) if since > 0 && age >= d.saturating_mul(DAY) => {
+                    Some(format!("finished {} ago", days(age)))
+                },
+                _ => None
+            }
+        },
+        "seeding" => {
+            if let Some(r) = rules.seeding_at_ratio {
+                if task.percent_up >= r {
+                    return Some(format!("ratio {:.2} reached", task.percent_up));
+                }
+            }
+            match rules.seeding_after {
+                Some(d) if task.seed_elapsed >= d.saturating_mul(DAY) => {
+                    Some(format!("seeding for {}", days(task.seed_elapsed)))
+                },
+                _ => None
+            }
+        },
+        _ => None
+    }
+}
+
+/* the tasks the rules want gone, with the reason */
+fn expired<'a>(rules: &CleanupRules, tasks: &'a [Task], now: u64) -> Vec<(&'a Task, String)> {
+    tasks.iter().filter_map(|t| reason(rules, t, now).map(|r| (t, r))).collect()
+}
+
+/* synodl cleanup, carries on after a task could not be removed */
+pub fn cleanup<D: DownloadBackend>(client: &mut D, rules: &CleanupRules, dry_run: bool)
+        -> Result<bool, Box<dyn error::Error>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    println!("Listing download tasks ...");
+    let tasks = client.list()?;
+
+    let expired = expired(rules, &tasks, now);
+    let mut ok = true;
+    let mut removed = 0;
+    for (task, reason) in &expired {
+        if dry_run {
+            println!("Would remove task {} ({}): {}", task.id, reason, task.title);
+            continue;
+        }
+        match client.delete(task) {
+            Ok(()) => {
+                println!("Removed task {} ({}): {}", task.id, reason, task.title);
+                removed += 1;
+            },
+            Err(e) => {
+                eprintln!("Task {}: {}", task.id, e);
+                ok = false;
+            }
+        }
+    }
+
+    match (expired.len(), dry_run) {
+        (0, _) => println!("Nothing to clean up"),
+        (n, true) => println!("Would remove {} of {} tasks", n, tasks.len()),
+        (_, false) => println!("Removed {} of {} tasks", removed, tasks.len())
+    }
+
+    println!("Disconnecting ...");
+    client.logout()?;
+    Ok(ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::{make_task, MemoryBackend};
+
+    const NOW: u64 = 1700000000;
+
+    fn old_task(id: &str, status: &str) -> Task {
+        Task { create_time: NOW - 30 * DAY, ..make_task(id, &format!("{}.iso", id), status) }
+    }
+
+    fn rules() -> CleanupRules {
+        CleanupRules { finished_after: Some(7), seeding_after: Some(3), seeding_at_ratio: Some(2.0) }
+    }
+
+    #[test]
+    fn finished_tasks() {
+        let mut t = old_task("dbid_1", "finished");
+        t.completed_time = NOW - 8 * DAY;
+        assert_eq!(reason(&rules(), &t, NOW).unwrap(), "finished 8 days ago");
+
+        t.completed_time = NOW - 6 * DAY;
+        assert_eq!(reason(&rules(), &t, NOW), None);
+
+        /* without a completion time the age of the task counts */
+        t.completed_time = 0;
+        assert_eq!(reason(&rules(), &t, NOW).unwrap(), "finished 30 days ago");
+
+        /* nothing known, nothing removed */
+        t.create_time = 0;
+        assert_eq!(reason(&rules(), &t, NOW), None);
+    }
+
+    #[test]
+    fn seeding_tasks() {
+        let mut t = old_task("dbid_1", "seeding");
+        t.percent_up = 1.5;
+        t.seed_elapsed = DAY;
+        assert_eq!(reason(&rules(), &t, NOW), None);
+
+        t.seed_elapsed = 3 * DAY;
+        assert_eq!(reason(&rules(), &t, NOW).unwrap(), "seeding for 3 days");
+
+        t.percent_up = 2.0;
+        assert_eq!(reason(&rules(), &t, NOW).unwrap(), "ratio 2.00 reached");
+    }
+
+    #[test]
+    fn huge_ages_keep_tasks() {
+        let rules = CleanupRules { finished_after: Some(u64::MAX), seeding_after: Some(u64::MAX),
+                                   seeding_at_ratio: None };
+        let mut seeding = old_task("dbid_1", "seeding");
+        seeding.seed_elapsed = 100 * DAY;
+        assert_eq!(reason(&rules, &seeding, NOW), None);
+        assert_eq!(reason(&rules, &old_task("dbid_2", "finished"), NOW), None);
+    }
+
+    #[test]
+    fn rules_only_apply_when_configured() {
+        let mut seeding = old_task("dbid_1", "seeding");
+        seeding.percent_up = 5.0;
+        seeding.seed_elapsed = 100 * DAY;
+        let finished = old_task("dbid_2", "finished");
+        let tasks = [seeding, finished, old_task("dbid_3", "downloading")];
+
+        assert!(expired(&CleanupRules::default(), &tasks, NOW).is_empty());
+
+        let only_finished = CleanupRules { finished_after: Some(1), ..CleanupRules::default() };
+        let ids: Vec<&str> = expired(&only_finished, &tasks, NOW).iter()
+            .map(|(t, _)| t.id.as_str()).collect();
+        assert_eq!(ids, ["dbid_2"]);
+        assert_eq!(expired(&rules(), &tasks, NOW).len(), 2);
+    }
+
+    #[test]
+    fn dry_run_keeps_tasks() {
+        let mut backend = MemoryBackend::new(vec![old_task("dbid_1", "finished"),
+                                                  old_task("dbid_2", "paused")]);
+        assert!(cleanup(&mut backend, &rules(), true).unwrap());
+        assert_eq!(backend.tasks.len(), 2);
+
+        assert!(cleanup(&mut backend, &rules(), false).unwrap());
+        let ids: Vec<&str> = backend.tasks.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["dbid_2"]);
+        assert!(backend.logged_out);
+    }
+}
@@ -0,0 +1,796 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{error, fs, fs::File, io, io::BufRead, io::BufReader, time::Duration};
+
+use getopts::{Matches, Options};
+use synodl::syno::{Limits, SynoClient, Task};
+
+use crate::{
+    backend::DownloadBackend,
+    duplicate::{find_file, find_link},
+    exporter,
+    handler::{local_file, read_local},
+    monitor,
+    stats::Period,
+    torrent::Torrent,
+    ui::util::{limit_text, size_text, speed_text},
+    wait::wait_for_new_tasks
+};
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Add { urls: Vec<String>, from_file: Option<String>, wait: bool, timeout: Option<u64>,
+          force: bool, preview: bool },
+    List { status: Option<String> },
+    Info { ids: Vec<String> },
+    Pause { ids: Vec<String> },
+    Resume { ids: Vec<String>, all_tasks: bool },
+    Delete { ids: Vec<String> },
+    Watch { dir: String },
+    Monitor { interval: u64, auto_resume: bool },
+    Exporter { listen: String, cache: u64 },
+    Stats { period: Period },
+    Cleanup { dry_run: bool },
+    /* new limits in KB/s, 0 for unlimited */
+    Limits { down: Option<u64>, up: Option<u64> },
+    Tui
+}
+
+/* name, arguments, description */
+const COMMANDS: [(&str, &str, &str); 13] = [
+    ("add", "URL...", "Add download tasks, - reads URLs from stdin"),
+    ("list", "", "List download tasks"),
+    ("info", "ID...", "Show the details of tasks"),
+    ("pause", "ID...", "Pause tasks"),
+    ("resume", "ID...", "Resume tasks"),
+    ("delete", "ID...", "Delete tasks"),
+    ("watch", "DIR", "Add .torrent, .nzb, .magnet and .url files saved in DIR"),
+    ("monitor", "", "Run the on_* hooks when tasks change, until interrupted"),
+    ("exporter", "", "Serve task metrics for Prometheus, until interrupted"),
+    ("cleanup", "", "Remove the tasks the rules in [cleanup] are done with"),
+    ("stats", "", "Show the traffic recorded while synodl was running"),
+    ("limits", "", "Show or change the speed limits of the NAS"),
+    ("tui", "", "Show the interactive task list (the default)")
+];
+
+/* An option and the commands it belongs to, none for global options */
+struct Opt {
+    commands: &'static [&'static str],
+    short: &'static str,
+    long: &'static str,
+    desc: &'static str,
+    hint: Option<&'static str>
+}
+
+const fn flag(commands: &'static [&'static str], short: &'static str,
+              long: &'static str, desc: &'static str) -> Opt {
+    Opt { commands, short, long, desc, hint: None }
+}
+
+const fn opt(commands: &'static [&'static str], short: &'static str, long: &'static str,
+             desc: &'static str, hint: &'static str) -> Opt {
+    Opt { commands, short, long, desc, hint: Some(hint) }
+}
+
+const OPTIONS: [Opt; 31] = [
+    flag(&[], "h", "help", "Print help"),
+    opt(&[], "p", "profile", "Use the NAS profile NAME from the configuration", "NAME"),
+    flag(&[], "a", "all", "Show the tasks of all profiles together"),
+    opt(&[], "c", "config", "Read the configuration from PATH", "PATH"),
+    opt(&[], "", "url", "Connect to the DownloadStation at URL", "URL"),
+    opt(&[], "", "user", "Log in as USER", "USER"),
+    flag(&[], "", "init", "Create a configuration file interactively"),
+    flag(&[], "", "check-config", "Check the configuration and the login, then exit"),
+    flag(&[], "", "install-handler", "Open magnet links and torrent files with synodl"),
+    opt(&[], "", "handle", "Add URI without any user interface, for the desktop", "URI"),
+    opt(&[], "", "record", "Save all API requests and responses in DIR", "DIR"),
+    opt(&[], "", "replay", "Answer API requests from a recording in DIR", "DIR"),
+    /* kept for scripts written for older versions */
+    flag(&[], "l", "list", "Same as the list command"),
+    flag(&[], "r", "resume-all", "Same as resume --all-tasks"),
+    flag(&["resume"], "", "all-tasks", "Resume all tasks"),
+    opt(&["add"], "f", "from-file", "Read URLs from FILE, one per line", "FILE"),
+    flag(&["add"], "w", "wait", "Wait until the new tasks are finished"),
+    opt(&["add"], "t", "timeout", "Give up waiting after SECS seconds", "SECS"),
+    flag(&["add"], "", "force", "Add URLs even if there already is a task for them"),
+    flag(&["add"], "", "preview", "Show what torrents contain instead of adding them"),
+    opt(&["monitor"], "i", "interval", "Look at the tasks every SECS seconds", "SECS"),
+    flag(&["monitor"], "", "auto-resume", "Resume failed tasks, waiting longer each time"),
+    opt(&["exporter"], "", "listen", "Serve on ADDR:PORT (default 127.0.0.1:9781)", "ADDR"),
+    opt(&["exporter"], "", "cache", "Reuse the task list for SECS seconds (default 15)",
+        "SECS"),
+    flag(&["cleanup"], "n", "dry-run", "Only show what would be removed"),
+    flag(&["stats"], "", "day", "Per hour over the last 24 hours (the default)"),
+    flag(&["stats"], "", "week", "Per day over the last 7 days"),
+    flag(&["stats"], "", "month", "Per day over the last 30 days"),
+    opt(&["limits"], "", "down", "Limit downloads to N KB/s, 0 for unlimited", "N"),
+    opt(&["limits"], "", "up", "Limit BitTorrent uploads to N KB/s, 0 for unlimited", "N"),
+    opt(&["list"], "s", "status", "Only list tasks with the given STATUS", "STATUS"),
+];
+
+fn make_options<F: Fn(&Opt) -> bool>(filter: F) -> Options {
+    let mut opts = Options::new();
+    for o in OPTIONS.iter().filter(|o| filter(o)) {
+        match o.hint {
+            Some(hint) => opts.optopt(o.short, o.long, o.desc, hint),
+            None => opts.optflag(o.short, o.long, o.desc)
+        };
+    }
+    opts
+}
+
+pub struct Invocation {
+    pub matches: Matches,
+    pub command: Command,
+    /* the command help was asked for, if any */
+    pub help: Option<String>
+}
+
+fn is_command(name: &str) -> bool {
+    COMMANDS.iter().any(|(c, _, _)| *c == name)
+}
+
+fn seconds(matches: &Matches, name: &str) -> Result<Option<u64>, String> {
+    match matches.opt_str(name) {
+        Some(s) => match s.parse::<u64>() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(format!("Invalid {} {}, expected seconds", name, s))
+        },
+        None => Ok(None)
+    }
+}
+
+fn rate(matches: &Matches, name: &str) -> Result<Option<u64>, String> {
+    match matches.opt_str(name) {
+        Some(s) if s == "unlimited" => Ok(Some(0)),
+        Some(s) => match s.parse::<u64>() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(format!("Invalid {} {}, expected KB/s", name, s))
+        },
+        None => Ok(None)
+    }
+}
+
+pub fn parse(args: &[String]) -> Result<Invocation, String> {
+    /* options may come before or after the command */
+    let matches = make_options(|_| true).parse(args).map_err(|e| e.to_string())?;
+
+    let (name, free) = match matches.free.first() {
+        Some(c) if c == "help" => {
+            let help = matches.free.get(1).filter(|c| is_command(c)).cloned();
+            return Ok(Invocation { matches, command: Command::Tui,
+                                   help: Some(help.unwrap_or_default()) });
+        },
+        Some(c) if is_command(c) => (c.as_str(), matches.free[1..].to_vec()),
+        /* synodl URL is short for synodl add URL */
+        Some(_) => ("add", matches.free.clone()),
+        None if matches.opt_present("l") => ("list", vec![]),
+        None if matches.opt_present("resume-all") => ("resume", vec![]),
+        None => ("tui", vec![])
+    };
+    let name = String::from(name);
+
+    for o in OPTIONS.iter().filter(|o| !o.commands.is_empty()) {
+        if matches.opt_present(o.long) && !o.commands.contains(&name.as_str()) {
+            return Err(format!("--{} does not work with {}", o.long, name));
+        }
+    }
+
+    if matches.opt_present("help") {
+        let name = match matches.free.is_empty() {
+            true => String::new(),
+            false => name
+        };
+        return Ok(Invocation { matches, command: Command::Tui, help: Some(name) });
+    }
+
+    let needs_ids = || match free.is_empty() {
+        true => Err(format!("{} needs at least one task ID", name)),
+        false => Ok(free.clone())
+    };
+
+    let command = match name.as_str() {
+        "add" => match (free.is_empty(), matches.opt_str("from-file")) {
+            (true, None) => return Err(String::from("add needs at least one URL")),
+            (_, from_file) => {
+                let timeout = seconds(&matches, "timeout")?;
+                let wait = matches.opt_present("wait");
+                if timeout.is_some() && !wait {
+                    return Err(String::from("--timeout only works with --wait"));
+                }
+                let preview = matches.opt_present("preview");
+                if preview && wait {
+                    return Err(String::from("--preview does not add anything to wait for"));
+                }
+                Command::Add { urls: free.clone(), from_file, wait, timeout,
+                               force: matches.opt_present("force"), preview }
+            }
+        },
+        "list" => Command::List { status: matches.opt_str("status") },
+        "info" => Command::Info { ids: needs_ids()? },
+        "pause" => Command::Pause { ids: needs_ids()? },
+        "resume" => {
+            let all_tasks = matches.opt_present("all-tasks") || matches.opt_present("resume-all");
+            match all_tasks {
+                true => Command::Resume { ids: free.clone(), all_tasks },
+                false => Command::Resume { ids: needs_ids()?, all_tasks }
+            }
+        },
+        "delete" => Command::Delete { ids: needs_ids()? },
+        "watch" => match free.as_slice() {
+            [dir] => Command::Watch { dir: dir.clone() },
+            _ => return Err(String::from("watch needs exactly one directory"))
+        },
+        "monitor" => Command::Monitor {
+            interval: seconds(&matches, "interval")?.unwrap_or(monitor::INTERVAL),
+            auto_resume: matches.opt_present("auto-resume")
+        },
+        "exporter" => Command::Exporter {
+            listen: matches.opt_str("listen").unwrap_or_else(|| String::from(exporter::LISTEN)),
+            cache: seconds(&matches, "cache")?.unwrap_or(exporter::CACHE)
+        },
+        "cleanup" => Command::Cleanup { dry_run: matches.opt_present("dry-run") },
+        "stats" => {
+            let periods: Vec<Period> = [("day", Period::Day), ("week", Period::Week),
+                                        ("month", Period::Month)].iter()
+                .filter(|(name, _)| matches.opt_present(name))
+                .map(|(_, p)| *p)
+                .collect();
+            match periods.as_slice() {
+                [] => Command::Stats { period: Period::Day },
+                [period] => Command::Stats { period: *period },
+                _ => return Err(String::from("Pick one of --day, --week and --month"))
+            }
+        },
+        "limits" => Command::Limits { down: rate(&matches, "down")?, up: rate(&matches, "up")? },
+        _ => Command::Tui
+    };
+
+    if matches!(command, Command::List { .. } | Command::Monitor { .. }
+                               | Command::Exporter { .. } | Command::Stats { .. }
+                               | Command::Cleanup { .. } | Command::Limits { .. }
+                               | Command::Tui)
+            && !free.is_empty() {
+        return Err(format!("{} takes no arguments", name));
+    }
+
+    Ok(Invocation { matches, command, help: None })
+}
+
+pub fn print_usage(program: &str, command: &str) {
+    match COMMANDS.iter().find(|(c, _, _)| *c == command) {
+        Some((name, args, desc)) => {
+            let brief = format!("Usage: {} {} [options] {}\n\n{}.", program, name, args, desc);
+            let opts = make_options(|o| o.commands.contains(name) || o.long == "help");
+            print!("{}", opts.usage(&brief));
+            println!("\nSee {} --help for the options all commands share.", program);
+        },
+        None => {
+            let mut brief = format!("Usage: {} [options] [COMMAND] [ARGS]\n\nCommands:\n", program);
+            for (name, args, desc) in COMMANDS.iter() {
+                brief.push_str(&format!("    {:<16} {}\n", format!("{} {}", name, args), desc));
+            }
+            brief.push_str(&format!("\n{} URL is short for {} add URL.\n\
+                                     Use {} help COMMAND for the options of a command.",
+                                    program, program, program));
+            print!("{}", make_options(|o| o.commands.is_empty()).usage(&brief));
+        }
+    }
+
+    println!("\nThis is synodl {}.", env!("CARGO_PKG_VERSION"));
+    println!("Report bugs at {}", env!("CARGO_PKG_HOMEPAGE"));
+}
+
+/* how many URLs go into a single request, more make the URL too long */
+const BATCH_SIZE: usize = 20;
+
+/* one URL per line, empty lines and those starting with # are skipped */
+pub fn read_urls<R: BufRead>(input: R) -> io::Result<Vec<String>> {
+    let mut urls = vec![];
+    for line in input.lines() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            urls.push(String::from(line));
+        }
+    }
+    Ok(urls)
+}
+
+fn read_url_file(path: &str) -> Result<Vec<String>, String> {
+    let res = match path {
+        "-" => read_urls(io::stdin().lock()),
+        _ => File::open(path).and_then(|f| read_urls(BufReader::new(f)))
+    };
+    res.map_err(|e| match path {
+        "-" => format!("Cannot read URLs from stdin: {}", e),
+        _ => format!("Cannot read URLs from {}: {}", path, e)
+    })
+}
+
+/* the URLs to add, with - and --from-file replaced by what they contain */
+pub fn load_urls(urls: &[String], from_file: Option<&str>) -> Result<Vec<String>, String> {
+    let mut all = vec![];
+    for url in urls {
+        match url.as_str() {
+            "-" => all.extend(read_url_file("-")?),
+            _ => all.push(url.clone())
+        }
+    }
+    if let Some(path) = from_file {
+        all.extend(read_url_file(path)?);
+    }
+
+    match all.is_empty() {
+        true => Err(String::from("No URLs to add")),
+        false => Ok(all)
+    }
+}
+
+/* what a task for a URL or local torrent file would look like, to find copies of it */
+fn planned_task(url: &str) -> Task {
+    let torrent = local_file(url)
+        .and_then(|path| fs::read(path).ok())
+        .and_then(|data| Torrent::parse(&data).ok());
+    match torrent {
+        Some(t) => Task { uri: format!("magnet:?xt=urn:btih:{}", t.info_hash), title: t.name,
+                          size: t.length, ..Task::default() },
+        None => Task { uri: String::from(url), title: String::from(url), ..Task::default() }
+    }
+}
+
+/* leaves out the URLs and files there already is a task for, or that come up twice */
+fn skip_duplicates(urls: &[String], tasks: &[Task]) -> Vec<String> {
+    let mut known = tasks.to_vec();
+    let mut keep = vec![];
+    for url in urls {
+        let copy = match local_file(url) {
+            Some(path) => fs::read(path).ok().and_then(|data| find_file(&data, &known)),
+            None => find_link(url, &known)
+        };
+        match copy {
+            Some(t) => println!("SKIPPED {}: already added as {}", url, t.title),
+            None => {
+                known.push(planned_task(url));
+                keep.push(url.clone());
+            }
+        }
+    }
+
+    if keep.len() < urls.len() {
+        println!("{} of {} URLs were added before, use --force to add them anyway",
+                 urls.len() - keep.len(), urls.len());
+    }
+    keep
+}
+
+fn add_tasks<D: DownloadBackend>(client: &mut D, urls: &[String]) -> bool {
+    match urls.len() {
+        1 => println!("Adding download task ..."),
+        n => println!("Adding {} download tasks ...", n)
+    }
+
+    let mut failed = 0;
+    let mut links = vec![];
+    for url in urls {
+        let path = match local_file(url) {
+            Some(path) => path,
+            None => {
+                links.push(url.as_str());
+                continue;
+            }
+        };
+        let res = read_local(&path).map_err(|e| e.into())
+            .and_then(|(name, data)| client.upload(&name, &data));
+        match res {
+            Ok(()) => println!("OK      {}", url),
+            Err(e) => {
+                println!("FAILED  {}: {}", url, e);
+                failed += 1;
+            }
+        }
+    }
+
+    for batch in links.chunks(BATCH_SIZE) {
+        for (url, res) in batch.iter().zip(client.add_many(batch)) {
+            match res {
+                Ok(()) => println!("OK      {}", url),
+                Err(e) => {
+                    println!("FAILED  {}: {}", url, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        println!("{} of {} URLs could not be added", failed, urls.len());
+    }
+    failed == 0
+}
+
+/* a task we only know the ID of */
+fn task_ref(id: &str) -> Task {
+    Task { id: String::from(id), ..Task::default() }
+}
+
+/* runs an action on each of the given tasks, carrying on after failures */
+fn for_each_task<D, F>(client: &mut D, ids: &[String], done: &str, mut action: F) -> bool
+        where D: DownloadBackend,
+              F: FnMut(&mut D, &Task) -> Result<(), Box<dyn error::Error>> {
+    let mut ok = true;
+    for id in ids {
+        match action(client, &task_ref(id)) {
+            Ok(()) => println!("{} task {}", done, id),
+            Err(e) => {
+                eprintln!("Task {}: {}", id, e);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+fn print_task(t: &Task) {
+    println!("ID:          {}", t.id);
+    println!("Title:       {}", t.title);
+    println!("Status:      {}", t.status);
+    println!("Size:        {}", size_text(t.size));
+    println!("Downloaded:  {} ({:.1}%)", size_text(t.size_downloaded), 100.0 * t.percent_dn);
+    println!("Uploaded:    {} (ratio {:.2})", size_text(t.size_uploaded), t.percent_up);
+    println!("Speed:       down {}, up {}", speed_text(t.speed_download),
+             speed_text(t.speed_upload));
+}
+
+fn print_limits(limits: &Limits) {
+    println!("              Download    Upload");
+    println!("BitTorrent    {:<12}{}", limit_text(limits.bt_download),
+             limit_text(limits.bt_upload));
+    println!("HTTP          {}", limit_text(limits.http_download));
+    println!("FTP           {}", limit_text(limits.ftp_download));
+}
+
+/* carries out the command, returns whether everything worked; connect logs in
+   again when the session runs out during --wait */
+pub fn run<D, F>(client: &mut D, command: &Command, connect: F)
+        -> Result<bool, Box<dyn error::Error>>
+        where D: DownloadBackend,
+              F: FnMut() -> Result<D, Box<dyn error::Error>> {
+    let ok = match command {
+        Command::Add { urls, wait: false, force: true, .. } => add_tasks(client, urls),
+        Command::Add { urls, wait, timeout, force, .. } => {
+            let tasks = client.list()?;
+            let urls = match force {
+                true => urls.clone(),
+                false => skip_duplicates(urls, &tasks)
+            };
+            let before: Vec<String> = tasks.into_iter().map(|t| t.id).collect();
+            match (urls.is_empty(), wait) {
+                (true, _) => true,
+                (false, false) => add_tasks(client, &urls),
+                (false, true) => match add_tasks(client, &urls) {
+                    true => {
+                        let timeout = timeout.map(Duration::from_secs);
+                        wait_for_new_tasks(client, connect, &before, urls.len(), timeout)?
+                    },
+                    false => false
+                }
+            }
+        },
+        Command::List { status } => {
+            println!("Listing download tasks ...");
+            let tasks = client.list()?;
+            for t in tasks.iter().filter(|t| status.iter().all(|s| *s == t.status)) {
+                println!("Task ID {0} status {1} titled {2}", t.id, t.status, t.title);
+            }
+            for (nas, e) in client.failures() {
+                eprintln!("Cannot list tasks of {}: {}", nas, e);
+            }
+            client.failures().is_empty()
+        },
+        Command::Info { ids } => {
+            let mut ok = true;
+            for (i, id) in ids.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                match client.info(&task_ref(id)) {
+                    Ok(t) => print_task(&t),
+                    Err(e) => {
+                        eprintln!("Task {}: {}", id, e);
+                        ok = false;
+                    }
+                }
+            }
+            ok
+        },
+        Command::Pause { ids } => for_each_task(client, ids, "Paused", |c, t| c.pause(t)),
+        Command::Resume { ids, .. } => for_each_task(client, ids, "Resumed", |c, t| c.resume(t)),
+        Command::Delete { ids } => for_each_task(client, ids, "Deleted", |c, t| c.delete(t)),
+        Command::Limits { down, up } => {
+            let mut limits = client.limits()?;
+            if down.is_some() || up.is_some() {
+                limits = limits.with(*down, *up);
+                client.set_limits(&limits)?;
+                println!("Changed the speed limits");
+            }
+            print_limits(&limits);
+            true
+        },
+        Command::Watch { .. } | Command::Monitor { .. } | Command::Exporter { .. }
+            | Command::Stats { .. } | Command::Cleanup { .. } | Command::Tui => true
+    };
+
+    println!("Disconnecting ...");
+    client.logout()?;
+    Ok(ok)
+}
+
+pub fn resume_all_tasks(client: SynoClient) -> Result<bool, Box<dyn error::Error>> {
+    println!("Resuming all download tasks ...");
+    let n = client.resume_all()?;
+    println!("Resumed {} tasks", n);
+
+    println!("Disconnecting ...");
+    client.logout()?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::{no_login, MemoryBackend};
+
+    fn parse_args(args: &str) -> Result<Invocation, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        parse(&args)
+    }
+
+    fn command(args: &str) -> Command {
+        parse_args(args).unwrap().command
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|s| String::from(*s)).collect()
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(command(""), Command::Tui);
+        assert_eq!(command("tui"), Command::Tui);
+        assert_eq!(command("list"), Command::List { status: None });
+        assert_eq!(command("list -s paused"),
+                   Command::List { status: Some(String::from("paused")) });
+        assert_eq!(command("add magnet:?xt=1"),
+                   Command::Add { urls: ids(&["magnet:?xt=1"]), from_file: None, wait: false,
+                                  timeout: None, force: false, preview: false });
+        assert_eq!(command("add a b - -f list.txt"),
+                   Command::Add { urls: ids(&["a", "b", "-"]),
+                                  from_file: Some(String::from("list.txt")), wait: false,
+                                  timeout: None, force: false, preview: false });
+        assert_eq!(command("add -w -t 600 a"),
+                   Command::Add { urls: ids(&["a"]), from_file: None, wait: true,
+                                  timeout: Some(600), force: false, preview: false });
+        assert_eq!(command("add --preview a"),
+                   Command::Add { urls: ids(&["a"]), from_file: None, wait: false,
+                                  timeout: None, force: false, preview: true });
+        assert_eq!(command("add --force a"),
+                   Command::Add { urls: ids(&["a"]), from_file: None, wait: false,
+                                  timeout: None, force: true, preview: false });
+        assert_eq!(command("add --from-file list.txt"),
+                   Command::Add { urls: vec![], from_file: Some(String::from("list.txt")),
+                                  wait: false, timeout: None, force: false, preview: false });
+        assert_eq!(command("info dbid_1 dbid_2"), Command::Info { ids: ids(&["dbid_1", "dbid_2"]) });
+        assert_eq!(command("pause dbid_1"), Command::Pause { ids: ids(&["dbid_1"]) });
+        assert_eq!(command("delete dbid_1"), Command::Delete { ids: ids(&["dbid_1"]) });
+        assert_eq!(command("watch Downloads"), Command::Watch { dir: String::from("Downloads") });
+        assert_eq!(command("monitor"), Command::Monitor { interval: 30, auto_resume: false });
+        assert_eq!(command("monitor -i 5 --auto-resume"),
+                   Command::Monitor { interval: 5, auto_resume: true });
+        assert_eq!(command("exporter"), Command::Exporter { listen: String::from("127.0.0.1:9781"),
+                                                            cache: 15 });
+        assert_eq!(command("exporter --listen [::]:9000 --cache 60"),
+                   Command::Exporter { listen: String::from("[::]:9000"), cache: 60 });
+        assert_eq!(command("stats"), Command::Stats { period: Period::Day });
+        assert_eq!(command("stats --month"), Command::Stats { period: Period::Month });
+        assert_eq!(command("stats --week"), Command::Stats { period: Period::Week });
+        assert_eq!(command("cleanup -n"), Command::Cleanup { dry_run: true });
+        assert_eq!(command("resume --all-tasks"),
+                   Command::Resume { ids: vec![], all_tasks: true });
+    }
+
+    #[test]
+    fn old_style_arguments() {
+        assert_eq!(command("http://foo/ http://bar/"),
+                   Command::Add { urls: ids(&["http://foo/", "http://bar/"]), from_file: None,
+                                  wait: false, timeout: None, force: false, preview: false });
+        assert_eq!(command("-l"), Command::List { status: None });
+        assert_eq!(command("-r"), Command::Resume { ids: vec![], all_tasks: true });
+    }
+
+    #[test]
+    fn global_options_anywhere() {
+        let inv = parse_args("list --profile office").unwrap();
+        assert_eq!(inv.command, Command::List { status: None });
+        assert_eq!(inv.matches.opt_str("profile").as_deref(), Some("office"));
+
+        let inv = parse_args("-p office pause dbid_1").unwrap();
+        assert_eq!(inv.command, Command::Pause { ids: ids(&["dbid_1"]) });
+        assert_eq!(inv.matches.opt_str("profile").as_deref(), Some("office"));
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(parse_args("--help").unwrap().help.as_deref(), Some(""));
+        assert_eq!(parse_args("tui --help").unwrap().help.as_deref(), Some("tui"));
+        assert_eq!(parse_args("pause -h").unwrap().help.as_deref(), Some("pause"));
+        assert_eq!(parse_args("help delete").unwrap().help.as_deref(), Some("delete"));
+        assert_eq!(parse_args("help").unwrap().help.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert_eq!(parse_args("pause").err().unwrap(), "pause needs at least one task ID");
+        assert_eq!(parse_args("add").err().unwrap(), "add needs at least one URL");
+        assert_eq!(parse_args("add -t 5 a").err().unwrap(), "--timeout only works with --wait");
+        assert_eq!(parse_args("limits --down fast").err().unwrap(),
+                   "Invalid down fast, expected KB/s");
+        assert_eq!(parse_args("add -w --preview a").err().unwrap(),
+                   "--preview does not add anything to wait for");
+        assert_eq!(parse_args("add -w -t 5m a").err().unwrap(),
+                   "Invalid timeout 5m, expected seconds");
+        assert_eq!(parse_args("list dbid_1").err().unwrap(), "list takes no arguments");
+        assert_eq!(parse_args("pause --all-tasks dbid_1").err().unwrap(),
+                   "--all-tasks does not work with pause");
+        assert_eq!(parse_args("add -s paused http://foo/").err().unwrap(),
+                   "--status does not work with add");
+        assert_eq!(parse_args("stats --day --week").err().unwrap(),
+                   "Pick one of --day, --week and --month");
+        assert!(parse_args("--bogus").is_err());
+    }
+
+    #[test]
+    fn url_list() {
+        let input = "magnet:?xt=1\n\n  # a comment\n  http://foo/#bar  \n";
+        assert_eq!(read_urls(input.as_bytes()).unwrap(), ["magnet:?xt=1", "http://foo/#bar"]);
+    }
+
+    #[test]
+    fn load_urls_from_file() {
+        let path = std::env::temp_dir().join(format!("synodl-urls-{}", std::process::id()));
+        std::fs::write(&path, "# wanted\nhttp://b/\n").unwrap();
+
+        let urls = load_urls(&ids(&["http://a/"]), path.to_str()).unwrap();
+        assert_eq!(urls, ["http://a/", "http://b/"]);
+        std::fs::write(&path, "# nothing\n").unwrap();
+        assert_eq!(load_urls(&[], path.to_str()).err().unwrap(), "No URLs to add");
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(load_urls(&[], Some("/nonexistent/urls")).err().unwrap()
+                .starts_with("Cannot read URLs from /nonexistent/urls: "));
+    }
+
+    #[test]
+    fn add_reports_failed_urls() {
+        let mut backend = get_test_backend();
+        let urls: Vec<String> = (0..45).map(|i| match i {
+            30 => String::new(),
+            _ => format!("http://foo/{}", i)
+        }).collect();
+
+        assert!(!add_tasks(&mut backend, &urls));
+        assert_eq!(backend.tasks.len(), 46);
+        assert!(add_tasks(&mut backend, &urls[..2]));
+    }
+
+    #[test]
+    fn limits_arguments() {
+        assert_eq!(command("limits"), Command::Limits { down: None, up: None });
+        assert_eq!(command("limits --down 500 --up unlimited"),
+                   Command::Limits { down: Some(500), up: Some(0) });
+        assert_eq!(parse_args("limits 500").err().unwrap(), "limits takes no arguments");
+    }
+
+    #[test]
+    fn change_limits() {
+        let mut backend = get_test_backend();
+        backend.limits.bt_upload = 50;
+
+        let limits = Command::Limits { down: Some(1000), up: None };
+        assert!(run(&mut backend, &limits, no_login).unwrap());
+        assert_eq!(backend.limits, Limits { bt_download: 1000, bt_upload: 50,
+                                            http_download: 1000, ftp_download: 1000 });
+        let limits = Command::Limits { down: None, up: Some(0) };
+        assert!(run(&mut backend, &limits, no_login).unwrap());
+        assert_eq!(backend.limits.bt_upload, 0);
+        assert_eq!(backend.limits.bt_download, 1000);
+    }
+
+    #[test]
+    fn add_skips_duplicates() {
+        let mut backend = get_test_backend();
+        let add = |urls: &[&str], force| Command::Add {
+            urls: ids(urls), from_file: None, wait: false, timeout: None, force, preview: false
+        };
+
+        assert!(run(&mut backend, &add(&["http://foo/a.iso"], false), no_login).unwrap());
+        assert!(run(&mut backend, &add(&["http://foo/a.iso/", "http://foo/b.iso",
+                                         "http://foo/b.iso#x"], false), no_login).unwrap());
+        let uris: Vec<&str> = backend.tasks.iter().map(|t| t.uri.as_str()).collect();
+        assert_eq!(uris, ["", "", "http://foo/a.iso", "http://foo/b.iso"]);
+
+        assert!(run(&mut backend, &add(&["http://foo/a.iso"], true), no_login).unwrap());
+        assert_eq!(backend.tasks.len(), 5);
+    }
+
+    #[test]
+    fn add_uploads_torrent_files() {
+        let torrent: &[u8] = b"d4:infod6:lengthi5e4:name5:a.iso12:piece lengthi16384e6:pieces0:ee";
+        let hash = Torrent::parse(torrent).unwrap().info_hash;
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str, data: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, data).unwrap();
+            String::from(path.to_str().unwrap())
+        };
+        let urls = vec![path("a.torrent", torrent), path("copy.torrent", torrent),
+                        format!("magnet:?xt=urn:btih:{}", hash), path("empty.torrent", b"")];
+        let add = |urls: &[String], force| Command::Add {
+            urls: urls.to_vec(), from_file: None, wait: false, timeout: None, force, preview: false
+        };
+        let mut backend = get_test_backend();
+
+        assert!(!run(&mut backend, &add(&urls, false), no_login).unwrap());
+        let uris: Vec<&str> = backend.tasks.iter().map(|t| t.uri.as_str()).collect();
+        assert_eq!(uris, ["", "", "a.torrent"]);
+
+        assert!(run(&mut backend, &add(&urls[1..2], true), no_login).unwrap());
+        assert_eq!(backend.tasks[3].uri, "copy.torrent");
+    }
+
+    fn get_test_backend() -> MemoryBackend {
+        MemoryBackend::new(vec![
+            Task { id: String::from("dbid_1"), status: String::from("downloading"),
+                   ..Task::default() },
+            Task { id: String::from("dbid_2"), status: String::from("paused"),
+                   ..Task::default() }
+        ])
+    }
+
+    #[test]
+    fn run_task_commands() {
+        let mut backend = get_test_backend();
+
+        let pause = Command::Pause { ids: ids(&["dbid_1"]) };
+        assert!(run(&mut backend, &pause, no_login).unwrap());
+        assert_eq!(backend.tasks[0].status, "paused");
+
+        let resume = Command::Resume { ids: ids(&["dbid_1", "dbid_9", "dbid_2"]),
+                                       all_tasks: false };
+        assert!(!run(&mut backend, &resume, no_login).unwrap());
+        assert_eq!(backend.tasks[0].status, "downloading");
+        assert_eq!(backend.tasks[1].status, "downloading");
+
+        let delete = Command::Delete { ids: ids(&["dbid_2"]) };
+        assert!(run(&mut backend, &delete, no_login).unwrap());
+        assert_eq!(backend.tasks.len(), 1);
+        assert!(backend.logged_out);
+    }
+}
@@ -0,0 +1,879 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{collections::BTreeMap, env, error, ffi::OsString, fmt, fs, io::{self, IsTerminal},
+          path::{Path, PathBuf}, time::Duration};
+
+use dirs::home_dir;
+use serde::{Deserialize, Serialize};
+use serde_ini::{parse::OkIter, Item, Parser};
+use subprocess::{Exec, ExitStatus, Redirection};
+use url::Url;
+
+use synodl::syno::ClientConfig;
+
+use crate::{backend::SEPARATOR, prompt::read_password};
+
+/* name of the profile made up of the keys outside of any section */
+pub const DEFAULT_PROFILE: &str = "default";
+
+/* everything a profile may contain */
+const KEYS: [&str; 14] = ["user", "url", "password", "password_command",
+                          "password_command_timeout", "password_file", "password_env",
+                          "cacert", "fingerprint", "on_added", "on_finished", "on_error",
+                          "on_removed", "auto_resume"];
+
+/* the section with the rules for `synodl cleanup`, it is not a profile */
+pub const CLEANUP_SECTION: &str = "cleanup";
+const CLEANUP_KEYS: [&str; 3] = ["remove_finished_after", "remove_seeding_after",
+                                 "remove_seeding_at_ratio"];
+
+/* the ways to get at a password, in the order they are tried */
+const PASSWORD_KEYS: [&str; 4] = ["password_command", "password_file", "password_env",
+                                  "password"];
+
+/* seconds password_command may take unless configured otherwise */
+const PASSWORD_COMMAND_TIMEOUT: u64 = 30;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read { path: PathBuf, error: io::Error },
+    Syntax { line: usize, reason: String },
+    MissingKey { profile: String, key: &'static str },
+    InvalidValue { profile: String, key: &'static str, reason: String },
+    InvalidRule { key: &'static str, reason: String },
+    NoPassword,
+    Prompt(io::Error),
+    PasswordSource { key: &'static str, reason: String },
+    UnknownProfile(String),
+    NoProfile
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, error } => {
+                write!(f, "Cannot read {}: {}", path.display(), error)
+            },
+            ConfigError::Syntax { line, reason } => write!(f, "Line {}: {}", line, reason),
+            ConfigError::MissingKey { profile, key } => {
+                write!(f, "Profile {}: `{}` is missing", profile, key)
+            },
+            ConfigError::InvalidValue { profile, key, reason } => {
+                write!(f, "Profile {}: invalid `{}`: {}", profile, key, reason)
+            },
+            ConfigError::InvalidRule { key, reason } => {
+                write!(f, "[{}]: invalid `{}`: {}", CLEANUP_SECTION, key, reason)
+            },
+            ConfigError::NoPassword => {
+                write!(f, "No password configured, set one of `{}`", PASSWORD_KEYS.join("`, `"))
+            },
+            ConfigError::Prompt(e) => write!(f, "Cannot read the password: {}", e),
+            ConfigError::PasswordSource { key, reason } => write!(f, "`{}`: {}", key, reason),
+            ConfigError::UnknownProfile(name) => write!(f, "No such profile: {}", name),
+            ConfigError::NoProfile => write!(f, "No profile configured")
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Config {
+    pub user: String,
+    pub url: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_command_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_env: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cacert: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+
+    /* commands to run when tasks change */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_added: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_finished: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_removed: Option<String>,
+
+    /* resume failed tasks while the task list is shown */
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto_resume: bool
+}
+
+pub struct Profile {
+    pub name: String,
+    pub config: Config
+}
+
+/* which tasks `synodl cleanup` removes, none unless configured */
+#[derive(Debug, Default, PartialEq)]
+pub struct CleanupRules {
+    /* days since the download finished */
+    pub finished_after: Option<u64>,
+    /* days of seeding */
+    pub seeding_after: Option<u64>,
+    pub seeding_at_ratio: Option<f64>
+}
+
+impl CleanupRules {
+    pub fn is_empty(&self) -> bool {
+        *self == CleanupRules::default()
+    }
+}
+
+pub struct ConfigFile {
+    pub profiles: Vec<Profile>,
+    pub default_profile: Option<String>,
+    pub cleanup: CleanupRules,
+
+    /* things that look wrong but do not keep synodl from working */
+    pub warnings: Vec<String>
+}
+
+/* Settings given in the environment or on the command line */
+#[derive(Default)]
+pub struct Overrides {
+    pub url: Option<String>,
+    pub user: Option<String>,
+    pub password: Option<String>
+}
+
+impl Config {
+    /* the password from whichever source is configured, asking for it if
+     * there is none */
+    pub fn password(&self) -> Result<String, ConfigError> {
+        self.find_password(io::stdin().is_terminal())
+    }
+
+    /* the same without asking, for when the terminal belongs to the task list */
+    pub fn stored_password(&self) -> Result<String, ConfigError> {
+        self.find_password(false)
+    }
+
+    fn find_password(&self, ask: bool) -> Result<String, ConfigError> {
+        if let Some(command) = &self.password_command {
+            let secs = self.password_command_timeout.unwrap_or(PASSWORD_COMMAND_TIMEOUT);
+            return run_password_command(command, Duration::from_secs(secs))
+                .map_err(|reason| ConfigError::PasswordSource { key: "password_command", reason });
+        }
+
+        if let Some(file) = &self.password_file {
+            let path = expand_home(file);
+            return match fs::read_to_string(&path) {
+                Ok(content) => first_line(&content),
+                Err(e) => Err(format!("cannot read {}: {}", path.display(), e))
+            }.map_err(|reason| ConfigError::PasswordSource { key: "password_file", reason });
+        }
+
+        if let Some(var) = &self.password_env {
+            return match env::var(var) {
+                Ok(password) if !password.is_empty() => Ok(password),
+                _ => Err(ConfigError::PasswordSource {
+                    key: "password_env",
+                    reason: format!("{} is not set", var)
+                })
+            };
+        }
+
+        match &self.password {
+            Some(password) => Ok(password.clone()),
+            /* nothing configured: ask, if there is someone to ask */
+            None if ask => {
+                let prompt = format!("Password for {} at {}: ", self.user, self.url);
+                read_password(&prompt).map_err(ConfigError::Prompt)
+            },
+            None => Err(ConfigError::NoPassword)
+        }
+    }
+
+    fn password_sources(&self) -> Vec<&'static str> {
+        let set = [self.password_command.is_some(), self.password_file.is_some(),
+                   self.password_env.is_some(), self.password.is_some()];
+        PASSWORD_KEYS.iter().zip(set).filter(|(_, s)| *s).map(|(k, _)| *k).collect()
+    }
+
+    /* the certificate settings for the API client */
+    pub fn apply_tls(&self, client: &mut ClientConfig) {
+        client.cacert = self.cacert.as_deref()
+            .filter(|f| *f != "ignore")
+            .map(PathBuf::from);
+        client.fingerprint = self.fingerprint.clone();
+    }
+}
+
+fn first_line(text: &str) -> Result<String, String> {
+    match text.lines().next() {
+        Some(line) if !line.is_empty() => Ok(String::from(line)),
+        _ => Err(String::from("it is empty"))
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path)
+    }
+}
+
+fn run_password_command(command: &str, timeout: Duration) -> Result<String, String> {
+    let mut p = Exec::shell(command)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .popen()
+        .map_err(|e| e.to_string())?;
+
+    let (out, err) = match p.communicate_start(None).limit_time(timeout).read() {
+        Ok(output) => output,
+        Err(e) => {
+            let _ = p.kill();
+            let _ = p.wait();
+            return Err(match e.error.kind() {
+                io::ErrorKind::TimedOut => {
+                    format!("no answer within {} seconds", timeout.as_secs())
+                },
+                _ => e.error.to_string()
+            });
+        }
+    };
+    let out = String::from_utf8_lossy(&out.unwrap_or_default()).into_owned();
+    let err = String::from_utf8_lossy(&err.unwrap_or_default()).into_owned();
+
+    match p.wait().map_err(|e| e.to_string())? {
+        ExitStatus::Exited(0) => {},
+        status => {
+            let status = match status {
+                ExitStatus::Exited(n) => format!("exited with status {}", n),
+                s => format!("{:?}", s)
+            };
+            /* whatever the command complained about is the best hint */
+            return Err(match err.lines().next() {
+                Some(line) if !line.is_empty() => format!("{} ({})", status, line),
+                _ => status
+            });
+        }
+    }
+
+    first_line(&out).map_err(|_| String::from("it printed nothing"))
+}
+
+impl Overrides {
+    pub fn from_env() -> Overrides {
+        Overrides {
+            url: env::var("SYNODL_URL").ok(),
+            user: env::var("SYNODL_USER").ok(),
+            password: env::var("SYNODL_PASSWORD").ok()
+        }
+    }
+
+    pub fn apply(&self, cfg: &mut Config) {
+        if let Some(url) = &self.url {
+            cfg.url = url.clone();
+        }
+        if let Some(user) = &self.user {
+            cfg.user = user.clone();
+        }
+        if let Some(password) = &self.password {
+            cfg.password = Some(password.clone());
+            cfg.password_command = None;
+            cfg.password_file = None;
+            cfg.password_env = None;
+        }
+    }
+
+    /* a configuration without any file, if enough was given */
+    pub fn to_config(&self) -> Option<Config> {
+        match (&self.url, &self.user) {
+            (Some(url), Some(user)) => Some(Config {
+                user: user.clone(),
+                url: url.clone(),
+                password: self.password.clone(),
+                ..Config::default()
+            }),
+            _ => None
+        }
+    }
+}
+
+impl ConfigFile {
+    pub fn single(config: Config) -> ConfigFile {
+        ConfigFile {
+            profiles: vec![Profile { name: String::from(DEFAULT_PROFILE), config }],
+            default_profile: None,
+            cleanup: CleanupRules::default(),
+            warnings: vec![]
+        }
+    }
+
+    /* Picks the profile to start with: the one asked for, the configured
+     * default or else the first one in the file */
+    pub fn select(&self, name: Option<&str>) -> Result<usize, ConfigError> {
+        let wanted = match name {
+            Some(n) => Some(n),
+            None => self.default_profile.as_deref()
+        };
+
+        match wanted {
+            Some(n) => match self.profiles.iter().position(|p| p.name == n) {
+                Some(i) => Ok(i),
+                None => Err(ConfigError::UnknownProfile(String::from(n)))
+            },
+            None => match self.profiles.is_empty() {
+                true => Err(ConfigError::NoProfile),
+                false => Ok(0)
+            }
+        }
+    }
+}
+
+pub fn validate_url(profile: &str, url: &str) -> Result<(), ConfigError> {
+    let invalid = |reason: String| ConfigError::InvalidValue {
+        profile: String::from(profile), key: "url", reason
+    };
+
+    match Url::parse(url) {
+        Ok(u) if u.scheme() == "http" || u.scheme() == "https" => Ok(()),
+        Ok(u) => Err(invalid(format!("unsupported scheme {}", u.scheme()))),
+        Err(e) => Err(invalid(e.to_string()))
+    }
+}
+
+fn validate_cacert(profile: &str, f: &str) -> Result<(), ConfigError> {
+    match f {
+        "ignore" => Ok(()),
+        _ if Path::new(f).exists() => Ok(()),
+        _ => Err(ConfigError::InvalidValue {
+            profile: String::from(profile),
+            key: "cacert",
+            reason: format!("{} not found", f)
+        })
+    }
+}
+
+fn make_config(profile: &str, mut keys: BTreeMap<String, String>)
+        -> Result<Config, ConfigError> {
+    let password_command_timeout = match keys.remove("password_command_timeout") {
+        Some(t) => Some(t.parse::<u64>().map_err(|e| ConfigError::InvalidValue {
+            profile: String::from(profile),
+            key: "password_command_timeout",
+            reason: e.to_string()
+        })?),
+        None => None
+    };
+
+    let auto_resume = match keys.remove("auto_resume").as_deref() {
+        None | Some("no") | Some("false") | Some("off") => false,
+        Some("yes") | Some("true") | Some("on") => true,
+        Some(other) => return Err(ConfigError::InvalidValue {
+            profile: String::from(profile),
+            key: "auto_resume",
+            reason: format!("{}, expected yes or no", other)
+        })
+    };
+
+    let mut required = |key: &'static str| keys.remove(key).ok_or_else(|| {
+        ConfigError::MissingKey { profile: String::from(profile), key }
+    });
+    let user = required("user")?;
+    let url = required("url")?;
+
+    validate_url(profile, &url)?;
+    if let Some(f) = keys.get("cacert") {
+        validate_cacert(profile, f)?;
+    }
+
+    Ok(Config {
+        user,
+        url,
+        password: keys.remove("password"),
+        password_command: keys.remove("password_command"),
+        password_command_timeout,
+        password_file: keys.remove("password_file"),
+        password_env: keys.remove("password_env"),
+        cacert: keys.remove("cacert"),
+        fingerprint: keys.remove("fingerprint"),
+        on_added: keys.remove("on_added"),
+        on_finished: keys.remove("on_finished"),
+        on_error: keys.remove("on_error"),
+        on_removed: keys.remove("on_removed"),
+        auto_resume
+    })
+}
+
+fn make_rules(mut keys: BTreeMap<String, String>) -> Result<CleanupRules, ConfigError> {
+    let mut days = |key: &'static str| match keys.remove(key) {
+        Some(d) => d.parse::<u64>().map(Some).map_err(|e| ConfigError::InvalidRule {
+            key, reason: format!("{}, expected days", e)
+        }),
+        None => Ok(None)
+    };
+    let finished_after = days("remove_finished_after")?;
+    let seeding_after = days("remove_seeding_after")?;
+
+    let key = "remove_seeding_at_ratio";
+    let seeding_at_ratio = match keys.remove(key) {
+        Some(r) => match r.parse::<f64>() {
+            Ok(r) if r > 0.0 => Some(r),
+            Ok(_) => return Err(ConfigError::InvalidRule {
+                key, reason: String::from("must be more than 0")
+            }),
+            Err(e) => return Err(ConfigError::InvalidRule { key, reason: e.to_string() })
+        },
+        None => None
+    };
+
+    Ok(CleanupRules { finished_after, seeding_after, seeding_at_ratio })
+}
+
+pub fn parse_config(content: &str) -> Result<ConfigFile, ConfigError> {
+    let mut default_profile = None;
+    let mut warnings = vec![];
+    let mut sections: Vec<(String, BTreeMap<String, String>)> = vec![
+        (String::from(DEFAULT_PROFILE), BTreeMap::new())
+    ];
+
+    /* the parser turns every line into exactly one item */
+    let items = Parser::new(OkIter(content.lines().map(str::trim)));
+    for (i, item) in items.enumerate() {
+        let line = i + 1;
+        let item = item.map_err(|e| {
+            ConfigError::Syntax { line, reason: e.to_string() }
+        })?;
+
+        match item {
+            /* the name goes in front of task ids with --all */
+            Item::Section { name } if name.contains(SEPARATOR) => {
+                let reason = format!("profile name `{}` contains `{}`", name, SEPARATOR);
+                return Err(ConfigError::Syntax { line, reason });
+            },
+            Item::Section { name } => sections.push((name, BTreeMap::new())),
+            Item::Value { key, value } => {
+                if key == "default_profile" && sections.len() == 1 {
+                    default_profile = Some(value);
+                    continue;
+                }
+                let known = match sections.last() {
+                    Some((name, _)) if name == CLEANUP_SECTION => &CLEANUP_KEYS[..],
+                    _ => &KEYS[..]
+                };
+                if !known.contains(&key.as_str()) {
+                    warnings.push(format!("Line {}: unknown key `{}`", line, key));
+                }
+                if let Some((_, keys)) = sections.last_mut() {
+                    keys.insert(key, value);
+                }
+            },
+            Item::Comment { .. } | Item::Empty => {}
+        }
+    }
+
+    let mut cleanup = BTreeMap::new();
+    let mut profiles = vec![];
+    for (name, keys) in sections {
+        if name == CLEANUP_SECTION {
+            cleanup.extend(keys);
+            continue;
+        }
+        /* a file with only sections has no default profile */
+        if name == DEFAULT_PROFILE && keys.is_empty() {
+            continue;
+        }
+        let config = make_config(&name, keys)?;
+        let sources = config.password_sources();
+        if sources.len() > 1 {
+            warnings.push(format!("Profile {}: several passwords configured, using `{}`",
+                                  name, sources[0]));
+        }
+        profiles.push(Profile { name, config });
+    }
+
+    if let Some(name) = &default_profile {
+        if !profiles.iter().any(|p| &p.name == name) {
+            warnings.push(format!("default_profile: there is no profile {}", name));
+        }
+    }
+
+    let cleanup = make_rules(cleanup)?;
+    Ok(ConfigFile { profiles, default_profile, cleanup, warnings })
+}
+
+/* where to look for the configuration, in order */
+fn config_paths(xdg_config_home: Option<OsString>, home: Option<PathBuf>) -> Vec<PathBuf> {
+    let mut paths = vec![];
+
+    /* relative paths in XDG_CONFIG_HOME are to be ignored */
+    let xdg = xdg_config_home.map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| home.as_ref().map(|h| h.join(".config")));
+    if let Some(dir) = xdg {
+        paths.push(dir.join("synodl").join("config"));
+    }
+    if let Some(dir) = home {
+        paths.push(dir.join(".synodl"));
+    }
+
+    paths
+}
+
+pub fn find_config() -> Option<PathBuf> {
+    config_paths(env::var_os("XDG_CONFIG_HOME"), home_dir())
+        .into_iter()
+        .find(|p| p.exists())
+}
+
+#[cfg(unix)]
+fn check_permissions(file: &Path, meta: &fs::Metadata) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match meta.permissions().mode() & 0o077 {
+        0 => None,
+        _ => Some(format!("{} can be read by other users, run chmod 600 on it",
+                          file.display()))
+    }
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_file: &Path, _meta: &fs::Metadata) -> Option<String> {
+    None
+}
+
+pub fn load_config(file: &Path) -> Result<ConfigFile, ConfigError> {
+    let read_error = |error| ConfigError::Read { path: PathBuf::from(file), error };
+    let file_content = fs::read_to_string(file).map_err(read_error)?;
+    let meta = fs::metadata(file).map_err(read_error)?;
+
+    let mut config = parse_config(&file_content)?;
+    if let Some(warning) = check_permissions(file, &meta) {
+        config.warnings.push(warning);
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_profile() {
+        let cfg = parse_config("user = admin\npassword = secret\nurl = https://nas:5001/\n")
+            .unwrap();
+        assert_eq!(cfg.profiles.len(), 1);
+        assert_eq!(cfg.profiles[0].name, "default");
+        assert_eq!(cfg.profiles[0].config.user, "admin");
+        assert_eq!(cfg.profiles[0].config.url, "https://nas:5001/");
+        assert_eq!(cfg.select(None).unwrap(), 0);
+    }
+
+    #[test]
+    fn named_profiles() {
+        let cfg = parse_config("
+            default_profile = office
+
+            [home]
+            user = me
+            password = one
+            url = https://home:5001/
+
+            ; the one at work
+            [office]
+            user = admin
+            password = two
+            url = https://office:5001/
+            auto_resume = yes
+        ").unwrap();
+
+        let names: Vec<&str> = cfg.profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["home", "office"]);
+        assert_eq!(cfg.select(None).unwrap(), 1);
+        assert_eq!(cfg.select(Some("home")).unwrap(), 0);
+        assert!(cfg.select(Some("garage")).is_err());
+        assert_eq!(cfg.profiles[0].config.password().unwrap(), "one");
+        assert!(!cfg.profiles[0].config.auto_resume);
+        assert!(cfg.profiles[1].config.auto_resume);
+    }
+
+    #[test]
+    fn profile_names_without_separator() {
+        let err = parse_config("
+            [home/nas]
+            user = me
+            password = one
+            url = https://home:5001/
+        ").err().unwrap();
+        assert_eq!(err.to_string(), "Line 2: profile name `home/nas` contains `/`");
+    }
+
+    #[test]
+    fn default_and_named_profiles() {
+        let cfg = parse_config("
+            user = admin
+            password = secret
+            url = https://nas:5001/
+
+            [backup]
+            user = backup
+            password = secret
+            url = https://backup:5001/
+        ").unwrap();
+
+        let names: Vec<&str> = cfg.profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["default", "backup"]);
+        assert_eq!(cfg.select(None).unwrap(), 0);
+    }
+
+    #[test]
+    fn cleanup_rules() {
+        let cfg = parse_config("
+            user = admin
+            password = secret
+            url = https://nas:5001/
+
+            [cleanup]
+            remove_finished_after = 14
+            remove_seeding_at_ratio = 1.5
+            remove_leeching = yes
+        ").unwrap();
+
+        assert_eq!(cfg.profiles.len(), 1);
+        assert_eq!(cfg.cleanup, CleanupRules {
+            finished_after: Some(14), seeding_after: None, seeding_at_ratio: Some(1.5)
+        });
+        assert_eq!(cfg.warnings, ["Line 9: unknown key `remove_leeching`"]);
+
+        let cfg = parse_config("user = me\npassword = x\nurl = https://nas/\n").unwrap();
+        assert_eq!(cfg.cleanup, CleanupRules::default());
+    }
+
+    #[test]
+    fn invalid_cleanup_rules() {
+        let res = parse_config("[cleanup]\nremove_seeding_after = 3d\n");
+        assert_eq!(res.err().unwrap().to_string(),
+                   "[cleanup]: invalid `remove_seeding_after`: \
+                    invalid digit found in string, expected days");
+
+        let res = parse_config("[cleanup]\nremove_seeding_at_ratio = 0\n");
+        assert_eq!(res.err().unwrap().to_string(),
+                   "[cleanup]: invalid `remove_seeding_at_ratio`: must be more than 0");
+    }
+
+    #[test]
+    fn incomplete_profile() {
+        let res = parse_config("[home]\nuser = me\n");
+        assert_eq!(res.err().unwrap().to_string(), "Profile home: `url` is missing");
+    }
+
+    #[test]
+    fn invalid_values() {
+        let res = parse_config("user = me\nurl = nas:5001\n");
+        assert_eq!(res.err().unwrap().to_string(),
+                   "Profile default: invalid `url`: unsupported scheme nas");
+
+        let res = parse_config("user = me\nurl = https://nas/\ncacert = /nonexistent.pem\n");
+        assert_eq!(res.err().unwrap().to_string(),
+                   "Profile default: invalid `cacert`: /nonexistent.pem not found");
+
+        let res = parse_config("user = me\nurl = https://nas/\nauto_resume = sometimes\n");
+        assert_eq!(res.err().unwrap().to_string(),
+                   "Profile default: invalid `auto_resume`: sometimes, expected yes or no");
+    }
+
+    #[test]
+    fn syntax_error_names_line() {
+        let res = parse_config("user = me\n\n[home\n");
+        assert_eq!(res.err().unwrap().to_string(),
+                   "Line 3: INI syntax error: section missing ']'");
+    }
+
+    #[test]
+    fn warnings() {
+        let cfg = parse_config("
+            default_profile = office
+            user = me
+            pasword = secret
+            url = https://nas/
+        ").unwrap();
+        assert_eq!(cfg.warnings, [
+            "Line 4: unknown key `pasword`",
+            "default_profile: there is no profile office"
+        ]);
+    }
+
+    #[test]
+    fn file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+        fs::write(&path, "user = me\npassword = x\nurl = https://nas/\n").unwrap();
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(load_config(&path).unwrap().warnings.is_empty());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(load_config(&path).unwrap().warnings.len(), 1);
+
+        let res = load_config(&dir.path().join("missing"));
+        assert!(matches!(res, Err(ConfigError::Read { .. })));
+    }
+
+    #[test]
+    fn failing_password_command() {
+        let cfg = parse_config("user = admin\nurl = https://nas/\npassword_command = exit 3\n")
+            .unwrap();
+        assert_eq!(cfg.profiles[0].config.password().err().unwrap().to_string(),
+                   "`password_command`: exited with status 3");
+
+        let cfg = parse_config("user = admin\nurl = https://nas/\npassword_command = true\n")
+            .unwrap();
+        assert_eq!(cfg.profiles[0].config.password().err().unwrap().to_string(),
+                   "`password_command`: it printed nothing");
+
+        let cfg = parse_config("user = admin\nurl = https://nas/\n\
+                                password_command = echo oops >&2; exit 2\n").unwrap();
+        assert_eq!(cfg.profiles[0].config.password().err().unwrap().to_string(),
+                   "`password_command`: exited with status 2 (oops)");
+    }
+
+    #[test]
+    fn slow_password_command() {
+        let cfg = parse_config("user = admin\nurl = https://nas/\n\
+                                password_command = sleep 5; echo late\n\
+                                password_command_timeout = 1\n").unwrap();
+        assert_eq!(cfg.profiles[0].config.password().err().unwrap().to_string(),
+                   "`password_command`: no answer within 1 seconds");
+
+        let res = parse_config("user = admin\nurl = https://nas/\npassword_command_timeout = x\n");
+        assert!(matches!(res, Err(ConfigError::InvalidValue { key: "password_command_timeout", .. })));
+    }
+
+    #[test]
+    fn password_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pw");
+        fs::write(&path, "secret\n").unwrap();
+
+        let cfg = Config { password_file: Some(path.to_string_lossy().into_owned()),
+                           ..Config::default() };
+        assert_eq!(cfg.password().unwrap(), "secret");
+
+        fs::write(&path, "").unwrap();
+        assert_eq!(cfg.password().err().unwrap().to_string(), "`password_file`: it is empty");
+
+        let cfg = Config { password_file: Some(String::from("/nonexistent")),
+                           ..Config::default() };
+        assert!(cfg.password().err().unwrap().to_string()
+                .starts_with("`password_file`: cannot read /nonexistent: "));
+    }
+
+    #[test]
+    fn password_env() {
+        env::set_var("SYNODL_TEST_PASSWORD", "secret");
+        let cfg = Config { password_env: Some(String::from("SYNODL_TEST_PASSWORD")),
+                           ..Config::default() };
+        assert_eq!(cfg.password().unwrap(), "secret");
+
+        let cfg = Config { password_env: Some(String::from("SYNODL_TEST_UNSET")),
+                           ..Config::default() };
+        assert_eq!(cfg.password().err().unwrap().to_string(),
+                   "`password_env`: SYNODL_TEST_UNSET is not set");
+    }
+
+    #[test]
+    fn stored_password_never_asks() {
+        let cfg = Config { password: Some(String::from("secret")), ..Config::default() };
+        assert_eq!(cfg.stored_password().unwrap(), "secret");
+
+        let err = Config::default().stored_password().err().unwrap();
+        assert!(matches!(err, ConfigError::NoPassword));
+    }
+
+    #[test]
+    fn several_password_sources() {
+        let cfg = parse_config("user = admin\nurl = https://nas/\n\
+                                password = plain\npassword_env = HOME\n").unwrap();
+        assert_eq!(cfg.warnings, ["Profile default: several passwords configured, \
+                                   using `password_env`"]);
+    }
+
+    #[test]
+    fn config_locations() {
+        let home = Some(PathBuf::from("/home/me"));
+        assert_eq!(config_paths(None, home.clone()), [
+            PathBuf::from("/home/me/.config/synodl/config"),
+            PathBuf::from("/home/me/.synodl")
+        ]);
+        assert_eq!(config_paths(Some(OsString::from("/etc/xdg")), home.clone()), [
+            PathBuf::from("/etc/xdg/synodl/config"),
+            PathBuf::from("/home/me/.synodl")
+        ]);
+        assert_eq!(config_paths(Some(OsString::from("xdg")), home)[0],
+                   PathBuf::from("/home/me/.config/synodl/config"));
+        assert!(config_paths(None, None).is_empty());
+    }
+
+    #[test]
+    fn overrides_replace_file_settings() {
+        let mut cfg = parse_config("user = admin\nurl = https://nas/\npassword_command = false\n")
+            .unwrap();
+        let overrides = Overrides {
+            url: Some(String::from("https://other/")),
+            user: None,
+            password: Some(String::from("secret"))
+        };
+        overrides.apply(&mut cfg.profiles[0].config);
+
+        let cfg = &cfg.profiles[0].config;
+        assert_eq!(cfg.url, "https://other/");
+        assert_eq!(cfg.user, "admin");
+        assert_eq!(cfg.password().unwrap(), "secret");
+    }
+
+    #[test]
+    fn overrides_without_file() {
+        let mut overrides = Overrides {
+            url: Some(String::from("https://nas/")),
+            ..Overrides::default()
+        };
+        assert!(overrides.to_config().is_none());
+
+        overrides.user = Some(String::from("admin"));
+        let cfg = overrides.to_config().unwrap();
+        assert_eq!(cfg.user, "admin");
+        assert_eq!(cfg.password, None);
+    }
+
+    #[test]
+    fn password_command() {
+        let cfg = parse_config("user = admin\nurl = https://nas/\npassword_command = echo foo\n")
+            .unwrap();
+        assert_eq!(cfg.profiles[0].config.password().unwrap(), "foo");
+    }
+}
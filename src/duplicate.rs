@@ -0,0 +1,138 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::error;
+
+use synodl::syno::Task;
+use url::Url;
+
+use crate::torrent::{magnet_hash, Torrent};
+
+/* a URL in a form that makes copies of it compare equal: no fragment, no
+   trailing slash and the query parameters in order */
+pub fn normalize(uri: &str) -> String {
+    let uri = uri.trim();
+    let mut url = match Url::parse(uri) {
+        Ok(url) if !url.cannot_be_a_base() => url,
+        _ => return String::from(uri)
+    };
+
+    url.set_fragment(None);
+    let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    pairs.sort();
+    match pairs.is_empty() {
+        true => url.set_query(None),
+        false => {
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+    }
+    let path = url.path().trim_end_matches('/').to_string();
+    if !path.is_empty() {
+        url.set_path(&path);
+    }
+    url.to_string()
+}
+
+/* the task a link would be a copy of: the same torrent for magnet links,
+   the same URL for everything else */
+pub fn find_link<'a>(uri: &str, tasks: &'a [Task]) -> Option<&'a Task> {
+    match magnet_hash(uri) {
+        Some(hash) => tasks.iter().find(|t| magnet_hash(&t.uri).as_deref() == Some(&hash)),
+        None => {
+            let uri = normalize(uri);
+            tasks.iter().find(|t| !t.uri.is_empty() && normalize(&t.uri) == uri)
+        }
+    }
+}
+
+/* the task a torrent file would be a copy of: one added through a magnet
+   link with the same info-hash, or one with the same name and size */
+pub fn find_torrent<'a>(torrent: &Torrent, tasks: &'a [Task]) -> Option<&'a Task> {
+    tasks.iter().find(|t| {
+        magnet_hash(&t.uri).as_deref() == Some(&torrent.info_hash)
+            || (!torrent.name.is_empty() && t.title == torrent.name && t.size == torrent.length)
+    })
+}
+
+/* the same for a file to upload, only torrent files can be checked */
+pub fn find_file<'a>(data: &[u8], tasks: &'a [Task]) -> Option<&'a Task> {
+    Torrent::parse(data).ok().and_then(|t| find_torrent(&t, tasks))
+}
+
+pub fn already_added(task: &Task) -> Box<dyn error::Error> {
+    format!("Already added as {} ({})", task.title, task.id).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::make_task;
+
+    const HASH: &str = "c1599dd742faa9d633b4fb96d49c5abb02798eef";
+
+    fn linked(id: &str, title: &str, uri: &str) -> Task {
+        Task { uri: String::from(uri), size: 5, ..make_task(id, title, "downloading") }
+    }
+
+    #[test]
+    fn normalized_urls() {
+        assert_eq!(normalize(" HTTPS://Example.com:443/a/b/?y=2&x=1#top "),
+                   "https://example.com/a/b?x=1&y=2");
+        assert_eq!(normalize("http://example.com"), "http://example.com/");
+        assert_eq!(normalize("http://example.com/"), "http://example.com/");
+        assert_eq!(normalize("not a url"), "not a url");
+    }
+
+    #[test]
+    fn same_url() {
+        let tasks = [linked("dbid_1", "a.iso", "https://example.com/a.iso?mirror=1&v=2"),
+                     linked("dbid_2", "b.iso", "")];
+        assert_eq!(find_link("https://EXAMPLE.com/a.iso?v=2&mirror=1#x", &tasks).unwrap().id,
+                   "dbid_1");
+        assert!(find_link("https://example.com/a.iso", &tasks).is_none());
+        assert!(find_link("", &tasks).is_none());
+    }
+
+    #[test]
+    fn same_magnet() {
+        let uri = format!("magnet:?xt=urn:btih:{}&dn=a.iso", HASH.to_uppercase());
+        let tasks = [linked("dbid_1", "a.iso", &uri)];
+        assert!(find_link(&format!("magnet:?xt=urn:btih:{}&tr=http://t/", HASH), &tasks)
+                    .is_some());
+        assert!(find_link("magnet:?xt=urn:btih:YFMZ3V2C7KU5MM5U7OLNJHC2XMBHTDXP", &tasks)
+                    .is_some());
+        assert!(find_link("magnet:?xt=urn:btih:0000000000000000000000000000000000000000",
+                          &tasks).is_none());
+    }
+
+    #[test]
+    fn same_torrent() {
+        let data: &[u8] = b"d4:infod6:lengthi5e4:name5:a.iso12:piece lengthi16384e\
+                            6:pieces0:ee";
+        let magnet = linked("dbid_1", "something", &format!("magnet:?xt=urn:btih:{}", HASH));
+        assert_eq!(find_file(data, &[magnet]).unwrap().id, "dbid_1");
+
+        let uploaded = linked("dbid_2", "a.iso", "a.torrent");
+        assert_eq!(find_file(data, &[uploaded]).unwrap().id, "dbid_2");
+
+        let other = linked("dbid_3", "b.iso", "b.torrent");
+        assert!(find_file(data, &[other]).is_none());
+        assert!(find_file(b"<nzb/>", &[linked("dbid_4", "", "")]).is_none());
+    }
+}
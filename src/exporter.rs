@@ -0,0 +1,302 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{
+    error, fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant}
+};
+
+use synodl::syno::Task;
+
+use crate::{backend::{is_session_error, DownloadBackend}, stats::Recorder};
+
+/* where the metrics are served unless --listen says otherwise */
+pub const LISTEN: &str = "127.0.0.1:9781";
+/* seconds a task list is reused before asking the NAS again */
+pub const CACHE: u64 = 15;
+
+/* the states DownloadStation knows about, each gets a series in synodl_task_status */
+const STATES: [&str; 10] = ["waiting", "downloading", "paused", "finishing", "finished",
+                            "hash_checking", "seeding", "filehosting_waiting", "extracting",
+                            "error"];
+
+/* a label value in the text exposition format */
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+}
+
+fn task_gauge(out: &mut String, tasks: &[Task], name: &str, help: &str,
+              value: fn(&Task) -> u64) {
+    header(out, name, help);
+    for t in tasks {
+        let _ = writeln!(out, "{}{{id=\"{}\",title=\"{}\"}} {}",
+                         name, escape(&t.id), escape(&t.title), value(t));
+    }
+}
+
+fn total(out: &mut String, tasks: &[Task], name: &str, help: &str, value: fn(&Task) -> u64) {
+    header(out, name, help);
+    let _ = writeln!(out, "{} {}", name, tasks.iter().map(value).sum::<u64>());
+}
+
+/* the page Prometheus gets, None when the tasks could not be listed */
+pub fn render(tasks: Option<&[Task]>) -> String {
+    let mut out = String::new();
+    header(&mut out, "synodl_up", "Whether the last look at the task list worked");
+    let tasks = match tasks {
+        Some(tasks) => {
+            out.push_str("synodl_up 1\n");
+            tasks
+        },
+        None => {
+            out.push_str("synodl_up 0\n");
+            return out;
+        }
+    };
+
+    task_gauge(&mut out, tasks, "synodl_task_size_bytes", "Total size of the task",
+               |t| t.size);
+    task_gauge(&mut out, tasks, "synodl_task_downloaded_bytes", "Bytes downloaded so far",
+               |t| t.size_downloaded);
+    task_gauge(&mut out, tasks, "synodl_task_uploaded_bytes", "Bytes uploaded so far",
+               |t| t.size_uploaded);
+    task_gauge(&mut out, tasks, "synodl_task_download_bytes_per_second",
+               "Current download speed in bytes per second", |t| t.speed_download);
+    task_gauge(&mut out, tasks, "synodl_task_upload_bytes_per_second",
+               "Current upload speed in bytes per second", |t| t.speed_upload);
+
+    header(&mut out, "synodl_task_status", "1 for the state the task is in, 0 for the others");
+    for t in tasks {
+        let mut states: Vec<&str> = STATES.to_vec();
+        if !states.contains(&t.status.as_str()) {
+            states.push(&t.status);
+        }
+        for s in states {
+            let _ = writeln!(out, "synodl_task_status{{id=\"{}\",title=\"{}\",status=\"{}\"}} {}",
+                             escape(&t.id), escape(&t.title), escape(s),
+                             (s == t.status) as u8);
+        }
+    }
+
+    header(&mut out, "synodl_tasks", "Number of tasks in each state");
+    for s in STATES {
+        let n = tasks.iter().filter(|t| t.status == s).count();
+        let _ = writeln!(out, "synodl_tasks{{status=\"{}\"}} {}", s, n);
+    }
+
+    total(&mut out, tasks, "synodl_size_bytes", "Total size of all tasks", |t| t.size);
+    total(&mut out, tasks, "synodl_downloaded_bytes", "Bytes downloaded by all tasks",
+          |t| t.size_downloaded);
+    total(&mut out, tasks, "synodl_uploaded_bytes", "Bytes uploaded by all tasks",
+          |t| t.size_uploaded);
+    total(&mut out, tasks, "synodl_download_bytes_per_second",
+          "Current download speed of all tasks in bytes per second", |t| t.speed_download);
+    total(&mut out, tasks, "synodl_upload_bytes_per_second",
+          "Current upload speed of all tasks in bytes per second", |t| t.speed_upload);
+    out
+}
+
+/* serves /metrics, one scrape at a time, all through the same session */
+pub struct Exporter<D, F> {
+    client: D,
+    connect: F,
+    cache: Duration,
+    /* the last page and when the tasks in it were listed */
+    page: Option<(Instant, String)>,
+    pub recorder: Recorder
+}
+
+impl<D, F> Exporter<D, F>
+        where D: DownloadBackend,
+              F: FnMut() -> Result<D, Box<dyn error::Error>> {
+    pub fn new(client: D, connect: F, cache: Duration) -> Exporter<D, F> {
+        Exporter { client, connect, cache, page: None, recorder: Recorder::default() }
+    }
+
+    fn list(&mut self) -> Result<Vec<Task>, Box<dyn error::Error>> {
+        match self.client.list() {
+            Err(e) if is_session_error(e.as_ref()) => {
+                self.client = (self.connect)()?;
+                self.client.list()
+            },
+            res => res
+        }
+    }
+
+    /* failures are cached as well, a NAS in trouble is not asked any more often */
+    fn metrics(&mut self) -> &str {
+        let fresh = matches!(&self.page, Some((at, _)) if at.elapsed() < self.cache);
+        if !fresh {
+            let page = match self.list() {
+                Ok(tasks) => {
+                    for (nas, e) in self.client.failures() {
+                        eprintln!("Cannot list tasks of {}: {}", nas, e);
+                    }
+                    if let Err(e) = self.recorder.record(&tasks) {
+                        eprintln!("{}", e);
+                    }
+                    render(Some(&tasks))
+                },
+                Err(e) => {
+                    eprintln!("Cannot list tasks: {}", e);
+                    render(None)
+                }
+            };
+            self.page = Some((Instant::now(), page));
+        }
+        self.page.as_ref().map(|(_, page)| page.as_str()).unwrap_or_default()
+    }
+
+    /* answers one request */
+    fn respond<R: BufRead, W: Write>(&mut self, reader: &mut R, writer: &mut W)
+            -> io::Result<()> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (method, path) = (parts.next(), parts.next());
+
+        /* the headers do not matter */
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+
+        let (status, body) = match (method, path) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.metrics().to_owned()),
+            (Some("GET"), _) => ("404 Not Found", String::from("Try /metrics\n")),
+            _ => ("405 Method Not Allowed", String::new())
+        };
+        write!(writer, "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                        Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+               status, body.len(), body)?;
+        writer.flush()
+    }
+
+    fn serve(&mut self, stream: TcpStream) -> io::Result<()> {
+        /* a client that never finishes its request must not block the others */
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        self.respond(&mut reader, &mut writer)
+    }
+
+    pub fn run(&mut self, listen: &str) -> Result<(), Box<dyn error::Error>> {
+        let listener = TcpListener::bind(listen)
+            .map_err(|e| format!("Cannot listen on {}: {}", listen, e))?;
+        println!("Serving metrics on http://{}/metrics, press Ctrl-C to stop",
+                 listener.local_addr()?);
+
+        for stream in listener.incoming() {
+            if let Err(e) = stream.and_then(|s| self.serve(s)) {
+                eprintln!("Scrape failed: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn logout(&mut self) -> Result<(), Box<dyn error::Error>> {
+        self.client.logout()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::{make_task, no_login, MemoryBackend};
+
+    fn scrape<D, F>(exporter: &mut Exporter<D, F>, request: &str) -> String
+            where D: DownloadBackend,
+                  F: FnMut() -> Result<D, Box<dyn error::Error>> {
+        let mut out = vec![];
+        exporter.respond(&mut request.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn render_tasks() {
+        let tasks = [make_task("dbid_1", "debian.iso", "downloading"),
+                     make_task("dbid_2", "say \"hi\"", "seeding")];
+        let page = render(Some(&tasks));
+        let lines: Vec<&str> = page.lines().collect();
+
+        assert!(lines.contains(&"synodl_up 1"));
+        assert!(lines.contains(&"# TYPE synodl_task_size_bytes gauge"));
+        assert!(lines.contains(&"synodl_task_size_bytes{id=\"dbid_1\",title=\"debian.iso\"} 1024000"));
+        assert!(lines.contains(&"synodl_task_downloaded_bytes{id=\"dbid_2\",title=\"say \\\"hi\\\"\"} 512000"));
+        assert!(lines.contains(
+            &"synodl_task_status{id=\"dbid_1\",title=\"debian.iso\",status=\"downloading\"} 1"));
+        assert!(lines.contains(
+            &"synodl_task_status{id=\"dbid_1\",title=\"debian.iso\",status=\"seeding\"} 0"));
+        assert!(lines.contains(&"synodl_tasks{status=\"seeding\"} 1"));
+        assert!(lines.contains(&"synodl_tasks{status=\"paused\"} 0"));
+        assert!(lines.contains(&"synodl_size_bytes 2048000"));
+        assert!(lines.contains(&"synodl_download_bytes_per_second 200"));
+    }
+
+    #[test]
+    fn render_unknown_status() {
+        let page = render(Some(&[make_task("dbid_1", "a", "moving")]));
+        assert!(page.contains("synodl_task_status{id=\"dbid_1\",title=\"a\",status=\"moving\"} 1\n"));
+        assert!(page.contains("synodl_task_status{id=\"dbid_1\",title=\"a\",status=\"error\"} 0\n"));
+    }
+
+    #[test]
+    fn render_failure() {
+        assert_eq!(render(None), "# HELP synodl_up Whether the last look at the task list worked\n\
+                                  # TYPE synodl_up gauge\nsynodl_up 0\n");
+    }
+
+    #[test]
+    fn serve_metrics() {
+        let backend = MemoryBackend::new(vec![make_task("dbid_1", "a", "waiting")]);
+        let mut exporter = Exporter::new(backend, no_login, Duration::from_secs(60));
+
+        let response = scrape(&mut exporter, "GET /metrics HTTP/1.1\r\nHost: nas\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP synodl_up"));
+        assert!(response.contains("synodl_tasks{status=\"waiting\"} 1\n"));
+
+        let response = scrape(&mut exporter, "GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = scrape(&mut exporter, "POST /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+
+    #[test]
+    fn cache_tasks() {
+        let backend = MemoryBackend::new(vec![make_task("dbid_1", "a", "waiting")]);
+        let mut exporter = Exporter::new(backend, no_login, Duration::from_secs(60));
+        assert!(exporter.metrics().contains("synodl_tasks{status=\"waiting\"} 1\n"));
+
+        exporter.client.add("magnet:?xt=1").unwrap();
+        assert!(exporter.metrics().contains("synodl_tasks{status=\"waiting\"} 1\n"));
+
+        exporter.cache = Duration::ZERO;
+        assert!(exporter.metrics().contains("synodl_tasks{status=\"waiting\"} 2\n"));
+    }
+}
@@ -0,0 +1,773 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+//! A fake DownloadStation for testing.
+//!
+//! [`FakeServer`] speaks just enough HTTP and DownloadStation API to run the
+//! real client against it: it keeps track of sessions, models the life cycle
+//! of download tasks and answers with the same error codes as DSM. It serves
+//! both the legacy task API and, unless disabled, DownloadStation2.
+//!
+//! Only available with the `fake` feature.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use serde_json::{json, Value};
+use url::Url;
+
+/// A download task as stored by the fake server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FakeTask {
+    pub id: String,
+    pub title: String,
+    pub uri: String,
+    pub status: String,
+    pub size: u64,
+    pub size_downloaded: u64,
+    pub size_uploaded: u64,
+    pub speed_download: u64,
+    pub speed_upload: u64,
+    /// Seconds since the epoch, like the times below.
+    pub create_time: u64,
+    pub completed_time: u64,
+    pub seed_elapsed: u64,
+    /// Why a task in `error` failed, e.g. `disk_full`.
+    pub error_detail: String
+}
+
+const DAY: u64 = 86400;
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl FakeTask {
+    /// Creates a task that has not transferred anything yet.
+    pub fn new(id: &str, title: &str, status: &str, size: u64) -> FakeTask {
+        FakeTask {
+            id: String::from(id),
+            title: String::from(title),
+            uri: format!("https://example.com/{}", title),
+            status: String::from(status),
+            size,
+            size_downloaded: 0,
+            size_uploaded: 0,
+            speed_download: 0,
+            speed_upload: 0,
+            create_time: now(),
+            completed_time: 0,
+            seed_elapsed: 0,
+            error_detail: String::new()
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.size_downloaded >= self.size
+    }
+}
+
+/// How the fake server should behave.
+#[derive(Clone, Debug)]
+pub struct FakeOptions {
+    pub user: String,
+    pub password: String,
+    /// Whether to advertise and serve the DownloadStation2 API.
+    pub ds2: bool,
+    /// Maximum number of tasks returned by a single list request.
+    pub page_limit: usize,
+    pub tasks: Vec<FakeTask>
+}
+
+impl Default for FakeOptions {
+    fn default() -> FakeOptions {
+        FakeOptions {
+            user: String::from("admin"),
+            password: String::from("secret"),
+            ds2: true,
+            page_limit: 100,
+            tasks: sample_tasks()
+        }
+    }
+}
+
+/// A handful of tasks in different states.
+pub fn sample_tasks() -> Vec<FakeTask> {
+    let mut debian = FakeTask::new("dbid_1", "debian-12.5.0-amd64-netinst.iso",
+                                   "downloading", 658505728);
+    debian.size_downloaded = 158998272;
+    debian.speed_download = 12102100;
+
+    let mut slackware = FakeTask::new("dbid_2", "Slackware 15.0 x86_64 DVD ISO",
+                                      "paused", 2770253906);
+    slackware.size_downloaded = 770253906;
+
+    let mut archbang = FakeTask::new("dbid_3", "archbang-011215-i686.iso",
+                                     "seeding", 456130560);
+    archbang.size_downloaded = 456130560;
+    archbang.size_uploaded = 406130560;
+    archbang.speed_upload = 83923;
+    archbang.create_time -= 4 * DAY;
+    archbang.completed_time = archbang.create_time + DAY;
+    archbang.seed_elapsed = 3 * DAY;
+
+    let mut knoppix = FakeTask::new("dbid_4", "KNOPPIX 9.1 DVD", "finished",
+                                    4112431185);
+    knoppix.size_downloaded = 4112431185;
+    knoppix.create_time -= 12 * DAY;
+    knoppix.completed_time = knoppix.create_time + 2 * DAY;
+
+    let mut broken = FakeTask::new("dbid_5", "ubuntu-15.04-desktop-amd64.iso",
+                                   "error", 1148903751);
+    broken.size_downloaded = 1148903751;
+    broken.error_detail = String::from("timeout");
+
+    vec![debian, slackware, archbang, knoppix, broken]
+}
+
+struct State {
+    options: FakeOptions,
+    /* DownloadStation's settings, as getconfig returns them */
+    config: Value,
+    sessions: HashSet<String>,
+    next_sid: u32,
+    next_id: u32
+}
+
+type Params = HashMap<String, String>;
+
+/* An API error, carrying the DSM error code */
+struct ApiError(u32);
+
+type ApiResult = Result<Value, ApiError>;
+
+/// A fake DownloadStation listening on a local port.
+pub struct FakeServer {
+    url: String,
+    state: Arc<Mutex<State>>
+}
+
+impl FakeServer {
+    /// Starts a server with the default options on a random local port.
+    pub fn start() -> io::Result<FakeServer> {
+        FakeServer::start_with(FakeOptions::default(), "127.0.0.1:0")
+    }
+
+    /// Starts a server with the given options on the given address.
+    pub fn start_with(options: FakeOptions, addr: &str) -> io::Result<FakeServer> {
+        let listener = TcpListener::bind(addr)?;
+        let url = format!("http://{}/", listener.local_addr()?);
+        let next_id = options.tasks.len() as u32 + 1;
+        let state = Arc::new(Mutex::new(State {
+            options,
+            config: json!({
+                "bt_max_download": 0, "bt_max_upload": 0, "emule_max_download": 0,
+                "emule_max_upload": 0, "ftp_max_download": 0, "http_max_download": 0,
+                "nzb_max_download": 0, "emule_enabled": false, "unzip_service_enabled": false,
+                "default_destination": "downloads"
+            }),
+            sessions: HashSet::new(),
+            next_sid: 1,
+            next_id
+        }));
+
+        let shared = Arc::clone(&state);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = Arc::clone(&shared);
+                thread::spawn(move || serve(stream, state));
+            }
+        });
+
+        Ok(FakeServer { url, state })
+    }
+
+    /// Base URL to configure the client with.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The tasks currently known to the server, oldest first.
+    pub fn tasks(&self) -> Vec<FakeTask> {
+        self.state().options.tasks.clone()
+    }
+
+    /// Looks up a single task.
+    pub fn task(&self, id: &str) -> Option<FakeTask> {
+        self.state().options.tasks.iter().find(|t| t.id == id).cloned()
+    }
+
+    /// Replaces a task with the same ID, or adds it.
+    pub fn put_task(&self, task: FakeTask) {
+        let mut state = self.state();
+        match state.options.tasks.iter_mut().find(|t| t.id == task.id) {
+            Some(t) => *t = task,
+            None => state.options.tasks.push(task)
+        }
+    }
+
+    /// The settings as changed through `setserverconfig`.
+    pub fn config(&self) -> Value {
+        self.state().config.clone()
+    }
+
+    /// Number of sessions that are currently logged in.
+    pub fn sessions(&self) -> usize {
+        self.state().sessions.len()
+    }
+
+    /// Forgets all sessions, as DSM does when they time out.
+    pub fn expire_sessions(&self) {
+        self.state().sessions.clear();
+    }
+
+    /// Lets one second pass: active tasks transfer data at their current
+    /// speed and move on to the next state once they are complete.
+    pub fn tick(&self) {
+        for t in self.state().options.tasks.iter_mut() {
+            match t.status.as_str() {
+                "waiting" => {
+                    t.status = String::from("downloading");
+                    t.speed_download = 1000000;
+                }
+                "downloading" => {
+                    t.size_downloaded = (t.size_downloaded + t.speed_download).min(t.size);
+                    if t.is_complete() {
+                        t.status = String::from("finished");
+                        t.speed_download = 0;
+                        t.completed_time = now();
+                    }
+                }
+                "seeding" => {
+                    t.size_uploaded += t.speed_upload;
+                    t.seed_elapsed += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn serve(stream: TcpStream, state: Arc<Mutex<State>>) {
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(s) => s,
+        Err(_) => return
+    });
+    let mut writer = stream;
+
+    /* keep serving requests on this connection until the client closes it */
+    while let Ok(Some((path, content_type, body))) = read_request(&mut reader) {
+        let (status, body) = match Url::parse("http://localhost")
+            .and_then(|base| base.join(&path))
+        {
+            Ok(url) => {
+                let mut params: Params = url.query_pairs().into_owned().collect();
+                match content_type.strip_prefix("multipart/form-data; boundary=") {
+                    Some(boundary) => params.extend(parse_multipart(&body, boundary)),
+                    None => params.extend(url::form_urlencoded::parse(&body).into_owned())
+                }
+
+                let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+                match handle(&mut state, url.path(), &params) {
+                    Some(Ok(data)) => ("200 OK", json!({"success": true, "data": data})),
+                    Some(Err(ApiError(code))) => {
+                        ("200 OK", json!({"success": false, "error": {"code": code}}))
+                    }
+                    None => ("404 Not Found", json!({}))
+                }
+            }
+            Err(_) => ("400 Bad Request", json!({}))
+        };
+
+        let body = body.to_string();
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status, body.len(), body
+        );
+        if writer.write_all(response.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/* Reads one HTTP request, returns the request target, content type and body */
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<(String, String, Vec<u8>)>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let path = match line.split_whitespace().nth(1) {
+        Some(path) => String::from(path),
+        None => return Ok(None)
+    };
+
+    let mut length = 0;
+    let mut content_type = String::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("content-type") {
+                content_type = String::from(value.trim());
+            }
+        }
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some((path, content_type, body)))
+}
+
+/* Form fields of an upload; for a file we keep its name, or nothing if it is empty */
+fn parse_multipart(body: &[u8], boundary: &str) -> Params {
+    let body = String::from_utf8_lossy(body);
+    let mut params = Params::new();
+
+    for part in body.split(&format!("--{}", boundary)) {
+        let (headers, value) = match part.split_once("\r\n\r\n") {
+            Some(split) => split,
+            None => continue
+        };
+        let value = value.strip_suffix("\r\n").unwrap_or(value);
+
+        let disposition = headers.lines()
+            .find(|h| h.to_ascii_lowercase().starts_with("content-disposition:"))
+            .unwrap_or_default();
+        let disposition_param = |key: &str| {
+            disposition.split(';')
+                .filter_map(|p| p.trim().strip_prefix(key))
+                .find_map(|v| v.strip_prefix("=\""))
+                .and_then(|v| v.split('"').next())
+                .map(String::from)
+        };
+        let name = match disposition_param("name") {
+            Some(name) => name,
+            None => continue
+        };
+        let value = match disposition_param("filename") {
+            Some(_) if value.is_empty() => String::new(),
+            Some(filename) => filename,
+            None => String::from(value)
+        };
+        params.insert(name, value);
+    }
+    params
+}
+
+fn handle(state: &mut State, path: &str, params: &Params) -> Option<ApiResult> {
+    let api = params.get("api").map(String::as_str).unwrap_or_default();
+    let res = match (path, api) {
+        ("/webapi/query.cgi", "SYNO.API.Info") => query(state, params),
+        ("/webapi/auth.cgi", "SYNO.API.Auth") => auth(state, params),
+        ("/webapi/DownloadStation/task.cgi", "SYNO.DownloadStation.Task") => {
+            check_session(state, params).and_then(|_| legacy_task(state, params))
+        }
+        ("/webapi/DownloadStation/info.cgi", "SYNO.DownloadStation.Info") => {
+            check_session(state, params).and_then(|_| info(state, params))
+        }
+        ("/webapi/entry.cgi", "SYNO.DownloadStation2.Task") if state.options.ds2 => {
+            check_session(state, params).and_then(|_| ds2_task(state, params))
+        }
+        ("/webapi/entry.cgi", "SYNO.DownloadStation2.Settings.Location") if state.options.ds2 => {
+            check_session(state, params).map(|_| json!({"default_destination": "downloads"}))
+        }
+        ("/webapi/query.cgi", _)
+        | ("/webapi/auth.cgi", _)
+        | ("/webapi/DownloadStation/task.cgi", _)
+        | ("/webapi/DownloadStation/info.cgi", _)
+        | ("/webapi/entry.cgi", _) => Err(ApiError(102)),
+        _ => return None
+    };
+    Some(res)
+}
+
+fn param<'a>(params: &'a Params, name: &str) -> Result<&'a str, ApiError> {
+    match params.get(name) {
+        Some(value) => Ok(value),
+        None => Err(ApiError(101))
+    }
+}
+
+fn method(params: &Params) -> Result<&str, ApiError> {
+    param(params, "method")
+}
+
+fn version(params: &Params) -> Result<u32, ApiError> {
+    param(params, "version")?.parse().map_err(|_| ApiError(101))
+}
+
+fn query(state: &State, params: &Params) -> ApiResult {
+    if method(params)? != "query" {
+        return Err(ApiError(103));
+    }
+
+    let mut apis = json!({
+        "SYNO.API.Auth": {"maxVersion": 2, "minVersion": 1, "path": "auth.cgi"},
+        "SYNO.DownloadStation.Task": {"maxVersion": 3, "minVersion": 1,
+                                      "path": "DownloadStation/task.cgi"},
+        "SYNO.DownloadStation.Info": {"maxVersion": 2, "minVersion": 1,
+                                      "path": "DownloadStation/info.cgi"}
+    });
+    if state.options.ds2 {
+        apis["SYNO.DownloadStation2.Task"] =
+            json!({"maxVersion": 2, "minVersion": 1, "path": "entry.cgi"});
+    }
+
+    let wanted = params.get("query").map(String::as_str).unwrap_or("ALL");
+    match wanted {
+        "ALL" => Ok(apis),
+        _ => {
+            let mut res = json!({});
+            for name in wanted.split(',') {
+                if let Some(api) = apis.get(name) {
+                    res[name] = api.clone();
+                }
+            }
+            Ok(res)
+        }
+    }
+}
+
+fn auth(state: &mut State, params: &Params) -> ApiResult {
+    match method(params)? {
+        "login" => {
+            let account = param(params, "account")?;
+            let passwd = param(params, "passwd")?;
+            if account != state.options.user || passwd != state.options.password {
+                return Err(ApiError(400));
+            }
+            let sid = format!("fake-sid-{}", state.next_sid);
+            state.next_sid += 1;
+            state.sessions.insert(sid.clone());
+            Ok(json!({"sid": sid}))
+        }
+        "logout" => {
+            let sid = param(params, "_sid")?;
+            state.sessions.remove(sid);
+            Ok(json!({}))
+        }
+        _ => Err(ApiError(103))
+    }
+}
+
+fn check_session(state: &State, params: &Params) -> Result<(), ApiError> {
+    match params.get("_sid") {
+        None => Err(ApiError(105)),
+        Some(sid) if state.sessions.contains(sid) => Ok(()),
+        Some(_) => Err(ApiError(106))
+    }
+}
+
+fn ds2_status(status: &str) -> u32 {
+    match status {
+        "waiting" => 1,
+        "downloading" => 2,
+        "paused" => 3,
+        "finishing" => 4,
+        "finished" => 5,
+        "hash_checking" => 6,
+        "seeding" => 8,
+        "filehosting_waiting" => 9,
+        "extracting" => 10,
+        _ => 101
+    }
+}
+
+/* the status code DownloadStation2 has for a failed task */
+fn ds2_error(detail: &str) -> u32 {
+    match detail {
+        "broken_link" => 102,
+        "disk_full" => 105,
+        "timeout" => 107,
+        _ => 101
+    }
+}
+
+fn task_json(t: &FakeTask, ds2: bool) -> Value {
+    let status = match ds2 {
+        true if t.status == "error" => json!(ds2_error(&t.error_detail)),
+        true => json!(ds2_status(&t.status)),
+        false => json!(t.status)
+    };
+    let mut task = json!({
+        "id": t.id,
+        "title": t.title,
+        "status": status,
+        "size": t.size,
+        "type": "bt",
+        "username": "admin",
+        "additional": {
+            "detail": {
+                "uri": t.uri,
+                "destination": "downloads",
+                "create_time": t.create_time,
+                "completed_time": t.completed_time,
+                "seedelapsed": t.seed_elapsed
+            },
+            "transfer": {
+                "size_downloaded": t.size_downloaded,
+                "size_uploaded": t.size_uploaded,
+                "speed_download": t.speed_download,
+                "speed_upload": t.speed_upload
+            }
+        }
+    });
+    /* the legacy API names the reason next to the status */
+    if !ds2 && t.status == "error" {
+        task["status_extra"] = json!({"error_detail": t.error_detail});
+    }
+    task
+}
+
+/* offset and limit as DSM treats them, a limit of -1 means "all" */
+fn page(state: &State, params: &Params) -> Result<(usize, usize), ApiError> {
+    let offset = match params.get("offset") {
+        Some(o) => o.parse().map_err(|_| ApiError(101))?,
+        None => 0
+    };
+    let limit = match params.get("limit").map(String::as_str) {
+        None | Some("-1") => state.options.page_limit,
+        Some(l) => l.parse::<usize>().map_err(|_| ApiError(101))?
+    };
+    Ok((offset, limit.min(state.options.page_limit)))
+}
+
+fn list(state: &State, params: &Params, ds2: bool) -> ApiResult {
+    let (offset, limit) = page(state, params)?;
+    let tasks: Vec<Value> = state.options.tasks.iter()
+        .skip(offset)
+        .take(limit)
+        .map(|t| task_json(t, ds2))
+        .collect();
+    let key = match ds2 {
+        true => "task",
+        false => "tasks"
+    };
+    let mut res = json!({"offset": offset, "total": state.options.tasks.len()});
+    res[key] = json!(tasks);
+    Ok(res)
+}
+
+fn get(state: &State, ids: &[String], ds2: bool) -> ApiResult {
+    let mut tasks = vec![];
+    for id in ids {
+        match state.options.tasks.iter().find(|t| &t.id == id) {
+            Some(t) => tasks.push(task_json(t, ds2)),
+            None => return Err(ApiError(404))
+        }
+    }
+    let key = match ds2 {
+        true => "task",
+        false => "tasks"
+    };
+    let mut res = json!({});
+    res[key] = json!(tasks);
+    Ok(res)
+}
+
+fn create(state: &mut State, uris: &[String]) -> Result<Vec<String>, ApiError> {
+    if uris.is_empty() || uris.iter().any(|u| u.trim().is_empty()) {
+        return Err(ApiError(101));
+    }
+
+    let mut ids = vec![];
+    for uri in uris {
+        let id = format!("dbid_{}", state.next_id);
+        state.next_id += 1;
+
+        let title = uri.trim_end_matches('/').rsplit('/').next().unwrap_or(uri);
+        let mut task = FakeTask::new(&id, title, "waiting", 1000000000);
+        task.uri = String::from(uri);
+        state.options.tasks.push(task);
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+/* Creates tasks from uploaded files, which DSM names after their contents */
+fn create_from_files(state: &mut State, names: &[&str]) -> Result<Vec<String>, ApiError> {
+    if names.is_empty() || names.iter().any(|n| n.is_empty()) {
+        return Err(ApiError(400));
+    }
+    let uris: Vec<String> = names.iter().map(|n| n.trim_end_matches(".torrent")
+                                                  .trim_end_matches(".nzb")
+                                                  .to_string())
+        .collect();
+    create(state, &uris)
+}
+
+/* Applies a pause/resume/delete action to each task, like DSM does it:
+ * every ID gets its own result and unknown IDs are reported per task. */
+fn action(state: &mut State, ids: &[String], method: &str) -> ApiResult {
+    let mut results = vec![];
+    for id in ids {
+        let pos = state.options.tasks.iter().position(|t| &t.id == id);
+        let error = match pos {
+            None => 404,
+            Some(i) if method == "delete" => {
+                state.options.tasks.remove(i);
+                0
+            }
+            Some(i) => {
+                let t = &mut state.options.tasks[i];
+                match (method, t.status.as_str()) {
+                    ("pause", "waiting") | ("pause", "downloading") | ("pause", "seeding") => {
+                        t.status = String::from("paused");
+                        t.speed_download = 0;
+                        t.speed_upload = 0;
+                        0
+                    }
+                    ("resume", "paused") | ("resume", "error") => {
+                        t.status = String::from(match t.is_complete() {
+                            true => "seeding",
+                            false => "downloading"
+                        });
+                        t.error_detail.clear();
+                        0
+                    }
+                    _ => 405
+                }
+            }
+        };
+        results.push(json!({"id": id, "error": error}));
+    }
+
+    /* a request about a single task fails as a whole */
+    match results.as_slice() {
+        [r] if r["error"] != 0 => Err(ApiError(r["error"].as_u64().unwrap_or(100) as u32)),
+        _ => Ok(json!(results))
+    }
+}
+
+/* rates are KB/s, the other settings are left alone */
+const RATE_SETTINGS: [&str; 7] = [
+    "bt_max_download", "bt_max_upload", "emule_max_download", "emule_max_upload",
+    "ftp_max_download", "http_max_download", "nzb_max_download"
+];
+
+fn info(state: &mut State, params: &Params) -> ApiResult {
+    if version(params)? > 2 {
+        return Err(ApiError(104));
+    }
+
+    match method(params)? {
+        "getinfo" => Ok(json!({"is_manager": true, "version": 3500,
+                               "version_string": "3.5-3500"})),
+        "getconfig" => Ok(state.config.clone()),
+        "setserverconfig" => {
+            let mut changes = vec![];
+            for name in RATE_SETTINGS {
+                if let Some(value) = params.get(name) {
+                    let n: u64 = value.parse().map_err(|_| ApiError(101))?;
+                    changes.push((name, n));
+                }
+            }
+            if changes.is_empty() {
+                return Err(ApiError(101));
+            }
+            for (name, n) in changes {
+                state.config[name] = json!(n);
+            }
+            Ok(json!({}))
+        }
+        _ => Err(ApiError(103))
+    }
+}
+
+fn legacy_ids(params: &Params) -> Result<Vec<String>, ApiError> {
+    let ids = param(params, "id")?;
+    Ok(ids.split(',').filter(|id| !id.is_empty()).map(String::from).collect())
+}
+
+fn legacy_task(state: &mut State, params: &Params) -> ApiResult {
+    let method = method(params)?;
+    let version = version(params)?;
+    if version > 3 {
+        return Err(ApiError(104));
+    }
+
+    match method {
+        "list" => list(state, params, false),
+        "getinfo" => get(state, &legacy_ids(params)?, false),
+        "create" if params.contains_key("file") => {
+            create_from_files(state, &[param(params, "file")?]).map(|_| json!({}))
+        }
+        "create" => {
+            let uris: Vec<String> = param(params, "uri")?.split(',')
+                .map(String::from)
+                .collect();
+            create(state, &uris).map(|_| json!({}))
+        }
+        "pause" | "resume" | "delete" => action(state, &legacy_ids(params)?, method),
+        _ => Err(ApiError(103))
+    }
+}
+
+fn ds2_ids(params: &Params) -> Result<Vec<String>, ApiError> {
+    serde_json::from_str(param(params, "id")?).map_err(|_| ApiError(101))
+}
+
+fn ds2_task(state: &mut State, params: &Params) -> ApiResult {
+    let method = method(params)?;
+    let version = version(params)?;
+    if version > 2 {
+        return Err(ApiError(104));
+    }
+
+    match method {
+        "list" => list(state, params, true),
+        "get" => get(state, &ds2_ids(params)?, true),
+        "create" if param(params, "type")? == "\"file\"" => {
+            param(params, "destination")?;
+            let fields: Vec<String> = serde_json::from_str(param(params, "file")?)
+                .map_err(|_| ApiError(101))?;
+            let names = fields.iter()
+                .map(|f| param(params, f))
+                .collect::<Result<Vec<&str>, ApiError>>()?;
+            let ids = create_from_files(state, &names)?;
+            Ok(json!({"list_id": [], "task_id": ids}))
+        }
+        "create" => {
+            param(params, "destination")?;
+            if param(params, "type")? != "\"url\"" {
+                return Err(ApiError(101));
+            }
+            let uris: Vec<String> = serde_json::from_str(param(params, "url")?)
+                .map_err(|_| ApiError(101))?;
+            let ids = create(state, &uris)?;
+            Ok(json!({"list_id": [], "task_id": ids}))
+        }
+        "pause" | "resume" | "delete" => action(state, &ds2_ids(params)?, method),
+        _ => Err(ApiError(103))
+    }
+}
@@ -0,0 +1,257 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{env, error, fs, io, path::{Path, PathBuf}};
+
+use url::Url;
+
+use crate::{backend::DownloadBackend, duplicate::{already_added, find_file, find_link}};
+
+const DESKTOP_FILE: &str = "synodl.desktop";
+const MIME_TYPES: [&str; 2] = ["x-scheme-handler/magnet", "application/x-bittorrent"];
+const DEFAULTS_SECTION: &str = "[Default Applications]";
+
+/* quoting as the desktop entry spec wants it for arguments of Exec */
+fn quote_exec(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+    let reserved = |c: char| " \t\n\"'\\><~|&;$*?#()`".contains(c);
+    match arg.contains(reserved) {
+        false => arg,
+        true => {
+            let mut quoted = String::from("\"");
+            for c in arg.chars() {
+                if "\"`$\\".contains(c) {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted.push('"');
+            quoted
+        }
+    }
+}
+
+fn desktop_entry(command: &[String]) -> String {
+    let exec: Vec<String> = command.iter().map(|a| quote_exec(a)).collect();
+    format!("[Desktop Entry]
+Type=Application
+Name=synodl
+GenericName=DownloadStation client
+Comment=Add download tasks to Synology DownloadStation
+Exec={} --handle %u
+Terminal=false
+NoDisplay=true
+MimeType={};
+", exec.join(" "), MIME_TYPES.join(";"))
+}
+
+/* adds lines to the section that ends the list so far, before empty lines */
+fn append_to_section(lines: &mut Vec<String>, new: &[String]) {
+    let end = lines.iter().rposition(|l| !l.trim().is_empty()).map_or(0, |i| i + 1);
+    lines.splice(end..end, new.iter().cloned());
+}
+
+/* makes synodl the default for our MIME types, keeping everything else */
+fn set_defaults(mimeapps: &str) -> String {
+    let ours = |line: &str| MIME_TYPES.iter().any(|t| {
+        line.split_once('=').map(|(k, _)| k.trim() == *t).unwrap_or(false)
+    });
+    let defaults: Vec<String> = MIME_TYPES.iter()
+        .map(|t| format!("{}={};", t, DESKTOP_FILE))
+        .collect();
+
+    let mut lines = vec![];
+    let mut section = String::new();
+    let mut added = false;
+    for line in mimeapps.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            if section == DEFAULTS_SECTION && !added {
+                append_to_section(&mut lines, &defaults);
+                added = true;
+            }
+            section = String::from(trimmed);
+        }
+        if section == DEFAULTS_SECTION && ours(trimmed) {
+            continue;
+        }
+        lines.push(String::from(line));
+    }
+
+    if section == DEFAULTS_SECTION && !added {
+        append_to_section(&mut lines, &defaults);
+    } else if !added {
+        if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+            lines.push(String::new());
+        }
+        lines.push(String::from(DEFAULTS_SECTION));
+        lines.extend(defaults);
+    }
+
+    lines.join("\n") + "\n"
+}
+
+fn data_dir() -> io::Result<PathBuf> {
+    dirs::data_dir().ok_or_else(|| io::Error::other("Cannot find your data directory"))
+}
+
+fn config_dir() -> io::Result<PathBuf> {
+    dirs::config_dir().ok_or_else(|| io::Error::other("Cannot find your config directory"))
+}
+
+/* writes the .desktop file and registers it for magnet links and torrent files;
+ * args are passed on to synodl --handle */
+pub fn install_handler(args: &[String]) -> Result<(), Box<dyn error::Error>> {
+    let mut command = vec![env::current_exe()?.to_string_lossy().into_owned()];
+    command.extend(args.iter().cloned());
+
+    let applications = data_dir()?.join("applications");
+    fs::create_dir_all(&applications)?;
+    let desktop = applications.join(DESKTOP_FILE);
+    fs::write(&desktop, desktop_entry(&command))?;
+    println!("Wrote {}", desktop.display());
+
+    let config = config_dir()?;
+    fs::create_dir_all(&config)?;
+    let mimeapps = config.join("mimeapps.list");
+    let current = match fs::read_to_string(&mimeapps) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(Box::new(e))
+    };
+    fs::write(&mimeapps, set_defaults(&current))?;
+    println!("Registered synodl for {} in {}", MIME_TYPES.join(" and "), mimeapps.display());
+    Ok(())
+}
+
+/* torrent files are handed to us as a path or a file:// URL */
+pub fn local_file(uri: &str) -> Option<PathBuf> {
+    match Url::parse(uri) {
+        Ok(url) if url.scheme() == "file" => url.to_file_path().ok(),
+        _ if Path::new(uri).is_file() => Some(PathBuf::from(uri)),
+        _ => None
+    }
+}
+
+/* the name to upload a local file under, and its contents */
+pub fn read_local(path: &Path) -> io::Result<(String, Vec<u8>)> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    Ok((name, fs::read(path)?))
+}
+
+/* adds a link or uploads a local torrent file */
+pub fn handle<D: DownloadBackend>(client: &mut D, uri: &str)
+        -> Result<(), Box<dyn error::Error>> {
+    let tasks = client.list()?;
+    match local_file(uri) {
+        Some(path) => {
+            let (name, data) = read_local(&path)?;
+            match find_file(&data, &tasks) {
+                Some(task) => Err(already_added(task)),
+                None => client.upload(&name, &data)
+            }
+        },
+        None => match find_link(uri, &tasks) {
+            Some(task) => Err(already_added(task)),
+            None => client.add(uri)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::MemoryBackend;
+
+    #[test]
+    fn exec_line() {
+        let entry = desktop_entry(&[String::from("/home/me/my bin/synodl"),
+                                    String::from("--profile"), String::from("home")]);
+        assert!(entry.contains("\nExec=\"/home/me/my bin/synodl\" --profile home --handle %u\n"));
+        assert!(entry.contains("\nMimeType=x-scheme-handler/magnet;application/x-bittorrent;\n"));
+
+        assert_eq!(quote_exec("100%"), "100%%");
+        assert_eq!(quote_exec("a\"$b"), "\"a\\\"\\$b\"");
+    }
+
+    #[test]
+    fn mimeapps_without_defaults() {
+        assert_eq!(set_defaults(""), "[Default Applications]
+x-scheme-handler/magnet=synodl.desktop;
+application/x-bittorrent=synodl.desktop;
+");
+
+        let list = "[Added Associations]\ntext/plain=vim.desktop;\n";
+        assert_eq!(set_defaults(list), "[Added Associations]
+text/plain=vim.desktop;
+
+[Default Applications]
+x-scheme-handler/magnet=synodl.desktop;
+application/x-bittorrent=synodl.desktop;
+");
+    }
+
+    #[test]
+    fn mimeapps_with_defaults() {
+        let list = "[Default Applications]
+text/html=firefox.desktop;
+x-scheme-handler/magnet=transmission.desktop;
+
+[Added Associations]
+text/plain=vim.desktop;
+";
+        assert_eq!(set_defaults(list), "[Default Applications]
+text/html=firefox.desktop;
+x-scheme-handler/magnet=synodl.desktop;
+application/x-bittorrent=synodl.desktop;
+
+[Added Associations]
+text/plain=vim.desktop;
+");
+    }
+
+    #[test]
+    fn handle_links_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let torrent = dir.path().join("debian.torrent");
+        fs::write(&torrent, "d4:infoe").unwrap();
+        let mut backend = MemoryBackend::new(vec![]);
+
+        handle(&mut backend, "magnet:?xt=urn:btih:1").unwrap();
+        handle(&mut backend, torrent.to_str().unwrap()).unwrap();
+        handle(&mut backend, Url::from_file_path(&torrent).unwrap().as_str()).unwrap();
+
+        let titles: Vec<&str> = backend.tasks.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, ["magnet:?xt=urn:btih:1", "debian.torrent", "debian.torrent"]);
+
+        assert!(handle(&mut backend, "file:///nonexistent/x.torrent").is_err());
+    }
+
+    #[test]
+    fn handle_refuses_duplicates() {
+        let mut backend = MemoryBackend::new(vec![]);
+        let magnet = "magnet:?xt=urn:btih:c1599dd742faa9d633b4fb96d49c5abb02798eef&dn=a";
+
+        handle(&mut backend, magnet).unwrap();
+        let err = handle(&mut backend, "magnet:?xt=urn:btih:YFMZ3V2C7KU5MM5U7OLNJHC2XMBHTDXP")
+            .unwrap_err();
+        assert_eq!(err.to_string(), format!("Already added as {} (dbid_1)", magnet));
+        assert_eq!(backend.tasks.len(), 1);
+    }
+}
@@ -0,0 +1,308 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::thread;
+
+use subprocess::{Exec, NullFile};
+use synodl::syno::Task;
+
+use crate::{backend::owner, config::{Config, Profile}};
+
+/* what can happen to a task between two refreshes */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Added,
+    Finished,
+    Error,
+    Removed
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Added => "added",
+            Event::Finished => "finished",
+            Event::Error => "error",
+            Event::Removed => "removed"
+        }
+    }
+}
+
+/* how the task list changed, by task ID */
+pub fn changes(old: &[Task], new: &[Task]) -> Vec<(Event, Task)> {
+    let mut changes = vec![];
+
+    for task in new {
+        /* a task can be done already when we first see it */
+        let old = old.iter().find(|t| t.id == task.id);
+        if old.is_none() {
+            changes.push((Event::Added, task.clone()));
+        }
+        if task.is_finished() && !old.is_some_and(Task::is_finished) {
+            changes.push((Event::Finished, task.clone()));
+        } else if task.is_failed() && !old.is_some_and(Task::is_failed) {
+            changes.push((Event::Error, task.clone()));
+        }
+    }
+
+    for task in old.iter().filter(|o| new.iter().all(|t| t.id != o.id)) {
+        changes.push((Event::Removed, task.clone()));
+    }
+    changes
+}
+
+/* the hook commands of one profile */
+#[derive(Clone, Debug, Default)]
+struct Commands {
+    on_added: Option<String>,
+    on_finished: Option<String>,
+    on_error: Option<String>,
+    on_removed: Option<String>
+}
+
+impl Commands {
+    fn new(cfg: &Config) -> Commands {
+        Commands {
+            on_added: cfg.on_added.clone(),
+            on_finished: cfg.on_finished.clone(),
+            on_error: cfg.on_error.clone(),
+            on_removed: cfg.on_removed.clone()
+        }
+    }
+
+    fn get(&self, event: Event) -> Option<&str> {
+        match event {
+            Event::Added => self.on_added.as_deref(),
+            Event::Finished => self.on_finished.as_deref(),
+            Event::Error => self.on_error.as_deref(),
+            Event::Removed => self.on_removed.as_deref()
+        }
+    }
+}
+
+/* what a hook gets to know about the task */
+fn environment(event: Event, task: &Task, profile: &str) -> Vec<(&'static str, String)> {
+    let id = match owner(task) {
+        Some(nas) => &task.id[nas.len() + 1..],
+        None => &task.id
+    };
+    vec![
+        ("SYNODL_EVENT", String::from(event.name())),
+        ("SYNODL_PROFILE", String::from(profile)),
+        ("SYNODL_TASK_ID", String::from(id)),
+        ("SYNODL_TASK_TITLE", task.title.clone()),
+        ("SYNODL_TASK_STATUS", task.status.clone()),
+        ("SYNODL_TASK_SIZE", task.size.to_string()),
+        ("SYNODL_TASK_DESTINATION", task.destination.clone())
+    ]
+}
+
+/* runs the configured commands when tasks are added, finish, fail or go away */
+#[derive(Default)]
+pub struct Hooks {
+    profiles: Vec<(String, Commands)>,
+    /* the last task list, nothing is reported for the first one */
+    known: Option<Vec<Task>>,
+    /* hide the output of hooks, it would mess up the screen */
+    pub quiet: bool
+}
+
+impl Hooks {
+    pub fn new(profiles: &[Profile]) -> Hooks {
+        Hooks {
+            profiles: profiles.iter().map(|p| (p.name.clone(), Commands::new(&p.config))).collect(),
+            ..Hooks::default()
+        }
+    }
+
+    /* the same, with the output of the commands hidden */
+    pub fn silent(self) -> Hooks {
+        Hooks { quiet: true, ..self }
+    }
+
+    /* start over, e.g. after switching to another NAS */
+    pub fn reset(&mut self) {
+        self.known = None;
+    }
+
+    /* remembers the new task list, returns what changed since the last one */
+    pub fn update(&mut self, tasks: &[Task]) -> Vec<(Event, Task)> {
+        let changes = match &self.known {
+            Some(old) => changes(old, tasks),
+            None => vec![]
+        };
+        self.known = Some(tasks.to_vec());
+        changes
+    }
+
+    /* like update, but keeps the tasks of the devices that did not answer as they were */
+    pub fn update_partial(&mut self, tasks: &[Task], failures: &[(String, String)])
+            -> Vec<(Event, Task)> {
+        let mut tasks = tasks.to_vec();
+        if let Some(old) = &self.known {
+            tasks.extend(old.iter()
+                .filter(|t| failures.iter().any(|(nas, _)| owner(t) == Some(nas)))
+                .cloned());
+        }
+        self.update(&tasks)
+    }
+
+    /* tasks of several NAS carry the profile name, otherwise it is the current one */
+    fn commands(&self, profile: usize, task: &Task) -> Option<&(String, Commands)> {
+        match owner(task) {
+            Some(nas) => self.profiles.iter().find(|(name, _)| name == nas),
+            None => self.profiles.get(profile)
+        }
+    }
+
+    /* starts the hooks for the changes without waiting for them, returns what failed */
+    pub fn run(&self, profile: usize, changes: &[(Event, Task)]) -> Vec<String> {
+        let mut errors = vec![];
+        for (event, task) in changes {
+            let (name, command) = match self.commands(profile, task) {
+                Some((name, commands)) => match commands.get(*event) {
+                    Some(command) => (name, command),
+                    None => continue
+                },
+                None => continue
+            };
+
+            let mut exec = Exec::shell(command).stdin(NullFile);
+            for (key, value) in environment(*event, task, name) {
+                exec = exec.env(key, value);
+            }
+            if self.quiet {
+                exec = exec.stdout(NullFile).stderr(NullFile);
+            }
+
+            match exec.popen() {
+                Ok(mut p) => {
+                    thread::spawn(move || p.wait());
+                },
+                Err(e) => errors.push(format!("on_{} hook for {}: {}", event.name(),
+                                              task.title, e))
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::{Duration, Instant}};
+
+    use super::*;
+    use crate::backend::memory::make_task;
+
+    fn events(changes: &[(Event, Task)]) -> Vec<(Event, &str)> {
+        changes.iter().map(|(e, t)| (*e, t.id.as_str())).collect()
+    }
+
+    #[test]
+    fn changes_between_lists() {
+        let old = [make_task("dbid_1", "a", "downloading"),
+                   make_task("dbid_2", "b", "downloading"),
+                   make_task("dbid_3", "c", "seeding"),
+                   make_task("dbid_4", "d", "waiting")];
+        let new = [make_task("dbid_1", "a", "finished"),
+                   make_task("dbid_2", "b", "error"),
+                   make_task("dbid_3", "c", "finished"),
+                   make_task("dbid_5", "e", "waiting"),
+                   make_task("dbid_6", "f", "seeding"),
+                   make_task("dbid_7", "g", "error")];
+
+        assert_eq!(events(&changes(&old, &new)), [(Event::Finished, "dbid_1"),
+                                                  (Event::Error, "dbid_2"),
+                                                  (Event::Added, "dbid_5"),
+                                                  (Event::Added, "dbid_6"),
+                                                  (Event::Finished, "dbid_6"),
+                                                  (Event::Added, "dbid_7"),
+                                                  (Event::Error, "dbid_7"),
+                                                  (Event::Removed, "dbid_4")]);
+    }
+
+    #[test]
+    fn first_list_is_not_reported() {
+        let mut hooks = Hooks::default();
+        let tasks = [make_task("dbid_1", "a", "downloading")];
+        assert!(hooks.update(&tasks).is_empty());
+        assert!(hooks.update(&tasks).is_empty());
+
+        hooks.reset();
+        assert!(hooks.update(&[]).is_empty());
+        assert_eq!(events(&hooks.update(&tasks)), [(Event::Added, "dbid_1")]);
+    }
+
+    #[test]
+    fn failed_devices_keep_their_tasks() {
+        let mut hooks = Hooks::default();
+        hooks.update(&[make_task("home/dbid_1", "a", "downloading"),
+                       make_task("office/dbid_1", "b", "downloading")]);
+
+        let failures = [(String::from("home"), String::from("Connection refused"))];
+        let tasks = [make_task("office/dbid_1", "b", "finished")];
+        assert_eq!(events(&hooks.update_partial(&tasks, &failures)),
+                   [(Event::Finished, "office/dbid_1")]);
+
+        let tasks = [make_task("office/dbid_1", "b", "finished")];
+        assert_eq!(events(&hooks.update_partial(&tasks, &[])), [(Event::Removed, "home/dbid_1")]);
+    }
+
+    #[test]
+    fn task_environment() {
+        let task = Task { destination: String::from("downloads/iso"),
+                          ..make_task("office/dbid_1", "debian.iso", "finished") };
+        let env = environment(Event::Finished, &task, "office");
+        assert_eq!(env, [("SYNODL_EVENT", String::from("finished")),
+                         ("SYNODL_PROFILE", String::from("office")),
+                         ("SYNODL_TASK_ID", String::from("dbid_1")),
+                         ("SYNODL_TASK_TITLE", String::from("debian.iso")),
+                         ("SYNODL_TASK_STATUS", String::from("finished")),
+                         ("SYNODL_TASK_SIZE", String::from("1024000")),
+                         ("SYNODL_TASK_DESTINATION", String::from("downloads/iso"))]);
+    }
+
+    fn profile(name: &str, on_finished: Option<String>) -> Profile {
+        Profile { name: String::from(name), config: Config { on_finished, ..Config::default() } }
+    }
+
+    #[test]
+    fn run_hooks_of_owning_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let command = format!("echo \"$SYNODL_PROFILE $SYNODL_TASK_ID $SYNODL_TASK_TITLE\" >> {}",
+                              log.display());
+
+        let mut hooks = Hooks::new(&[profile("home", None), profile("office", Some(command))]);
+        hooks.quiet = true;
+        let changes = [(Event::Finished, make_task("home/dbid_1", "a", "finished")),
+                       (Event::Finished, make_task("office/dbid_1", "my file", "finished")),
+                       (Event::Added, make_task("office/dbid_2", "c", "waiting"))];
+        assert!(hooks.run(0, &changes).is_empty());
+
+        /* the shell creates the file before echo writes to it */
+        let start = Instant::now();
+        let mut text = String::new();
+        while !text.ends_with('\n') && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+            text = fs::read_to_string(&log).unwrap_or_default();
+        }
+        assert_eq!(text, "office dbid_1 my file\n");
+    }
+}
@@ -0,0 +1,39 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+//! Client library for Synology's DownloadStation.
+//!
+//! The [`syno`] module talks to DownloadStation's web API, using the newer
+//! DownloadStation2 API on DSM 7 and the legacy task API on older systems.
+//!
+//! ```no_run
+//! use synodl::syno::{ClientConfig, SynoClient};
+//!
+//! let cfg = ClientConfig::new("https://nas:5001/", "admin", "secret");
+//! let client = SynoClient::login(&cfg)?;
+//! for task in client.list()? {
+//!     println!("{} {}", task.status, task.title);
+//! }
+//! client.logout()?;
+//! # Ok::<(), synodl::syno::Error>(())
+//! ```
+
+#[cfg(feature = "fake")]
+pub mod fake;
+pub mod syno;
@@ -17,99 +17,151 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 */
 
+mod backend;
+mod cleanup;
+mod cli;
+mod config;
+mod duplicate;
+mod exporter;
+mod handler;
+mod hooks;
+mod monitor;
+mod preview;
+mod prompt;
+mod retry;
+mod stats;
+mod torrent;
 mod ui;
-mod syno;
+mod wait;
+mod watch;
+mod wizard;
 
-use std::{io, io::{Error, ErrorKind}, fs, error, path::Path, cmp::min, env};
+use std::{io, io::{Error, IsTerminal}, error, fmt, fs, path::{Path, PathBuf}, process, cmp::min, env,
+          time::{Duration, Instant}};
 use dirs::home_dir;
-use getopts::Options;
 use crossterm::{
     event::{self, Event, KeyCode},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen,
                LeaveAlternateScreen},
 };
-use subprocess::Exec;
 use tui::{
     backend::{Backend, CrosstermBackend},
     widgets::TableState,
     Terminal
 };
-use serde::{Deserialize, Serialize};
-
-use crate::{ui::ui, syno::api::{syno_list, syno_list_tasks, syno_resume_all, syno_login, syno_logout, syno_download, syno_delete,
-Session}};
-
-#[derive(Deserialize, Serialize)]
-pub struct Task {
-    id: String,
-    title: String,
-    status: String,
-    size: u64,
-    size_downloaded: u64,
-    size_uploaded: u64,
-    speed_download: u64,
-    speed_upload: u64,
-    percent_dn: f64,
-    percent_up: f64
-}
+
+use synodl::syno::{ClientConfig, Limits, SynoClient, Task, Traffic};
+
+use crate::{
+    prompt::read_password,
+    ui::{ui, limits::PRESETS},
+    watch::Watcher,
+    wizard::{init, Prompter},
+    backend::{owner, DownloadBackend, Login, Member, MultiBackend},
+    cleanup::cleanup,
+    duplicate::{find_file, find_link},
+    cli::{load_urls, parse, print_usage, resume_all_tasks, Command},
+    handler::{install_handler, local_file, read_local},
+    preview::{preview, preview_urls},
+    torrent::Torrent,
+    hooks::Hooks,
+    retry::Retry,
+    stats::{show_stats, stats_file, Recorder},
+    monitor::Monitor,
+    exporter::Exporter,
+    config::{find_config, load_config, Config, ConfigError, ConfigFile, Overrides, Profile,
+             DEFAULT_PROFILE}
+};
+
+/* how often the task list is refreshed while nobody presses a key */
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
 
 #[derive(PartialEq)]
 enum Activity {
     Idle,
     Refresh,
     Quit,
-    Submit
+    Submit,
+    Switch
 }
 
 pub struct UI {
     ask_for_task_url: bool,
     confirm_delete: bool,
     delete_yes_selected: bool,
+    /* the task the new URL was added as before, while asking to add it again */
+    duplicate_of: Option<String>,
+    duplicate_yes_selected: bool,
     show_details: bool,
-    show_help: bool
+    show_help: bool,
+    select_profile: bool,
+    profile_selected: usize,
+    show_limits: bool,
+    preset_selected: usize
 }
 
 pub struct App {
     state: TableState,
     tasks: Vec<Task>,
     input: String,
+    /* what the URL being typed would add, when that is known locally */
+    preview: Option<Result<Torrent, String>>,
     error: String,
     activity: Activity,
+    profiles: Vec<String>,
+    profile: usize,
+    aggregate: bool,
+    hooks: Hooks,
+    recorder: Recorder,
+    /* whether failed tasks are resumed, per profile */
+    auto_resume: Vec<bool>,
+    retry: Retry,
+    /* speed limits of the NAS, as of the last time the popup was opened */
+    limits: Limits,
     ui: UI
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct Config {
-    user: String,
-    url: String,
-
-    password: Option<String>,
-    password_command: Option<String>,
-    cacert: Option<String>
-}
-
 impl App {
     fn new() -> App {
         let ui = UI {
             ask_for_task_url: false,
             confirm_delete: false,
             delete_yes_selected: false,
+            duplicate_of: None,
+            duplicate_yes_selected: false,
             show_details: false,
             show_help: false,
+            select_profile: false,
+            profile_selected: 0,
+            show_limits: false,
+            preset_selected: 0
         };
         App {
             activity: Activity::Refresh,
             state: TableState::default(),
             tasks: vec![],
             input: String::new(),
+            preview: None,
             error: String::new(),
-            ui: ui
+            profiles: vec![String::from(DEFAULT_PROFILE)],
+            profile: 0,
+            aggregate: false,
+            hooks: Hooks::default().silent(),
+            recorder: Recorder::default(),
+            auto_resume: vec![],
+            retry: Retry::default(),
+            limits: Limits::default(),
+            ui
         }
     }
 
+    fn with_profiles(profiles: Vec<String>, profile: usize) -> App {
+        App { profiles, profile, ..App::new() }
+    }
+
     fn next(&mut self) {
-        if self.tasks.len() > 0 {
+        if !self.tasks.is_empty() {
             let i = match self.state.selected() {
                 Some(i) => {
                     if i >= self.tasks.len() - 1 {
@@ -142,7 +194,7 @@ impl App {
     }
 
     fn first(&mut self) {
-        if self.tasks.len() > 0 {
+        if !self.tasks.is_empty() {
             self.state.select(Some(0));
         }
     }
@@ -167,15 +219,9 @@ impl App {
     }
 
     fn previous_page(&mut self, lines: usize) {
-        if self.tasks.len() > 0 {
+        if !self.tasks.is_empty() {
             let i = match self.state.selected() {
-                Some(i) => {
-                    if lines > i {
-                        0
-                    } else {
-                        i - lines
-                    }
-                },
+                Some(i) => i.saturating_sub(lines),
                 None => 0
             };
 
@@ -183,21 +229,88 @@ impl App {
         };
     }
 
-    fn reload(&mut self, cfg: &Config, session: &Session) -> Result<(), Box<dyn error::Error>> {
+    fn selected_task(&self) -> Option<&Task> {
+        match self.state.selected() {
+            Some(i) => self.tasks.get(i),
+            None => None
+        }
+    }
+
+    fn reload<D: DownloadBackend>(&mut self, backend: &mut D) -> Result<(), Box<dyn error::Error>> {
         self.activity = Activity::Refresh;
-        self.tasks = syno_list(cfg, session)?;
+        self.tasks = backend.list()?;
         self.activity = Activity::Idle;
 
+        let failures = backend.failures();
+        if !failures.is_empty() {
+            let errors: Vec<String> = failures.iter()
+                .map(|(nas, e)| format!("Cannot list tasks of {}: {}", nas, e))
+                .collect();
+            self.error = errors.join("\n");
+        }
+
+        let changes = self.hooks.update_partial(&self.tasks, failures);
+        let errors = self.hooks.run(self.profile, &changes);
+        if !errors.is_empty() {
+            self.error = errors.join("\n");
+        }
+        if let Err(e) = self.recorder.record(&self.tasks) {
+            self.error = e.to_string();
+        }
+
+        let failed: Vec<Task> = self.tasks.iter()
+            .filter(|t| t.is_failed() && self.auto_resumes(t))
+            .cloned()
+            .collect();
+        for res in self.retry.run(backend, &failed, Instant::now()) {
+            if let Err(e) = res {
+                self.error = e;
+            }
+        }
+
         match self.state.selected() {
-            Some(_i) => {},
-            None => self.first()
+            Some(i) if i < self.tasks.len() => {},
+            _ => {
+                self.state.select(None);
+                self.first()
+            }
         };
 
         Ok(())
     }
 
-    fn start_download(&mut self, cfg: &Config, session: &Session) {
-        match syno_download(cfg, session, &self.input) {
+    /* whether the profile the task belongs to has auto_resume set */
+    fn auto_resumes(&self, task: &Task) -> bool {
+        let profile = match owner(task) {
+            Some(nas) => self.profiles.iter().position(|p| p == nas),
+            None => Some(self.profile)
+        };
+        profile.and_then(|i| self.auto_resume.get(i).copied()).unwrap_or(false)
+    }
+
+    fn start_download<D: DownloadBackend>(&mut self, backend: &mut D) {
+        self.ui.ask_for_task_url = false;
+        self.preview = None;
+        let duplicate = match local_file(&self.input) {
+            Some(path) => fs::read(path).ok().and_then(|data| find_file(&data, &self.tasks)),
+            None => find_link(&self.input, &self.tasks)
+        };
+        if let Some(task) = duplicate {
+            self.ui.duplicate_of = Some(task.title.clone());
+            self.ui.duplicate_yes_selected = false;
+            return;
+        }
+        self.add_input(backend);
+    }
+
+    /* adds the URL typed in, or uploads the torrent file it names */
+    fn add_input<D: DownloadBackend>(&mut self, backend: &mut D) {
+        let res = match local_file(&self.input) {
+            Some(path) => read_local(&path).map_err(|e| e.into())
+                .and_then(|(name, data)| backend.upload(&name, &data)),
+            None => backend.add(&self.input)
+        };
+        match res {
             Ok(()) => {
                 self.input.clear();
                 self.activity = Activity::Refresh;
@@ -206,119 +319,343 @@ impl App {
                 self.error = e.to_string();
             }
         }
-        self.ui.ask_for_task_url = false;
     }
 
-    fn delete(&mut self, cfg: &Config, session: &Session) {
-        match self.state.selected() {
-            Some(i) => {
-                let t = &self.tasks.get(i);
-                match t {
-                    Some(task) =>  {
-                        match syno_delete(cfg, session, task) {
-                            Ok(()) => {
-                                self.input.clear();
-                                self.activity = Activity::Refresh;
-                            },
-                            Err(e) => {
-                                self.error = e.to_string();
-                            }
+    fn delete<D: DownloadBackend>(&mut self, backend: &mut D) {
+        if self.state.selected().is_some() {
+            match self.selected_task() {
+                Some(task) =>  {
+                    match backend.delete(task) {
+                        Ok(()) => {
+                            self.input.clear();
+                            self.activity = Activity::Refresh;
+                        },
+                        Err(e) => {
+                            self.error = e.to_string();
                         }
-                    },
-                    None => {
-                        self.error = String::from("No task found");
                     }
+                },
+                None => {
+                    self.error = String::from("No task found");
                 }
-            },
-            None => {}
-        };
+            }
+        }
     }
 
-    fn quit(&mut self, cfg: &Config, session: &Session)
-            -> Result<(), Box<dyn error::Error>> {
-        syno_logout(cfg, session)?;
-        Ok(())
+    fn toggle_pause<D: DownloadBackend>(&mut self, backend: &mut D) {
+        if let Some(task) = self.selected_task() {
+            let res = match task.status.as_str() {
+                "paused" => backend.resume(task),
+                _ => backend.pause(task)
+            };
+            match res {
+                Ok(()) => self.activity = Activity::Refresh,
+                Err(e) => self.error = e.to_string()
+            }
+        }
     }
-}
 
-fn validate_cacert(f: &str) -> Result<(), io::Error> {
-    match f {
-        "ignore" => Ok(()),
-        _ => {
-            if Path::new(f).exists() {
-                Ok(())
-            } else {
-                Err(Error::new(ErrorKind::NotFound, "CA certificate not found"))
+    fn show_details<D: DownloadBackend>(&mut self, backend: &mut D) {
+        if let Some(i) = self.state.selected() {
+            if let Some(task) = self.tasks.get(i) {
+                match backend.info(task) {
+                    Ok(t) => {
+                        self.tasks[i] = t;
+                        self.ui.show_details = true;
+                    },
+                    Err(e) => self.error = e.to_string()
+                }
             }
         }
     }
-}
 
-fn validate_config(opt: Config) -> Result<Config, io::Error> {
-    match &opt.cacert {
-        Some(f) => {
-            match validate_cacert(f) {
-                Ok(()) => Ok(opt),
-                Err(e) => Err(e)
+    fn ask_for_profile(&mut self) {
+        if self.aggregate {
+            self.error = String::from("Tasks from all profiles are shown already");
+        } else if self.profiles.len() > 1 {
+            self.ui.profile_selected = self.profile;
+            self.ui.select_profile = true;
+        } else {
+            self.error = String::from("There are no other profiles configured");
+        }
+    }
+
+    /* log in with the selected profile and drop the current session */
+    fn switch_profile<D, F>(&mut self, backend: &mut D, connect: &mut F)
+            where D: DownloadBackend,
+                  F: FnMut(usize) -> Result<D, Box<dyn error::Error>> {
+        let i = self.ui.profile_selected;
+
+        match connect(i) {
+            Ok(new) => {
+                let _ = backend.logout();
+                *backend = new;
+                self.profile = i;
+                self.hooks.reset();
+                self.recorder.profile = self.profiles[i].clone();
+                self.retry = Retry::default();
+                self.tasks.clear();
+                self.state.select(None);
+                self.activity = Activity::Refresh;
+            },
+            Err(e) => {
+                self.error = format!("Login to {} failed: {}", self.profiles[i], e);
+                self.activity = Activity::Idle;
             }
         }
-        None => Ok(opt)
     }
-}
 
-fn load_config(file: &Path) -> Result<Config, Box<dyn error::Error>> {
-    let file_content = fs::read_to_string(file)
-        .expect("Error reading file");
-    let mut opt = serde_ini::from_str::<Config>(&file_content)
-        .expect("Failed to load configuration");
-
-    // run password command if set
-    match &opt.password_command {
-        None => {},
-        Some(password_command) => {
-            let output = {
-                Exec::shell(password_command)
-            }.capture()?.stdout_str();
-
-            match output.lines().next() {
-                Some(line) => opt.password = Some(String::from(line)),
-                None => {}
-            };
+    fn show_limits<D: DownloadBackend>(&mut self, backend: &mut D) {
+        match backend.limits() {
+            Ok(limits) => {
+                self.limits = limits;
+                self.ui.preset_selected = 0;
+                self.ui.show_limits = true;
+            },
+            Err(e) => self.error = e.to_string()
         }
-    };
+    }
 
-    match validate_config(opt) {
-        Ok(cfg) => Ok(cfg),
-        Err(e) => Err(Box::new(e))
+    fn apply_preset<D: DownloadBackend>(&mut self, backend: &mut D) {
+        let (_, down, up) = PRESETS[self.ui.preset_selected];
+        let limits = self.limits.with(Some(down), Some(up));
+        match backend.set_limits(&limits) {
+            Ok(()) => self.limits = limits,
+            Err(e) => self.error = e.to_string()
+        }
     }
-}
 
-fn add_task(cfg: Config, session: Session, url: String) -> Result<(), Box<dyn error::Error>> {
-    println!("Adding download task ...");
-    syno_download(&cfg, &session, &url)?;
+    fn quit<D: DownloadBackend>(&mut self, backend: &mut D)
+            -> Result<(), Box<dyn error::Error>> {
+        backend.logout()?;
+        Ok(())
+    }
 
-    println!("Disconnecting ...");
-    syno_logout(&cfg, &session)
-}
+    /* carry out whatever the last key press asked for */
+    fn process<D: DownloadBackend>(&mut self, backend: &mut D) {
+        match self.activity {
+            Activity::Submit => {
+                self.start_download(backend);
+                self.activity = Activity::Refresh;
+            }
+            Activity::Refresh => {
+                if let Err(e) = self.reload(backend) {
+                    self.error = e.to_string()
+                }
+                self.activity = Activity::Idle;
+            }
+            _ => {}
+        }
+    }
 
+    /* None when no key was pressed for REFRESH_INTERVAL; the tasks are then
+       refreshed so that auto_resume and the hooks keep working while idle */
+    fn on_event<D: DownloadBackend>(&mut self, backend: &mut D, event: Option<Event>,
+                                    lines: usize) {
+        match event {
+            Some(Event::Key(key)) => self.on_key(backend, key.code, lines),
+            Some(_) => {},
+            /* the selected task must stay the one asked about */
+            None if self.ui.confirm_delete => {},
+            None => self.activity = Activity::Refresh
+        }
+    }
 
-fn list_tasks(cfg: Config, session: Session) -> Result<(), Box<dyn error::Error>> {
-    println!("Listing download tasks ...");
-    syno_list_tasks(&cfg, &session)?;
+    fn on_key<D: DownloadBackend>(&mut self, backend: &mut D, key: KeyCode,
+                                  lines: usize) {
+        if self.ui.show_help {
+            self.ui.show_help = false
+        } else if self.ui.show_details {
+            self.ui.show_details = false
+        } else if self.ui.ask_for_task_url {
+            match key {
+                KeyCode::Enter => {
+                    self.ui.ask_for_task_url = false;
+                    self.activity = Activity::Submit;
+                },
+                KeyCode::Esc => {
+                    self.ui.ask_for_task_url = false;
+                    self.input.clear();
+                    self.preview = None;
+                },
+                KeyCode::Backspace => {
+                    self.input.pop();
+                    self.preview = preview(&self.input);
+                },
+                KeyCode::Char(c) => {
+                    self.input.push(c);
+                    self.preview = preview(&self.input);
+                },
+                _ => { }
+            }
+        } else if self.ui.select_profile {
+            let n = self.profiles.len();
+            match key {
+                KeyCode::Down | KeyCode::Char('j') | KeyCode::Char('J') => {
+                    self.ui.profile_selected = (self.ui.profile_selected + 1) % n
+                },
+                KeyCode::Up | KeyCode::Char('k') | KeyCode::Char('K') => {
+                    self.ui.profile_selected = (self.ui.profile_selected + n - 1) % n
+                },
+                KeyCode::Enter => {
+                    self.ui.select_profile = false;
+                    if self.ui.profile_selected != self.profile {
+                        self.activity = Activity::Switch;
+                    }
+                },
+                KeyCode::Esc => self.ui.select_profile = false,
+                _ => {}
+            }
+        } else if self.ui.show_limits {
+            let n = PRESETS.len();
+            match key {
+                KeyCode::Down | KeyCode::Char('j') | KeyCode::Char('J') => {
+                    self.ui.preset_selected = (self.ui.preset_selected + 1) % n
+                },
+                KeyCode::Up | KeyCode::Char('k') | KeyCode::Char('K') => {
+                    self.ui.preset_selected = (self.ui.preset_selected + n - 1) % n
+                },
+                KeyCode::Enter => {
+                    self.ui.show_limits = false;
+                    self.apply_preset(backend);
+                },
+                KeyCode::Esc => self.ui.show_limits = false,
+                _ => {}
+            }
+        } else if self.ui.duplicate_of.is_some() {
+            self.ui.duplicate_yes_selected = match key {
+                KeyCode::Left => !self.ui.duplicate_yes_selected,
+                KeyCode::Down => !self.ui.duplicate_yes_selected,
+                KeyCode::Char('h') => !self.ui.duplicate_yes_selected,
+                KeyCode::Char('H') => !self.ui.duplicate_yes_selected,
+                KeyCode::Char('l') => !self.ui.duplicate_yes_selected,
+                KeyCode::Char('L') => !self.ui.duplicate_yes_selected,
+                KeyCode::Enter => {
+                    self.ui.duplicate_of = None;
+                    match self.ui.duplicate_yes_selected {
+                        true => self.add_input(backend),
+                        false => self.input.clear()
+                    }
+                    false
+                },
+                KeyCode::Esc => {
+                    self.ui.duplicate_of = None;
+                    self.input.clear();
+                    false
+                }
+                _ => self.ui.duplicate_yes_selected
+            }
+        } else if self.ui.confirm_delete {
+            self.ui.delete_yes_selected = match key {
+                KeyCode::Left => !self.ui.delete_yes_selected,
+                KeyCode::Down => !self.ui.delete_yes_selected,
+                KeyCode::Char('h') => !self.ui.delete_yes_selected,
+                KeyCode::Char('H') => !self.ui.delete_yes_selected,
+                KeyCode::Char('l') => !self.ui.delete_yes_selected,
+                KeyCode::Char('L') => !self.ui.delete_yes_selected,
+                KeyCode::Enter => {
+                    self.ui.confirm_delete = false;
+                    if self.ui.delete_yes_selected {
+                        self.delete(backend);
+                    }
+                    false
+                },
+                KeyCode::Esc => {
+                    self.ui.confirm_delete = false;
+                    false
+                }
+                _ => self.ui.delete_yes_selected
+            }
+        } else {
+            match key {
+                KeyCode::Down => self.next(),
+                KeyCode::Up => self.previous(),
+                KeyCode::Char('a') => self.ui.ask_for_task_url = true,
+                KeyCode::Char('A') => self.ui.ask_for_task_url = true,
+                KeyCode::Char('d') => self.ui.confirm_delete = true,
+                KeyCode::Char('D') => self.ui.confirm_delete = true,
+                KeyCode::Char('i') => self.show_details(backend),
+                KeyCode::Char('I') => self.show_details(backend),
+                KeyCode::Char('j') => self.next(),
+                KeyCode::Char('J') => self.next(),
+                KeyCode::Char('k') => self.previous(),
+                KeyCode::Char('K') => self.previous(),
+                KeyCode::Char('l') => self.show_limits(backend),
+                KeyCode::Char('L') => self.show_limits(backend),
+                KeyCode::Char('p') => self.toggle_pause(backend),
+                KeyCode::Char('P') => self.toggle_pause(backend),
+                KeyCode::Char('q') => self.activity = Activity::Quit,
+                KeyCode::Char('Q') => self.activity = Activity::Quit,
+                KeyCode::Char('r') => self.activity = Activity::Refresh,
+                KeyCode::Char('R') => self.activity = Activity::Refresh,
+                KeyCode::Char('s') => self.ask_for_profile(),
+                KeyCode::Char('S') => self.ask_for_profile(),
+                KeyCode::Char('?') => self.ui.show_help = true,
+                KeyCode::Home => self.first(),
+                KeyCode::End => self.last(),
+                KeyCode::PageDown => self.next_page(lines),
+                KeyCode::PageUp => self.previous_page(lines),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn connect(cfg: &Config, traffic: &Traffic) -> Result<SynoClient, Box<dyn error::Error>> {
+    login(cfg, &cfg.password()?, traffic)
+}
 
-    println!("Disconnecting ...");
-    syno_logout(&cfg, &session)
+fn login(cfg: &Config, password: &str, traffic: &Traffic)
+        -> Result<SynoClient, Box<dyn error::Error>> {
+    let mut client_cfg = ClientConfig::new(&cfg.url, &cfg.user, password);
+    client_cfg.traffic = traffic.clone();
+    cfg.apply_tls(&mut client_cfg);
+    Ok(SynoClient::login(&client_cfg)?)
 }
 
-fn resume_all_tasks(cfg: Config, session: Session) -> Result<(), Box<dyn error::Error>> {
-    println!("Resuming all download tasks ...");
-    syno_resume_all(&cfg, &session)?;
+/* log in to every profile, skipping those that cannot be reached */
+fn connect_all(config: &ConfigFile, primary: usize, traffic: &Traffic)
+        -> Result<MultiBackend, Box<dyn error::Error>> {
+    let mut members: Vec<Member> = vec![];
+    let mut first = 0;
+    /* logging in again must not ask for a password, the terminal may belong
+       to the task list by then */
+    let mut logins: Vec<(String, Config, String)> = vec![];
+
+    for (i, p) in config.profiles.iter().enumerate() {
+        println!("Connecting to {} ({}) ...", p.name, p.config.url);
+        let res = p.config.password().map_err(|e| e.into())
+            .and_then(|password| login(&p.config, &password, traffic).map(|c| (c, password)));
+        match res {
+            Ok((client, password)) => {
+                if i == primary {
+                    first = members.len();
+                }
+                members.push((p.name.clone(), Box::new(client)));
+                logins.push((p.name.clone(), p.config.clone(), password));
+            },
+            Err(e) => eprintln!("Login to {} failed: {}", p.name, e)
+        }
+    }
+
+    if members.is_empty() {
+        return Err(Box::new(Error::other("Could not log in to any NAS")));
+    }
 
-    println!("Disconnecting ...");
-    syno_logout(&cfg, &session)
+    let traffic = traffic.clone();
+    let relogin: Login = Box::new(move |name| {
+        match logins.iter().find(|(n, _, _)| n == name) {
+            Some((_, cfg, password)) => Ok(Box::new(login(cfg, password, &traffic)?)),
+            None => Err(Box::new(Error::other(format!("No such profile: {}", name))))
+        }
+    });
+    Ok(MultiBackend::new(members, first).with_login(relogin))
 }
 
-fn run_tui(cfg: Config, session: Session) -> Result<(), Box<dyn error::Error>> {
+fn run_tui<D, F>(profiles: &[Profile], app: App, mut client: D, connect: F)
+        -> Result<(), Box<dyn error::Error>>
+        where D: DownloadBackend,
+              F: FnMut(usize) -> Result<D, Box<dyn error::Error>> {
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -326,8 +663,7 @@ fn run_tui(cfg: Config, session: Session) -> Result<(), Box<dyn error::Error>> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let app = App::new();
-    let res = run_app(&mut terminal, app, &cfg, &session);
+    let res = run_app(&mut terminal, app, profiles, &mut client, connect);
 
     // restore terminal
     disable_raw_mode()?;
@@ -343,14 +679,33 @@ fn run_tui(cfg: Config, session: Session) -> Result<(), Box<dyn error::Error>> {
     Ok(())
 }
 
-fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} URL [options]\n
-If URL is empty a list of current download tasks is shown,
-otherwise the URL is added as a download task.", program);
-    print!("{}", opts.usage(&brief));
+fn exit_with<T: fmt::Display>(msg: T) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
+
+/* try to log in with every profile */
+fn check_config(config: &ConfigFile, traffic: &Traffic) -> bool {
+    let mut failed = 0;
+
+    for p in &config.profiles {
+        print!("Profile {} ({}): ", p.name, p.config.url);
+        let res = connect(&p.config, traffic)
+            .and_then(|client| Ok(client.logout()?));
+        match res {
+            Ok(()) => println!("login OK"),
+            Err(e) => {
+                println!("{}", e);
+                failed += 1;
+            }
+        }
+    }
 
-    println!("\nThis is synodl {}.", env!("CARGO_PKG_VERSION"));
-    println!("Report bugs at {}", env!("CARGO_PKG_HOMEPAGE"));
+    match failed {
+        0 => println!("Configuration OK"),
+        n => println!("{} of {} profiles failed", n, config.profiles.len())
+    }
+    failed == 0
 }
 
 fn main() -> Result<(), Box<dyn error::Error>> {
@@ -359,167 +714,319 @@ fn main() -> Result<(), Box<dyn error::Error>> {
     let args: Vec<String> = env::args().collect();
 
     let program = args[0].clone();
-    let mut opts = Options::new();
-
-    opts.optflag("h", "help", "Print help");
-    opts.optflag("l", "list", "List tasks");
-    opts.optflag("r", "resume-all", "resume all tasks");
-    let matches = match opts.parse(&args[1..]) {
-        Ok(m) => { m }
-        Err(f) => { panic!("{}", f.to_string()) }
+    let invocation = match parse(&args[1..]) {
+        Ok(i) => i,
+        Err(e) => exit_with(format!("{}, try --help", e))
+    };
+    let matches = &invocation.matches;
+    if let Some(name) = &invocation.help {
+        print_usage(&program, name);
+        return Ok(());
+    }
+
+    /* statistics are kept locally, no need to log in */
+    if let Command::Stats { period } = invocation.command {
+        return show_stats(period);
+    }
+
+    /* read the URLs to add before asking for a password */
+    let command = &match &invocation.command {
+        Command::Add { urls, from_file, wait, timeout, force, preview } => {
+            match load_urls(urls, from_file.as_deref()) {
+                Ok(urls) => Command::Add { urls, from_file: None, wait: *wait, timeout: *timeout,
+                                           force: *force, preview: *preview },
+                Err(e) => exit_with(e)
+            }
+        },
+        _ => invocation.command
     };
 
-    if matches.opt_present("h") {
-        print_usage(&program, opts);
+    /* previews are made locally, before anything is sent to the NAS */
+    if let Command::Add { urls, preview: true, .. } = command {
+        if !preview_urls(urls) {
+            process::exit(1);
+        }
         return Ok(());
     }
 
-    let add_url = match matches.free.len() {
-        0 => None,
-        _ => Some(matches.free[0].clone())
+    let traffic = match (matches.opt_str("record"), matches.opt_str("replay")) {
+        (Some(_), Some(_)) => exit_with("Cannot record and replay at the same time"),
+        (Some(dir), None) => Traffic::Record(PathBuf::from(dir)),
+        (None, Some(dir)) => Traffic::Replay(PathBuf::from(dir)),
+        (None, None) => Traffic::Live
     };
 
+    if matches.opt_present("init") {
+        let path = match matches.opt_str("config") {
+            Some(p) => PathBuf::from(p),
+            /* replace the file synodl reads, or it would not pick up the new one */
+            None => match find_config().or_else(|| home_dir().map(|h| h.join(".synodl"))) {
+                Some(p) => p,
+                None => exit_with("Cannot find your home directory")
+            }
+        };
+        let stdin = io::stdin();
+        let mut read_hidden = read_password;
+        let mut p = Prompter {
+            input: &mut stdin.lock(),
+            output: &mut io::stdout(),
+            read_password: match stdin.is_terminal() {
+                true => Some(&mut read_hidden),
+                false => None
+            }
+        };
+        return init(&path, &mut p);
+    }
+
+    if matches.opt_present("install-handler") {
+        /* the desktop runs synodl with the same configuration and profile */
+        let mut args = vec![];
+        if let Some(path) = matches.opt_str("config") {
+            args.push(String::from("--config"));
+            args.push(env::current_dir()?.join(path).to_string_lossy().into_owned());
+        }
+        if let Some(profile) = matches.opt_str("profile") {
+            args.push(String::from("--profile"));
+            args.push(profile);
+        }
+        return install_handler(&args);
+    }
+
+    /* settings from the command line win over the environment */
+    let mut overrides = Overrides::from_env();
+    if let Some(url) = matches.opt_str("url") {
+        overrides.url = Some(url);
+    }
+    if let Some(user) = matches.opt_str("user") {
+        overrides.user = Some(user);
+    }
+
     /* load configuration */
-    let mut path = home_dir().expect("Cannot find your home directory");
-    path.push(".synodl");
+    let path = match matches.opt_str("config") {
+        Some(p) if !Path::new(&p).exists() => {
+            exit_with(format!("Configuration file {} not found, aborting...", p))
+        },
+        Some(p) => Some(PathBuf::from(p)),
+        None => find_config()
+    };
+
+    let mut config = match (&path, overrides.to_config(), &traffic) {
+        (Some(path), _, _) => match load_config(path) {
+            Ok(config) => config,
+            Err(e @ ConfigError::Read { .. }) => exit_with(e),
+            Err(e) => exit_with(format!("Error in {}: {}", path.display(), e))
+        },
+        (None, Some(cfg), _) => ConfigFile::single(cfg),
+        /* a recording can be replayed without access to the NAS */
+        (None, None, Traffic::Replay(_)) => ConfigFile::single(Config {
+            user: String::from("replay"),
+            url: String::from("http://replay/"),
+            password: Some(String::new()),
+            ..Config::default()
+        }),
+        (None, None, _) => exit_with("Configuration file not found, aborting...")
+    };
+
+    for warning in &config.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    let profile = match config.select(matches.opt_str("profile").as_deref()) {
+        Ok(i) => i,
+        Err(e) => exit_with(e)
+    };
+    overrides.apply(&mut config.profiles[profile].config);
+
+    if matches.opt_present("check-config") {
+        if let Some(path) = &path {
+            println!("Configuration read from {}", path.display());
+        }
+        match check_config(&config, &traffic) {
+            true => return Ok(()),
+            false => process::exit(1)
+        }
+    }
 
-    if !path.exists() {
-        println!("Configuration file not found, aborting...");
-        return Ok({});
+    let cfg = &config.profiles[profile].config;
+
+    /* started by the desktop: no terminal, only a message and the exit code */
+    if let Some(uri) = matches.opt_str("handle") {
+        let res = connect(cfg, &traffic).and_then(|mut client| {
+            let res = handler::handle(&mut client, &uri);
+            let _ = client.logout();
+            res
+        });
+        match res {
+            Ok(()) => {
+                eprintln!("Added {} to {}", uri, cfg.url);
+                return Ok(());
+            },
+            Err(e) => exit_with(format!("Failed to add {}: {}", uri, e))
+        }
     }
 
-    let cfg = load_config(path.as_path())?;
+    if matches!(command, Command::Cleanup { .. }) && config.cleanup.is_empty() {
+        exit_with("No cleanup rules configured, add a [cleanup] section to the configuration");
+    }
+
+    let names: Vec<String> = config.profiles.iter().map(|p| p.name.clone()).collect();
+
+    let resume_all = matches!(command, Command::Resume { all_tasks: true, .. });
+
+    if matches.opt_present("all") {
+        if resume_all {
+            exit_with("--all does not work with resume --all-tasks");
+        }
+        if let Command::Watch { .. } = command {
+            exit_with("--all does not work with watch");
+        }
+        if let Command::Limits { .. } = command {
+            exit_with("--all does not work with limits, the limits are set per NAS");
+        }
+
+        let mut multi = connect_all(&config, profile, &traffic)?;
+        if let Command::Monitor { interval, auto_resume } = command {
+            let mut monitor = Monitor::new(multi, || connect_all(&config, profile, &traffic),
+                                           Hooks::new(&config.profiles), profile);
+            monitor.recorder = Recorder::new(stats_file(), &config.profiles[profile].name);
+            monitor.retry = auto_resume.then(Retry::default);
+            let res = monitor.run(Duration::from_secs(*interval));
+            let _ = monitor.logout();
+            return res;
+        }
+        if let Command::Exporter { listen, cache } = command {
+            let mut exporter = Exporter::new(multi, || connect_all(&config, profile, &traffic),
+                                             Duration::from_secs(*cache));
+            exporter.recorder = Recorder::new(stats_file(), &config.profiles[profile].name);
+            let res = exporter.run(listen);
+            let _ = exporter.logout();
+            return res;
+        }
+        if let Command::Cleanup { dry_run } = command {
+            return finish(cleanup(&mut multi, &config.cleanup, *dry_run));
+        }
+        if *command != Command::Tui {
+            return finish(cli::run(&mut multi, command,
+                                      || connect_all(&config, profile, &traffic)));
+        }
+
+        let mut app = App::with_profiles(names, profile);
+        app.aggregate = true;
+        app.hooks = Hooks::new(&config.profiles).silent();
+        app.recorder = Recorder::new(stats_file(), &config.profiles[profile].name);
+        app.auto_resume = config.profiles.iter().map(|p| p.config.auto_resume).collect();
+        return run_tui(&config.profiles, app, multi, |_| {
+            Err(Box::new(Error::other("Cannot switch profiles while showing all")))
+        });
+    }
 
     /* start operation */
     println!("Connecting to {} ...", cfg.url);
-    let session = match syno_login(&cfg) {
+    let password = match cfg.password() {
+        Ok(p) => p,
+        Err(e) => exit_with(format!("Login failed: {}", e))
+    };
+    let mut client = match login(cfg, &password, &traffic) {
         Ok(s) => s,
-        Err(e) => {
-            eprintln!("Login failed");
-            return Err(e)
-        }
+        Err(e) => exit_with(format!("Login failed: {}", e))
     };
 
-    if matches.opt_present("l") {
-        return list_tasks(cfg, session);
-    }
-
-    if matches.opt_present("r") {
-        return resume_all_tasks(cfg, session);
+    match command {
+        Command::Tui => {
+            let mut app = App::with_profiles(names, profile);
+            app.hooks = Hooks::new(&config.profiles).silent();
+            app.recorder = Recorder::new(stats_file(), &config.profiles[profile].name);
+            app.auto_resume = config.profiles.iter().map(|p| p.config.auto_resume).collect();
+            /* no asking for passwords once the task list is shown */
+            run_tui(&config.profiles, app, client, |i| {
+                let cfg = &config.profiles[i].config;
+                let password = match i == profile {
+                    true => password.clone(),
+                    false => cfg.stored_password()?
+                };
+                login(cfg, &password, &traffic)
+            })
+        },
+        Command::Watch { dir } => {
+            let mut watcher = Watcher::new(Path::new(dir), client,
+                                           || connect(cfg, &traffic));
+            let res = watcher.run();
+            let _ = watcher.logout();
+            res
+        },
+        Command::Monitor { interval, auto_resume } => {
+            let mut monitor = Monitor::new(client, || connect(cfg, &traffic),
+                                           Hooks::new(&config.profiles), profile);
+            monitor.recorder = Recorder::new(stats_file(), &config.profiles[profile].name);
+            monitor.retry = auto_resume.then(Retry::default);
+            let res = monitor.run(Duration::from_secs(*interval));
+            let _ = monitor.logout();
+            res
+        },
+        Command::Exporter { listen, cache } => {
+            let mut exporter = Exporter::new(client, || connect(cfg, &traffic),
+                                             Duration::from_secs(*cache));
+            exporter.recorder = Recorder::new(stats_file(), &config.profiles[profile].name);
+            let res = exporter.run(listen);
+            let _ = exporter.logout();
+            res
+        },
+        Command::Cleanup { dry_run } => finish(cleanup(&mut client, &config.cleanup, *dry_run)),
+        _ if resume_all => finish(resume_all_tasks(client)),
+        _ => finish(cli::run(&mut client, command, || login(cfg, &password, &traffic)))
     }
+}
 
-    match add_url {
-        None => run_tui(cfg, session),
-        Some(url) => add_task(cfg, session, url)
+/* exit with an error code unless the command worked for all tasks */
+fn finish(res: Result<bool, Box<dyn error::Error>>) -> Result<(), Box<dyn error::Error>> {
+    match res? {
+        true => Ok(()),
+        false => process::exit(1)
     }
 }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App,
-                       cfg: &Config, session: &Session)
-                       -> Result<(), Box<dyn error::Error>> {
+fn run_app<B, D, F>(terminal: &mut Terminal<B>, mut app: App,
+                    profiles: &[Profile], backend: &mut D, mut connect: F)
+                    -> Result<(), Box<dyn error::Error>>
+        where B: Backend,
+              D: DownloadBackend,
+              F: FnMut(usize) -> Result<D, Box<dyn error::Error>> {
     let mut lines = 0;
 
     loop {
         terminal.draw(|f| {
+            let cfg = &profiles[app.profile].config;
             ui(f, &mut app, cfg);
             lines = f.size().height - 2
         })?;
 
         match app.activity {
             Activity::Quit => {
-                return app.quit(cfg, session)
+                return app.quit(backend)
             }
-            Activity::Submit => {
-                app.start_download(cfg, session);
-                app.activity = Activity::Refresh;
+            Activity::Idle => {}
+            Activity::Switch => {
+                app.switch_profile(backend, &mut connect);
                 continue
             }
-            Activity::Refresh => {
-                match app.reload(cfg, session) {
-                    Ok(_) => {},
-                    Err(e) => {
-                        app.error = e.to_string()
-                    }
-                }
-                app.activity = Activity::Idle;
+            _ => {
+                app.process(backend);
                 continue
             }
-            _ => {}
         };
 
-        if let Event::Key(key) = event::read()? {
-            if app.ui.show_help {
-                app.ui.show_help = false
-            } else if app.ui.show_details {
-                app.ui.show_details = false
-            } else if app.ui.ask_for_task_url {
-                match key.code {
-                    KeyCode::Enter => {
-                        app.ui.ask_for_task_url = false;
-                        app.activity = Activity::Submit;
-                    },
-                    KeyCode::Esc => {
-                        app.ui.ask_for_task_url = false;
-                        app.input.clear();
-                    },
-                    KeyCode::Backspace => { app.input.pop(); },
-                    KeyCode::Char(c) => { app.input.push(c) },
-                    _ => { }
-                }
-            } else if app.ui.confirm_delete {
-                app.ui.delete_yes_selected = match key.code {
-                    KeyCode::Left => !app.ui.delete_yes_selected,
-                    KeyCode::Down => !app.ui.delete_yes_selected,
-                    KeyCode::Char('h') => !app.ui.delete_yes_selected,
-                    KeyCode::Char('H') => !app.ui.delete_yes_selected,
-                    KeyCode::Char('l') => !app.ui.delete_yes_selected,
-                    KeyCode::Char('L') => !app.ui.delete_yes_selected,
-                    KeyCode::Enter => {
-                        app.ui.confirm_delete = false;
-                        if app.ui.delete_yes_selected {
-                            app.delete(cfg, session);
-                        }
-                        false
-                    },
-                    KeyCode::Esc => {
-                        app.ui.confirm_delete = false;
-                        false
-                    }
-                    _ => app.ui.delete_yes_selected
-                }
-            } else {
-                match key.code {
-                    KeyCode::Down => app.next(),
-                    KeyCode::Up => app.previous(),
-                    KeyCode::Char('a') => app.ui.ask_for_task_url = true,
-                    KeyCode::Char('A') => app.ui.ask_for_task_url = true,
-                    KeyCode::Char('d') => app.ui.confirm_delete = true,
-                    KeyCode::Char('D') => app.ui.confirm_delete = true,
-                    KeyCode::Char('i') => app.ui.show_details = true,
-                    KeyCode::Char('I') => app.ui.show_details = true,
-                    KeyCode::Char('j') => app.next(),
-                    KeyCode::Char('J') => app.next(),
-                    KeyCode::Char('k') => app.previous(),
-                    KeyCode::Char('K') => app.previous(),
-                    KeyCode::Char('q') => app.activity = Activity::Quit,
-                    KeyCode::Char('Q') => app.activity = Activity::Quit,
-                    KeyCode::Char('r') => app.activity = Activity::Refresh,
-                    KeyCode::Char('R') => app.activity = Activity::Refresh,
-                    KeyCode::Char('?') => app.ui.show_help = true,
-                    KeyCode::Home => app.first(),
-                    KeyCode::End => app.last(),
-                    KeyCode::PageDown => app.next_page(lines as usize),
-                    KeyCode::PageUp => app.previous_page(lines as usize),
-                    _ => {}
-                }
-            }
-        }
+        let event = match event::poll(REFRESH_INTERVAL)? {
+            true => Some(event::read()?),
+            false => None
+        };
+        app.on_event(backend, event, lines as usize);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::memory::{make_task, MemoryBackend};
 
     fn get_test_app(_howmany: usize) -> App {
         let mut app = App::new();
@@ -531,10 +1038,9 @@ mod tests {
                 size: 1024000,
                 size_downloaded: 1024000,
                 size_uploaded: 1024000,
-                speed_download: 0,
-                speed_upload: 0,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             },
             Task {
                 id: String::from("uuid01"),
@@ -543,10 +1049,9 @@ mod tests {
                 size: 1024000,
                 size_downloaded: 1024000,
                 size_uploaded: 1024000,
-                speed_download: 0,
-                speed_upload: 0,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             },
             Task {
                 id: String::from("uuid01"),
@@ -555,10 +1060,9 @@ mod tests {
                 size: 1024000,
                 size_downloaded: 1024000,
                 size_uploaded: 1024000,
-                speed_download: 0,
-                speed_upload: 0,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             },
             Task {
                 id: String::from("uuid01"),
@@ -567,10 +1071,9 @@ mod tests {
                 size: 1024000,
                 size_downloaded: 1024000,
                 size_uploaded: 1024000,
-                speed_download: 0,
-                speed_upload: 0,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             },
             Task {
                 id: String::from("uuid01"),
@@ -579,10 +1082,9 @@ mod tests {
                 size: 1024000,
                 size_downloaded: 1024000,
                 size_uploaded: 1024000,
-                speed_download: 0,
-                speed_upload: 0,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             }
         ];
         app
@@ -793,4 +1295,398 @@ mod tests {
         app.previous_page(3);
         assert_eq!(app.state.selected(), Some(0));
     }
+
+
+    fn get_test_backend() -> MemoryBackend {
+        MemoryBackend::new(vec![
+            make_task("dbid_1", "debian.iso", "downloading"),
+            make_task("dbid_2", "slackware.iso", "paused"),
+            make_task("dbid_3", "archbang.iso", "seeding")
+        ])
+    }
+
+    fn press(app: &mut App, backend: &mut MemoryBackend, keys: &[KeyCode]) {
+        for key in keys {
+            app.on_key(backend, *key, 10);
+            while app.activity == Activity::Refresh
+                    || app.activity == Activity::Submit {
+                app.process(backend);
+            }
+        }
+    }
+
+    fn type_text(app: &mut App, backend: &mut MemoryBackend, text: &str) {
+        let keys: Vec<KeyCode> = text.chars().map(KeyCode::Char).collect();
+        press(app, backend, &keys);
+    }
+
+    #[test]
+    fn initial_refresh_loads_tasks() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        assert!(app.activity == Activity::Refresh);
+
+        app.process(&mut backend);
+        assert!(app.activity == Activity::Idle);
+        assert_eq!(app.tasks.len(), 3);
+        assert_eq!(app.state.selected(), Some(0));
+    }
+
+    #[test]
+    fn add_task_with_keyboard() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('a')]);
+        assert!(app.ui.ask_for_task_url);
+
+        type_text(&mut app, &mut backend, "magnet:?xt=1");
+        press(&mut app, &mut backend, &[KeyCode::Backspace]);
+        type_text(&mut app, &mut backend, "2");
+        press(&mut app, &mut backend, &[KeyCode::Enter]);
+
+        assert!(!app.ui.ask_for_task_url);
+        assert_eq!(app.input, "");
+        assert_eq!(app.tasks.len(), 4);
+        assert_eq!(backend.tasks[3].title, "magnet:?xt=2");
+    }
+
+    #[test]
+    fn cancel_add_task() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('A')]);
+        type_text(&mut app, &mut backend, "http://foo/");
+        press(&mut app, &mut backend, &[KeyCode::Esc]);
+
+        assert!(!app.ui.ask_for_task_url);
+        assert_eq!(app.input, "");
+        assert_eq!(backend.tasks.len(), 3);
+    }
+
+    #[test]
+    fn adding_a_duplicate_asks_first() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('a')]);
+        type_text(&mut app, &mut backend, "http://foo/a.iso");
+        press(&mut app, &mut backend, &[KeyCode::Enter]);
+        assert_eq!(backend.tasks.len(), 4);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('a')]);
+        type_text(&mut app, &mut backend, "http://foo/a.iso#x");
+        press(&mut app, &mut backend, &[KeyCode::Enter]);
+        assert_eq!(app.ui.duplicate_of.as_deref(), Some("http://foo/a.iso"));
+
+        press(&mut app, &mut backend, &[KeyCode::Enter]);
+        assert_eq!(app.ui.duplicate_of, None);
+        assert_eq!(app.input, "");
+        assert_eq!(backend.tasks.len(), 4);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('a')]);
+        type_text(&mut app, &mut backend, "http://foo/a.iso");
+        press(&mut app, &mut backend, &[KeyCode::Enter, KeyCode::Char('h'), KeyCode::Enter]);
+        assert_eq!(app.ui.duplicate_of, None);
+        assert_eq!(backend.tasks.len(), 5);
+    }
+
+    #[test]
+    fn add_dialog_previews_torrents() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('a')]);
+        type_text(&mut app, &mut backend, "magnet:?xt=urn:btih:c1599dd742faa9d633b4fb96d49c5abb02798eef");
+        assert_eq!(app.preview.as_ref().unwrap().as_ref().unwrap().info_hash,
+                   "c1599dd742faa9d633b4fb96d49c5abb02798eef");
+        press(&mut app, &mut backend, &[KeyCode::Backspace]);
+        assert!(app.preview.as_ref().unwrap().is_err());
+        press(&mut app, &mut backend, &[KeyCode::Esc]);
+        assert!(app.preview.is_none());
+
+        press(&mut app, &mut backend, &[KeyCode::Char('a')]);
+        type_text(&mut app, &mut backend, "http://foo/");
+        assert!(app.preview.is_none());
+    }
+
+    #[test]
+    fn add_dialog_uploads_torrent_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.torrent");
+        fs::write(&path, "d4:infod6:lengthi5e4:name5:a.isoee").unwrap();
+
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('a')]);
+        type_text(&mut app, &mut backend, path.to_str().unwrap());
+        assert_eq!(app.preview.as_ref().unwrap().as_ref().unwrap().name, "a.iso");
+        press(&mut app, &mut backend, &[KeyCode::Enter]);
+
+        assert_eq!(app.input, "");
+        assert!(app.preview.is_none());
+        assert_eq!(backend.tasks.last().unwrap().title, "a.torrent");
+    }
+
+    #[test]
+    fn apply_speed_limit_preset() {
+        let mut backend = get_test_backend();
+        backend.limits.http_download = 300;
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('l')]);
+        assert!(app.ui.show_limits);
+        assert_eq!(app.limits.http_download, 300);
+
+        press(&mut app, &mut backend, &[KeyCode::Up, KeyCode::Enter]);
+        assert!(!app.ui.show_limits);
+        assert_eq!(backend.limits, Limits { bt_download: 500, bt_upload: 100,
+                                            http_download: 500, ftp_download: 500 });
+
+        press(&mut app, &mut backend, &[KeyCode::Char('L'), KeyCode::Esc]);
+        assert!(!app.ui.show_limits);
+        assert_eq!(backend.limits.bt_download, 500);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('l'), KeyCode::Enter]);
+        assert_eq!(backend.limits, Limits::default());
+    }
+
+    #[test]
+    fn failing_add_shows_error() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('a'), KeyCode::Enter]);
+        assert_eq!(app.error, "Invalid parameter");
+        assert_eq!(backend.tasks.len(), 3);
+    }
+
+    #[test]
+    fn delete_selected_task_after_confirmation() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Down, KeyCode::Char('d')]);
+        assert!(app.ui.confirm_delete);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('h'), KeyCode::Enter]);
+        assert!(!app.ui.confirm_delete);
+        assert_eq!(app.tasks.len(), 2);
+        assert!(backend.tasks.iter().all(|t| t.id != "dbid_2"));
+    }
+
+    #[test]
+    fn delete_defaults_to_no() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('d'), KeyCode::Enter]);
+        assert!(!app.ui.confirm_delete);
+        assert_eq!(backend.tasks.len(), 3);
+    }
+
+    #[test]
+    fn delete_last_task_moves_selection() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::End, KeyCode::Char('d'),
+                                        KeyCode::Char('l'), KeyCode::Enter]);
+        assert_eq!(app.tasks.len(), 2);
+        assert_eq!(app.state.selected(), Some(0));
+    }
+
+    #[test]
+    fn pause_and_resume_selected_task() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('p')]);
+        assert_eq!(backend.tasks[0].status, "paused");
+        assert_eq!(app.tasks[0].status, "paused");
+
+        press(&mut app, &mut backend, &[KeyCode::Char('P')]);
+        assert_eq!(backend.tasks[0].status, "downloading");
+        assert_eq!(app.tasks[0].status, "downloading");
+    }
+
+    #[test]
+    fn show_details_fetches_task_info() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        backend.tasks[0].size_downloaded = 1024000;
+        press(&mut app, &mut backend, &[KeyCode::Char('i')]);
+        assert!(app.ui.show_details);
+        assert_eq!(app.tasks[0].size_downloaded, 1024000);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('x')]);
+        assert!(!app.ui.show_details);
+    }
+
+    #[test]
+    fn help_is_closed_by_any_key() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('?')]);
+        assert!(app.ui.show_help);
+        press(&mut app, &mut backend, &[KeyCode::Char('q')]);
+        assert!(!app.ui.show_help);
+        assert!(app.activity == Activity::Idle);
+    }
+
+    #[test]
+    fn quit_logs_out() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('q')]);
+        assert!(app.activity == Activity::Quit);
+
+        app.quit(&mut backend).unwrap();
+        assert!(backend.logged_out);
+    }
+
+    fn connect_test_backend(i: usize) -> Result<MemoryBackend, Box<dyn error::Error>> {
+        match i {
+            1 => Ok(MemoryBackend::new(vec![
+                make_task("dbid_7", "freebsd.iso", "waiting")
+            ])),
+            _ => Err(Box::new(io::Error::other("Connection refused")))
+        }
+    }
+
+    fn get_profile_app() -> App {
+        App::with_profiles(vec![String::from("home"), String::from("office"),
+                                String::from("garage")], 0)
+    }
+
+    #[test]
+    fn auto_resume() {
+        let mut backend = get_test_backend();
+        backend.tasks[1].status = String::from("error");
+        let mut app = get_profile_app();
+        app.process(&mut backend);
+        assert_eq!(backend.tasks[1].status, "error");
+
+        app.auto_resume = vec![true, false, false];
+        app.activity = Activity::Refresh;
+        app.process(&mut backend);
+        assert_eq!(backend.tasks[1].status, "downloading");
+    }
+
+    #[test]
+    fn auto_resume_while_idle() {
+        let mut backend = get_test_backend();
+        let mut app = get_profile_app();
+        app.auto_resume = vec![true, false, false];
+        app.process(&mut backend);
+        backend.tasks[1].status = String::from("error");
+
+        /* no key pressed for a while */
+        app.on_event(&mut backend, None, 10);
+        app.process(&mut backend);
+        assert_eq!(backend.tasks[1].status, "downloading");
+
+        /* but not while asking whether to delete the selected task */
+        backend.tasks[1].status = String::from("error");
+        app.ui.confirm_delete = true;
+        app.on_event(&mut backend, None, 10);
+        app.process(&mut backend);
+        assert_eq!(backend.tasks[1].status, "error");
+    }
+
+    #[test]
+    fn auto_resume_with_all_profiles() {
+        let mut backend = MemoryBackend::new(vec![
+            make_task("home/dbid_1", "debian.iso", "error"),
+            make_task("office/dbid_1", "slackware.iso", "error")
+        ]);
+        let mut app = get_profile_app();
+        app.aggregate = true;
+        app.auto_resume = vec![false, true, false];
+        app.process(&mut backend);
+
+        let statuses: Vec<&str> = backend.tasks.iter().map(|t| t.status.as_str()).collect();
+        assert_eq!(statuses, ["error", "downloading"]);
+    }
+
+    #[test]
+    fn switch_profile() {
+        let mut backend = get_test_backend();
+        let mut app = get_profile_app();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('s')]);
+        assert!(app.ui.select_profile);
+        assert_eq!(app.ui.profile_selected, 0);
+
+        press(&mut app, &mut backend, &[KeyCode::Down, KeyCode::Enter]);
+        assert!(!app.ui.select_profile);
+        assert!(app.activity == Activity::Switch);
+
+        app.switch_profile(&mut backend, &mut connect_test_backend);
+        app.process(&mut backend);
+        assert_eq!(app.profile, 1);
+        assert_eq!(app.tasks.len(), 1);
+        assert_eq!(app.tasks[0].title, "freebsd.iso");
+    }
+
+    #[test]
+    fn switch_profile_failed_login() {
+        let mut backend = get_test_backend();
+        let mut app = get_profile_app();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('S'), KeyCode::Up,
+                                        KeyCode::Enter]);
+        app.switch_profile(&mut backend, &mut connect_test_backend);
+        assert_eq!(app.error, "Login to garage failed: Connection refused");
+        assert!(app.activity == Activity::Idle);
+        assert_eq!(app.profile, 0);
+        assert!(!backend.logged_out);
+        assert_eq!(app.tasks.len(), 3);
+    }
+
+    #[test]
+    fn selecting_active_profile_does_nothing() {
+        let mut backend = get_test_backend();
+        let mut app = get_profile_app();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('s'), KeyCode::Enter]);
+        assert!(app.activity == Activity::Idle);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('s'), KeyCode::Esc]);
+        assert!(!app.ui.select_profile);
+        assert!(app.activity == Activity::Idle);
+    }
+
+    #[test]
+    fn switch_profile_needs_profiles() {
+        let mut backend = get_test_backend();
+        let mut app = App::new();
+        app.process(&mut backend);
+
+        press(&mut app, &mut backend, &[KeyCode::Char('s')]);
+        assert!(!app.ui.select_profile);
+        assert_eq!(app.error, "There are no other profiles configured");
+    }
 }
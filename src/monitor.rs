@@ -0,0 +1,141 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{error, thread, time::{Duration, Instant}};
+
+use synodl::syno::Task;
+
+use crate::{backend::{is_session_error, DownloadBackend}, hooks::{Event, Hooks},
+            retry::{self, Retry}, stats::Recorder};
+
+/* seconds between two looks at the task list */
+pub const INTERVAL: u64 = 30;
+
+/* keeps looking at the task list and runs hooks for what changed */
+pub struct Monitor<D, F> {
+    client: D,
+    connect: F,
+    hooks: Hooks,
+    profile: usize,
+    pub recorder: Recorder,
+    /* resumes failed tasks with --auto-resume */
+    pub retry: Option<Retry>
+}
+
+impl<D, F> Monitor<D, F>
+        where D: DownloadBackend,
+              F: FnMut() -> Result<D, Box<dyn error::Error>> {
+    pub fn new(client: D, connect: F, hooks: Hooks, profile: usize) -> Monitor<D, F> {
+        Monitor { client, connect, hooks, profile, recorder: Recorder::default(), retry: None }
+    }
+
+    fn list(&mut self) -> Result<Vec<Task>, Box<dyn error::Error>> {
+        match self.client.list() {
+            Err(e) if is_session_error(e.as_ref()) => {
+                self.client = (self.connect)()?;
+                self.client.list()
+            },
+            res => res
+        }
+    }
+
+    /* one look at the task list */
+    fn poll(&mut self) -> Result<Vec<(Event, Task)>, Box<dyn error::Error>> {
+        let tasks = self.list()?;
+        if let Err(e) = self.recorder.record(&tasks) {
+            eprintln!("{}", e);
+        }
+        for (nas, e) in self.client.failures() {
+            eprintln!("Cannot list tasks of {}: {}", nas, e);
+        }
+        let changes = self.hooks.update_partial(&tasks, self.client.failures());
+        for e in self.hooks.run(self.profile, &changes) {
+            eprintln!("{}", e);
+        }
+        if let Some(retry) = &mut self.retry {
+            for res in retry.run(&mut self.client, &tasks, Instant::now()) {
+                match res {
+                    Ok(done) => println!("{}", done),
+                    Err(e) => eprintln!("{}", e)
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    /* runs until interrupted, a failed look is tried again next time */
+    pub fn run(&mut self, interval: Duration) -> Result<(), Box<dyn error::Error>> {
+        let tasks = self.list()?;
+        self.hooks.update(&tasks);
+        println!("Watching {} tasks, press Ctrl-C to stop", tasks.len());
+        if self.retry.is_some() {
+            println!("Failed tasks are resumed up to {} times", retry::MAX_TRIES);
+        }
+
+        loop {
+            thread::sleep(interval);
+            match self.poll() {
+                Ok(changes) => {
+                    for (event, task) in changes {
+                        println!("{:<9} {}", event.name(), task.title);
+                    }
+                },
+                Err(e) => eprintln!("Cannot list tasks: {}", e)
+            }
+        }
+    }
+
+    pub fn logout(&mut self) -> Result<(), Box<dyn error::Error>> {
+        self.client.logout()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::{make_task, no_login, MemoryBackend};
+
+    #[test]
+    fn poll_reports_changes() {
+        let backend = MemoryBackend::new(vec![make_task("dbid_1", "dbid_1.iso", "downloading")]);
+        let mut monitor = Monitor::new(backend, no_login, Hooks::default(), 0);
+        assert!(monitor.poll().unwrap().is_empty());
+
+        monitor.client.tasks[0].status = String::from("seeding");
+        monitor.client.add("magnet:?xt=1").unwrap();
+        let changes: Vec<(Event, String)> = monitor.poll().unwrap().into_iter()
+            .map(|(e, t)| (e, t.id))
+            .collect();
+        assert_eq!(changes, [(Event::Finished, String::from("dbid_1")),
+                             (Event::Added, String::from("dbid_2"))]);
+        assert!(monitor.poll().unwrap().is_empty());
+    }
+
+    #[test]
+    fn poll_resumes_failed_tasks() {
+        let backend = MemoryBackend::new(vec![make_task("dbid_1", "dbid_1.iso", "error")]);
+        let mut monitor = Monitor::new(backend, no_login, Hooks::default(), 0);
+        monitor.poll().unwrap();
+        assert_eq!(monitor.client.tasks[0].status, "error");
+
+        monitor.retry = Some(Retry::default());
+        monitor.poll().unwrap();
+        assert_eq!(monitor.client.tasks[0].status, "downloading");
+    }
+}
@@ -0,0 +1,125 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+use std::fs;
+
+use crate::{
+    handler::local_file,
+    torrent::Torrent,
+    ui::util::size_text
+};
+
+/* what a magnet link or a local torrent file would add, without asking the
+   NAS; None for anything else, which only DownloadStation can look at */
+pub fn preview(uri: &str) -> Option<Result<Torrent, String>> {
+    let uri = uri.trim();
+    if uri.starts_with("magnet:") {
+        return Some(Torrent::from_magnet(uri)
+            .ok_or_else(|| String::from("Not a BitTorrent magnet link")));
+    }
+
+    let path = local_file(uri)?;
+    Some(fs::read(&path)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))
+        .and_then(|data| Torrent::parse(&data)))
+}
+
+pub fn describe(torrent: &Torrent) -> Vec<String> {
+    let unknown = || String::from("unknown");
+    let mut lines = vec![
+        format!("Name:  {}", match torrent.name.as_str() {
+            "" => unknown(),
+            name => String::from(name)
+        }),
+        format!("Size:  {}", match torrent.length {
+            0 if torrent.files.is_empty() => unknown(),
+            n => size_text(n)
+        }),
+        format!("Hash:  {}", torrent.info_hash)
+    ];
+
+    match torrent.files.len() {
+        0 => lines.push(String::from("Files: known once the download starts")),
+        n => lines.push(format!("Files: {}", n))
+    }
+    lines.extend(torrent.files.iter()
+        .map(|f| format!("  {} ({})", f.path, size_text(f.length))));
+    lines
+}
+
+/* prints what each URL would add, returns whether all could be read */
+pub fn preview_urls(urls: &[String]) -> bool {
+    let mut ok = true;
+    for (i, url) in urls.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{}", url);
+        match preview(url) {
+            Some(Ok(torrent)) => describe(&torrent).iter().for_each(|l| println!("{}", l)),
+            Some(Err(e)) => {
+                println!("FAILED  {}", e);
+                ok = false;
+            },
+            None => println!("Nothing to preview, only magnet links and torrent files can be \
+                              looked at before adding them")
+        }
+    }
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preview_magnet_links() {
+        let magnet = "magnet:?xt=urn:btih:c1599dd742faa9d633b4fb96d49c5abb02798eef&dn=a.iso";
+        assert_eq!(describe(&preview(magnet).unwrap().unwrap()), [
+            "Name:  a.iso",
+            "Size:  unknown",
+            "Hash:  c1599dd742faa9d633b4fb96d49c5abb02798eef",
+            "Files: known once the download starts"
+        ]);
+        assert_eq!(preview("magnet:?dn=a.iso").unwrap().unwrap_err(),
+                   "Not a BitTorrent magnet link");
+        assert!(preview("https://example.com/a.iso").is_none());
+    }
+
+    #[test]
+    fn preview_torrent_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dir.torrent");
+        fs::write(&path, &b"d4:infod5:filesld6:lengthi3e4:pathl1:aeed6:lengthi4000e\
+                            4:pathl3:sub1:beee4:name3:diree"[..]).unwrap();
+
+        let torrent = preview(path.to_str().unwrap()).unwrap().unwrap();
+        assert_eq!(describe(&torrent), [
+            "Name:  dir",
+            "Size:  4.00 KB",
+            "Hash:  00a50843205df72bc19862b2b56d3c65020d29c1",
+            "Files: 2",
+            "  a (3 B)",
+            "  sub/b (4.00 KB)"
+        ]);
+
+        fs::write(&path, "<html>").unwrap();
+        assert!(preview(path.to_str().unwrap()).unwrap().unwrap_err()
+                .starts_with("Not a torrent file"));
+    }
+}
@@ -0,0 +1,213 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use synodl::syno::Task;
+
+use crate::backend::DownloadBackend;
+
+/* how long to wait after the first attempt, doubled after each further one */
+const FIRST_DELAY: Duration = Duration::from_secs(60);
+const MAX_DELAY: Duration = Duration::from_secs(6 * 3600);
+/* attempts per task before giving up */
+pub const MAX_TRIES: u32 = 5;
+/* a task that stays out of error this long starts over with its attempts */
+const RECOVERED: Duration = Duration::from_secs(3600);
+
+/* errors that resuming does not fix, parts of what DSM reports as error_detail */
+const PERMANENT: [&str; 14] = ["broken_link", "not_exist", "deny", "denied", "disk_full",
+                               "diskfull", "quota", "exceed_max", "too_long", "duplicate",
+                               "premium", "not_supported", "encryption", "private_video"];
+
+pub fn is_permanent(detail: &str) -> bool {
+    PERMANENT.iter().any(|p| detail.contains(p))
+}
+
+fn delay(tries: u32) -> Duration {
+    let factor = 2u32.saturating_pow(tries.saturating_sub(1));
+    FIRST_DELAY.saturating_mul(factor).min(MAX_DELAY)
+}
+
+struct Attempts {
+    tries: u32,
+    /* when to try again */
+    next: Instant,
+    /* when the task was last seen failing */
+    failed: Instant,
+    /* whether giving up was already reported */
+    reported: bool
+}
+
+/* resumes failed tasks, waiting longer after each attempt */
+pub struct Retry {
+    max_tries: u32,
+    tasks: HashMap<String, Attempts>
+}
+
+impl Default for Retry {
+    fn default() -> Retry {
+        Retry::new(MAX_TRIES)
+    }
+}
+
+impl Retry {
+    pub fn new(max_tries: u32) -> Retry {
+        Retry { max_tries, tasks: HashMap::new() }
+    }
+
+    /* looks at the task list, resumes what is due; returns what was done or
+       not, failures as errors */
+    pub fn run<D: DownloadBackend>(&mut self, client: &mut D, tasks: &[Task], now: Instant)
+            -> Vec<Result<String, String>> {
+        /* forget about tasks that are gone or did fine for a while */
+        self.tasks.retain(|id, a| {
+            let failing = tasks.iter().any(|t| &t.id == id && t.is_failed());
+            failing || now.saturating_duration_since(a.failed) < RECOVERED
+        });
+
+        let mut report = vec![];
+        for task in tasks.iter().filter(|t| t.is_failed()) {
+            let permanent = is_permanent(&task.error_detail);
+            let a = self.tasks.entry(task.id.clone()).or_insert(Attempts {
+                tries: 0, next: now, failed: now, reported: false
+            });
+            a.failed = now;
+
+            if permanent || a.tries >= self.max_tries {
+                if !a.reported {
+                    a.reported = true;
+                    report.push(Ok(match permanent {
+                        true => format!("Not resuming {}: {}", task.title, task.error_detail),
+                        false => format!("Giving up on {} after {} attempts", task.title,
+                                         a.tries)
+                    }));
+                }
+                continue;
+            }
+            if now < a.next {
+                continue;
+            }
+
+            a.tries += 1;
+            a.next = now + delay(a.tries);
+            report.push(match client.resume(task) {
+                Ok(()) => Ok(format!("Resumed {} (attempt {} of {})", task.title, a.tries,
+                                     self.max_tries)),
+                Err(e) => Err(format!("Cannot resume {}: {}", task.title, e))
+            });
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::MemoryBackend;
+
+    fn failed(id: &str, detail: &str) -> Task {
+        Task { id: String::from(id), title: format!("{}.iso", id), status: String::from("error"),
+               error_detail: String::from(detail), ..Task::default() }
+    }
+
+    /* resumes what is due, then lets the tasks fail again */
+    fn step(retry: &mut Retry, backend: &mut MemoryBackend, now: Instant)
+            -> Vec<Result<String, String>> {
+        let tasks = backend.tasks.clone();
+        let report = retry.run(backend, &tasks, now);
+        for t in backend.tasks.iter_mut() {
+            t.status = String::from("error");
+        }
+        report
+    }
+
+    #[test]
+    fn permanent_errors() {
+        assert!(is_permanent("disk_full"));
+        assert!(is_permanent("broken_link"));
+        assert!(is_permanent("exceed_max_temp_size"));
+        assert!(!is_permanent("timeout"));
+        assert!(!is_permanent(""));
+    }
+
+    #[test]
+    fn backoff() {
+        assert_eq!(delay(1), Duration::from_secs(60));
+        assert_eq!(delay(3), Duration::from_secs(240));
+        assert_eq!(delay(30), MAX_DELAY);
+    }
+
+    #[test]
+    fn retries_with_backoff_until_max() {
+        let mut backend = MemoryBackend::new(vec![failed("dbid_1", "timeout")]);
+        let mut retry = Retry::new(3);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(step(&mut retry, &mut backend, at(0)),
+                   [Ok(String::from("Resumed dbid_1.iso (attempt 1 of 3)"))]);
+        assert!(step(&mut retry, &mut backend, at(59)).is_empty());
+        assert_eq!(step(&mut retry, &mut backend, at(60)).len(), 1);
+        /* the second attempt waits twice as long */
+        assert!(step(&mut retry, &mut backend, at(179)).is_empty());
+        assert_eq!(step(&mut retry, &mut backend, at(180)),
+                   [Ok(String::from("Resumed dbid_1.iso (attempt 3 of 3)"))]);
+        assert_eq!(step(&mut retry, &mut backend, at(10000)),
+                   [Ok(String::from("Giving up on dbid_1.iso after 3 attempts"))]);
+        assert!(step(&mut retry, &mut backend, at(20000)).is_empty());
+        assert_eq!(backend.tasks[0].status, "error");
+    }
+
+    #[test]
+    fn skips_permanent_errors() {
+        let mut backend = MemoryBackend::new(vec![failed("dbid_1", "disk_full")]);
+        let mut retry = Retry::default();
+        let now = Instant::now();
+
+        assert_eq!(step(&mut retry, &mut backend, now),
+                   [Ok(String::from("Not resuming dbid_1.iso: disk_full"))]);
+        assert!(step(&mut retry, &mut backend, now + RECOVERED).is_empty());
+    }
+
+    #[test]
+    fn recovered_tasks_start_over() {
+        let mut backend = MemoryBackend::new(vec![failed("dbid_1", "")]);
+        let mut retry = Retry::new(1);
+        let now = Instant::now();
+
+        let tasks = backend.tasks.clone();
+        assert_eq!(retry.run(&mut backend, &tasks, now).len(), 1);
+        let tasks = backend.tasks.clone();
+        assert!(!tasks[0].is_failed());
+        assert!(retry.run(&mut backend, &tasks, now + RECOVERED).is_empty());
+
+        let tasks = [failed("dbid_1", "")];
+        assert_eq!(retry.run(&mut backend, &tasks, now + RECOVERED),
+                   [Ok(String::from("Resumed dbid_1.iso (attempt 1 of 1)"))]);
+    }
+
+    #[test]
+    fn failed_resume() {
+        let mut backend = MemoryBackend::new(vec![]);
+        let mut retry = Retry::default();
+        let report = retry.run(&mut backend, &[failed("dbid_9", "")], Instant::now());
+        assert_eq!(report, [Err(String::from("Cannot resume dbid_9.iso: Invalid task id"))]);
+    }
+}
@@ -0,0 +1,470 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{
+    collections::HashMap, error,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH}
+};
+
+use chrono::{DateTime, Days, Local, TimeZone, Timelike};
+use synodl::syno::Task;
+
+use crate::{backend::owner, ui::util::size_text};
+
+/* how often the task list is written down at most */
+const EVERY: Duration = Duration::from_secs(60);
+/* how many tasks `synodl stats` shows */
+const TOP: usize = 10;
+const HEADER: &str = "time,profile,id,status,downloaded,uploaded,title";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Period {
+    Day,
+    Week,
+    Month
+}
+
+/* what a task looked like at some point */
+#[derive(Clone, Debug, PartialEq)]
+struct Sample {
+    time: i64,
+    profile: String,
+    id: String,
+    status: String,
+    downloaded: u64,
+    uploaded: u64,
+    title: String
+}
+
+impl Sample {
+    fn new(time: i64, profile: &str, task: &Task) -> Sample {
+        let (profile, id) = match owner(task) {
+            Some(nas) => (nas, &task.id[nas.len() + 1..]),
+            None => (profile, task.id.as_str())
+        };
+        Sample {
+            time,
+            profile: String::from(profile),
+            id: String::from(id),
+            status: task.status.clone(),
+            downloaded: task.size_downloaded,
+            uploaded: task.size_uploaded,
+            title: task.title.clone()
+        }
+    }
+
+    fn finished(&self) -> bool {
+        matches!(self.status.as_str(), "finished" | "seeding")
+    }
+
+    /* the same transfer as the other, apart from the time */
+    fn same(&self, other: &Sample) -> bool {
+        (&self.status, self.downloaded, self.uploaded)
+            == (&other.status, other.downloaded, other.uploaded)
+    }
+
+    fn line(&self) -> String {
+        format!("{},{},{},{},{},{},{}", self.time, quote(&self.profile), quote(&self.id),
+                quote(&self.status), self.downloaded, self.uploaded, quote(&self.title))
+    }
+
+    fn parse(line: &str) -> Option<Sample> {
+        let fields = split(line);
+        match fields.as_slice() {
+            [time, profile, id, status, downloaded, uploaded, title] => Some(Sample {
+                time: time.parse().ok()?,
+                profile: profile.clone(),
+                id: id.clone(),
+                status: status.clone(),
+                downloaded: downloaded.parse().ok()?,
+                uploaded: uploaded.parse().ok()?,
+                title: title.clone()
+            }),
+            _ => None
+        }
+    }
+}
+
+fn quote(s: &str) -> String {
+    match s.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"").replace(['\n', '\r'], " ")),
+        false => String::from(s)
+    }
+}
+
+fn split(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c)
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/* where the samples are kept */
+pub fn stats_file() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("synodl").join("stats.csv"))
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/* writes the task list to the stats file every now and then, but only the
+   tasks that changed since they were written last */
+#[derive(Default)]
+pub struct Recorder {
+    /* nothing is recorded without a file */
+    path: Option<PathBuf>,
+    /* the profile of tasks that do not carry one in their ID */
+    pub profile: String,
+    last: Option<Instant>,
+    written: HashMap<(String, String), Sample>
+}
+
+impl Recorder {
+    pub fn new(path: Option<PathBuf>, profile: &str) -> Recorder {
+        Recorder { path, profile: String::from(profile), ..Recorder::default() }
+    }
+
+    fn append(path: &Path, samples: &[Sample]) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", HEADER)?;
+        }
+        for s in samples {
+            writeln!(file, "{}", s.line())?;
+        }
+        Ok(())
+    }
+
+    /* stops recording after the first failure, reporting it once is enough */
+    pub fn record(&mut self, tasks: &[Task]) -> Result<(), Box<dyn error::Error>> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return Ok(())
+        };
+        if matches!(self.last, Some(t) if t.elapsed() < EVERY) {
+            return Ok(());
+        }
+        self.last = Some(Instant::now());
+
+        let time = now();
+        let samples: Vec<Sample> = tasks.iter()
+            .map(|t| Sample::new(time, &self.profile, t))
+            .filter(|s| match self.written.get(&(s.profile.clone(), s.id.clone())) {
+                Some(w) => !w.same(s),
+                None => true
+            })
+            .collect();
+        if samples.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = Recorder::append(&path, &samples) {
+            self.path = None;
+            return Err(format!("Cannot record statistics in {}: {}", path.display(), e).into());
+        }
+        for s in samples {
+            self.written.insert((s.profile.clone(), s.id.clone()), s);
+        }
+        Ok(())
+    }
+}
+
+/* traffic and finished downloads in part of the period */
+#[derive(Debug, Default, PartialEq)]
+struct Bucket {
+    label: String,
+    start: i64,
+    downloaded: u64,
+    uploaded: u64,
+    finished: usize
+}
+
+#[derive(Debug, PartialEq)]
+struct Report {
+    buckets: Vec<Bucket>,
+    /* title, downloaded, uploaded */
+    top: Vec<(String, u64, u64)>
+}
+
+/* hours of the last day or days of the last week or month, oldest first */
+fn buckets<Tz: TimeZone>(period: Period, now: &DateTime<Tz>) -> Vec<Bucket> {
+    let tz = now.timezone();
+    match period {
+        Period::Day => {
+            let hour = now.with_minute(0).and_then(|t| t.with_second(0))
+                .and_then(|t| t.with_nanosecond(0))
+                .unwrap_or_else(|| now.clone());
+            (0..24).rev().map(|i| {
+                let start = hour.timestamp() - i * 3600;
+                let label = tz.timestamp_opt(start, 0).single()
+                    .map(|t| t.naive_local().format("%H:00").to_string())
+                    .unwrap_or_default();
+                Bucket { label, start, ..Bucket::default() }
+            }).collect()
+        },
+        Period::Week | Period::Month => {
+            let days = match period {
+                Period::Week => 7,
+                _ => 30
+            };
+            let today = now.date_naive();
+            (0..days).rev().filter_map(|i| {
+                let day = today.checked_sub_days(Days::new(i))?;
+                let start = tz.from_local_datetime(&day.and_hms_opt(0, 0, 0)?).earliest()?;
+                Some(Bucket { label: day.format("%a %Y-%m-%d").to_string(),
+                              start: start.timestamp(), ..Bucket::default() })
+            }).collect()
+        }
+    }
+}
+
+/* what happened between successive samples of the same task, counted in the
+   part of the period the later sample falls in */
+fn report<Tz: TimeZone>(samples: &[Sample], period: Period, now: &DateTime<Tz>) -> Report {
+    let mut buckets = buckets(period, now);
+    let start = buckets.first().map(|b| b.start).unwrap_or(0);
+
+    let mut tasks: HashMap<(&str, &str), Vec<&Sample>> = HashMap::new();
+    for s in samples {
+        tasks.entry((&s.profile, &s.id)).or_default().push(s);
+    }
+
+    let mut top = vec![];
+    for (_, mut list) in tasks {
+        list.sort_by_key(|s| s.time);
+        let (mut down, mut up) = (0, 0);
+        for pair in list.windows(2) {
+            let (prev, cur) = (pair[0], pair[1]);
+            if cur.time < start {
+                continue;
+            }
+            let bucket = match buckets.iter_mut().rev().find(|b| b.start <= cur.time) {
+                Some(b) => b,
+                None => continue
+            };
+            let d = cur.downloaded.saturating_sub(prev.downloaded);
+            let u = cur.uploaded.saturating_sub(prev.uploaded);
+            bucket.downloaded += d;
+            bucket.uploaded += u;
+            if cur.finished() && !prev.finished() {
+                bucket.finished += 1;
+            }
+            down += d;
+            up += u;
+        }
+        if down + up > 0 {
+            let title = list.last().map(|s| s.title.clone()).unwrap_or_default();
+            top.push((title, down, up));
+        }
+    }
+
+    top.sort_by(|a, b| (b.1 + b.2).cmp(&(a.1 + a.2)).then_with(|| a.0.cmp(&b.0)));
+    top.truncate(TOP);
+    Report { buckets, top }
+}
+
+fn print_report(report: &Report, period: Period) {
+    let (title, unit) = match period {
+        Period::Day => ("Traffic in the last 24 hours", "Hour"),
+        Period::Week => ("Traffic in the last 7 days", "Day"),
+        Period::Month => ("Traffic in the last 30 days", "Day")
+    };
+    println!("{}\n", title);
+    println!("{:<16} {:>12} {:>12} {:>9}", unit, "Downloaded", "Uploaded", "Finished");
+    for b in &report.buckets {
+        println!("{:<16} {:>12} {:>12} {:>9}", b.label, size_text(b.downloaded),
+                 size_text(b.uploaded), b.finished);
+    }
+    println!("{:<16} {:>12} {:>12} {:>9}", "Total",
+             size_text(report.buckets.iter().map(|b| b.downloaded).sum()),
+             size_text(report.buckets.iter().map(|b| b.uploaded).sum()),
+             report.buckets.iter().map(|b| b.finished).sum::<usize>());
+
+    if !report.top.is_empty() {
+        println!("\nTop tasks\n");
+        println!("{:>12} {:>12}  Title", "Downloaded", "Uploaded");
+        for (title, down, up) in &report.top {
+            println!("{:>12} {:>12}  {}", size_text(*down), size_text(*up), title);
+        }
+    }
+}
+
+fn load(path: &Path) -> io::Result<Vec<Sample>> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e)
+    };
+    let mut samples = vec![];
+    for line in BufReader::new(file).lines() {
+        if let Some(s) = Sample::parse(&line?) {
+            samples.push(s);
+        }
+    }
+    Ok(samples)
+}
+
+/* synodl stats */
+pub fn show_stats(period: Period) -> Result<(), Box<dyn error::Error>> {
+    let path = stats_file().ok_or("Cannot find the data directory")?;
+    let samples = load(&path)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    if samples.is_empty() {
+        println!("Nothing recorded yet, statistics are collected while synodl shows the \
+                  task list or runs as monitor or exporter");
+        return Ok(());
+    }
+    print_report(&report(&samples, period, &Local::now()), period);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::backend::memory::make_task;
+
+    fn sampled(id: &str, status: &str, downloaded: u64) -> Task {
+        Task { size_downloaded: downloaded, ..make_task(id, &format!("{}, the movie", id), status) }
+    }
+
+    fn sample(time: i64, id: &str, status: &str, downloaded: u64, uploaded: u64) -> Sample {
+        Sample { time, profile: String::from("home"), id: String::from(id),
+                 status: String::from(status), downloaded, uploaded,
+                 title: format!("{}.iso", id) }
+    }
+
+    #[test]
+    fn csv_lines() {
+        let s = Sample::new(1700000000, "home", &sampled("dbid_1", "waiting", 5));
+        assert_eq!(s.line(), "1700000000,home,dbid_1,waiting,5,0,\"dbid_1, the movie\"");
+        assert_eq!(Sample::parse(&s.line()), Some(s));
+
+        assert_eq!(split("a,\"b \"\"c\"\", d\",e"), ["a", "b \"c\", d", "e"]);
+        assert_eq!(Sample::parse(HEADER), None);
+    }
+
+    #[test]
+    fn tagged_ids() {
+        let s = Sample::new(1, "home", &sampled("office/dbid_7", "waiting", 0));
+        assert_eq!((s.profile.as_str(), s.id.as_str()), ("office", "dbid_7"));
+    }
+
+    #[test]
+    fn record_changes_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data/stats.csv");
+        let mut recorder = Recorder::new(Some(path.clone()), "home");
+
+        let mut tasks = vec![sampled("dbid_1", "downloading", 10),
+                             sampled("dbid_2", "finished", 50)];
+        recorder.record(&tasks).unwrap();
+        tasks[0].size_downloaded = 20;
+        recorder.last = None;
+        recorder.record(&tasks).unwrap();
+        tasks[0].size_downloaded = 30;
+        recorder.record(&tasks).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], HEADER);
+        assert!(lines[3].contains(",home,dbid_1,downloading,20,0,"));
+        assert_eq!(load(&path).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn record_without_file() {
+        let mut recorder = Recorder::default();
+        recorder.record(&[sampled("dbid_1", "waiting", 0)]).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut recorder = Recorder::new(Some(dir.path().to_path_buf()), "home");
+        assert!(recorder.record(&[sampled("dbid_1", "waiting", 0)]).is_err());
+        recorder.last = None;
+        assert!(recorder.record(&[sampled("dbid_1", "waiting", 0)]).is_ok());
+    }
+
+    #[test]
+    fn hours_of_a_day() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 10, 14, 25, 0).unwrap();
+        let b = buckets(Period::Day, &now);
+        assert_eq!(b.len(), 24);
+        assert_eq!(b[0].label, "15:00");
+        assert_eq!(b[23].label, "14:00");
+        assert_eq!(b[23].start, Utc.with_ymd_and_hms(2024, 3, 10, 14, 0, 0).unwrap().timestamp());
+    }
+
+    #[test]
+    fn days_of_a_week() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 10, 14, 25, 0).unwrap();
+        let b = buckets(Period::Week, &now);
+        assert_eq!(b.len(), 7);
+        assert_eq!(b[0].label, "Mon 2024-03-04");
+        assert_eq!(b[6].label, "Sun 2024-03-10");
+        assert_eq!(b[6].start, Utc.with_ymd_and_hms(2024, 3, 10, 0, 0, 0).unwrap().timestamp());
+        assert_eq!(buckets(Period::Month, &now).len(), 30);
+    }
+
+    #[test]
+    fn traffic_report() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 10, 14, 25, 0).unwrap();
+        let t = |h, m| Utc.with_ymd_and_hms(2024, 3, 10, h, m, 0).unwrap().timestamp();
+        let samples = [
+            /* before the period, only the baseline counts */
+            sample(t(14, 0) - 86400, "a", "downloading", 100, 0),
+            sample(t(13, 10), "a", "downloading", 400, 0),
+            sample(t(13, 20), "b", "downloading", 0, 0),
+            sample(t(14, 5), "a", "seeding", 1000, 50),
+            sample(t(14, 6), "b", "downloading", 200, 0),
+            /* restarted, counts nothing instead of going negative */
+            sample(t(14, 7), "b", "downloading", 0, 0),
+            sample(t(14, 8), "c", "finished", 5000, 0),
+        ];
+        let r = report(&samples, Period::Day, &now);
+
+        let last = &r.buckets[23];
+        assert_eq!((last.downloaded, last.uploaded, last.finished), (800, 50, 1));
+        let before = &r.buckets[22];
+        assert_eq!((before.downloaded, before.finished), (300, 0));
+        assert_eq!(r.top, [(String::from("a.iso"), 900, 50), (String::from("b.iso"), 200, 0)]);
+    }
+}
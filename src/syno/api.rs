@@ -18,31 +18,134 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
 use std::{
-    error,
-    io::{self, ErrorKind},
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use serde::{Deserialize, Serialize};
-use ureq::tls::TlsConfig;
+use ureq::{
+    tls::{RootCerts, TlsConfig},
+    Agent,
+};
 use url::Url;
 
-use crate::{Config, Task};
+use super::{
+    tls::{fingerprint, load_certificates, peer_certificates, same_fingerprint},
+    traffic::{Recorder, Replayer},
+    Error, Limits, Task, Traffic,
+};
 
-#[derive(Deserialize, Serialize)]
-pub struct Session {
+/* The task API used to talk to DownloadStation: DSM 7 advertises the newer
+ * SYNO.DownloadStation2.Task while DSM 6 only knows about the legacy API. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TaskApi {
+    Legacy,
+    DownloadStation2,
+}
+
+struct Session {
     sid: String,
+    task_api: TaskApi,
+}
+
+/* How long to wait for the NAS to accept a connection, and for a whole
+ * request to complete (some DSM versions take their time listing tasks) */
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/* Number of tasks to ask for per list request; DSM may return fewer */
+const PAGE_SIZE: usize = 500;
+
+/// Where and how to connect to DownloadStation.
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// Base URL of the NAS, e.g. `https://nas:5001/`.
+    pub url: String,
+    pub user: String,
+    pub password: String,
+    /// How long to wait for the connection to be established.
+    pub connect_timeout: Duration,
+    /// How long a single request may take in total.
+    pub request_timeout: Duration,
+    /// Whether to record or replay the API traffic.
+    pub traffic: Traffic,
+    /// Certificates (PEM or DER) to verify the NAS against. Without one,
+    /// any certificate is accepted.
+    pub cacert: Option<PathBuf>,
+    /// SHA-256 fingerprint the NAS certificate must have, checked before
+    /// logging in.
+    pub fingerprint: Option<String>,
+}
+
+impl ClientConfig {
+    /// Creates a configuration with the default timeouts.
+    pub fn new(url: &str, user: &str, password: &str) -> ClientConfig {
+        ClientConfig {
+            url: String::from(url),
+            user: String::from(user),
+            password: String::from(password),
+            connect_timeout: CONNECT_TIMEOUT,
+            request_timeout: REQUEST_TIMEOUT,
+            traffic: Traffic::Live,
+            cacert: None,
+            fingerprint: None,
+        }
+    }
+}
+
+/// A logged-in connection to DownloadStation.
+///
+/// All requests go through one HTTP agent so that the TCP and TLS
+/// connection is kept alive between them.
+pub struct SynoClient {
+    base: Url,
+    agent: Agent,
+    transport: Transport,
+    session: Session,
+}
+
+enum Transport {
+    Live,
+    Record(Recorder),
+    Replay(Replayer),
+}
+
+#[derive(Deserialize, Serialize)]
+struct SynoError {
+    code: u32,
 }
 
 #[derive(Deserialize, Serialize)]
 struct SynoResponse {
     success: bool,
+    error: Option<SynoError>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SessionData {
+    sid: String,
 }
 
 #[derive(Deserialize, Serialize)]
 struct SessionResponse {
     #[serde(flatten)]
     response: SynoResponse,
-    data: Session,
+    data: SessionData,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ApiInfo {
+    #[serde(rename = "maxVersion")]
+    max_version: u32,
+    path: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ApiInfoResponse {
+    #[serde(flatten)]
+    response: SynoResponse,
+    data: HashMap<String, ApiInfo>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -53,23 +156,58 @@ struct TaskResponseTransfer {
     speed_upload: u64,
 }
 
+#[derive(Default, Deserialize, Serialize)]
+struct TaskResponseDetail {
+    #[serde(default)]
+    uri: String,
+    #[serde(default)]
+    destination: String,
+    #[serde(default)]
+    create_time: u64,
+    #[serde(default)]
+    completed_time: u64,
+    #[serde(default)]
+    seedelapsed: u64,
+}
+
 #[derive(Deserialize, Serialize)]
 struct TaskResponseAdditional {
+    #[serde(default)]
+    detail: TaskResponseDetail,
     transfer: TaskResponseTransfer,
 }
 
+#[derive(Default, Deserialize, Serialize)]
+struct TaskResponseStatusExtra {
+    #[serde(default)]
+    error_detail: String,
+}
+
+/* DownloadStation2 reports the task status as a number */
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum TaskResponseStatus {
+    Text(String),
+    Code(u32),
+}
+
 #[derive(Deserialize, Serialize)]
 struct TaskResponse {
     id: String,
     title: String,
-    status: String,
+    status: TaskResponseStatus,
     size: u64,
+    #[serde(default)]
+    status_extra: Option<TaskResponseStatusExtra>,
     additional: TaskResponseAdditional,
 }
 
 #[derive(Deserialize, Serialize)]
 struct TaskListResponseData {
-    offset: u32,
+    offset: usize,
+    #[serde(default)]
+    total: usize,
+    #[serde(alias = "task")]
     tasks: Vec<TaskResponse>,
 }
 
@@ -80,81 +218,132 @@ struct TaskListResponse {
     data: TaskListResponseData,
 }
 
-fn syno_do(url: &Url) -> Result<String, Box<dyn error::Error>> {
-    let agent = ureq::config::Config::builder()
-        .tls_config(TlsConfig::builder().disable_verification(true).build())
-        .build()
-        .new_agent();
-
-    let res = agent
-        .get(url.as_str())
-        .call()?
-        .body_mut()
-        .read_to_string()?;
-
-    let syno = serde_json::from_str::<SynoResponse>(&res);
-    let success = match syno {
-        Ok(res) => res.success,
-        Err(e) => {
-            println!("Failed to load JSON response: {}", res);
-            return Err(Box::new(e));
-        }
-    };
+#[derive(Deserialize, Serialize)]
+struct TaskInfoResponseData {
+    #[serde(alias = "task")]
+    tasks: Vec<TaskResponse>,
+}
 
-    match success {
-        true => Ok(res),
-        false => {
-            eprintln!("API request failed: {}", url);
-            eprintln!("Response was: {}", res);
-            Err(Box::new(io::Error::new(
-                ErrorKind::Other,
-                "API request failed",
-            )))
-        }
-    }
+#[derive(Deserialize, Serialize)]
+struct TaskInfoResponse {
+    #[serde(flatten)]
+    response: SynoResponse,
+    data: TaskInfoResponseData,
 }
 
-pub fn syno_login(cfg: &Config) -> Result<Session, Box<dyn error::Error>> {
-    let mut url = Url::parse(&cfg.url)?.join("/webapi/auth.cgi")?;
-    url.query_pairs_mut()
-        .clear()
-        .append_pair("api", "SYNO.API.Auth")
-        .append_pair("version", "2")
-        .append_pair("method", "login")
-        .append_pair("account", &cfg.user)
-        .append_pair("passwd", cfg.password.as_ref().unwrap())
-        .append_pair("session", "DownloadStation")
-        .append_pair("format", "sid");
+#[derive(Deserialize, Serialize)]
+struct LocationResponseData {
+    default_destination: String,
+}
 
-    let res = syno_do(&url)?;
+#[derive(Deserialize, Serialize)]
+struct LocationResponse {
+    #[serde(flatten)]
+    response: SynoResponse,
+    data: LocationResponseData,
+}
 
-    match serde_json::from_str::<SessionResponse>(&res) {
-        Ok(s) => Ok(s.data),
-        Err(e) => {
-            println!("Failed to parse server response: {}", res);
-            Err(Box::new(e))
-        }
+/* SYNO.DownloadStation.Info getconfig, the parts synodl cares about */
+#[derive(Deserialize, Serialize)]
+struct ConfigResponseData {
+    #[serde(default)]
+    bt_max_download: u64,
+    #[serde(default)]
+    bt_max_upload: u64,
+    #[serde(default)]
+    http_max_download: u64,
+    #[serde(default)]
+    ftp_max_download: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ConfigResponse {
+    #[serde(flatten)]
+    response: SynoResponse,
+    data: ConfigResponseData,
+}
+
+fn ds2_status_text(code: u32) -> &'static str {
+    match code {
+        1 => "waiting",
+        2 => "downloading",
+        3 => "paused",
+        4 => "finishing",
+        5 => "finished",
+        6 => "hash_checking",
+        7 => "preseeding",
+        8 => "seeding",
+        9 => "filehosting_waiting",
+        10 => "extracting",
+        11 => "preprocessing",
+        12 => "preprocess_pass",
+        13 => "downloaded",
+        14 => "postprocessing",
+        15 => "captcha_needed",
+        _ => "error",
     }
 }
 
-pub fn syno_list(cfg: &Config, s: &Session) -> Result<Vec<Task>, Box<dyn error::Error>> {
-    let mut url = Url::parse(&cfg.url)?.join("/webapi/DownloadStation/task.cgi")?;
-    url.query_pairs_mut()
-        .clear()
-        .append_pair("api", "SYNO.DownloadStation.Task")
-        .append_pair("version", "2")
-        .append_pair("method", "list")
-        .append_pair("additional", "transfer")
-        .append_pair("_sid", &s.sid);
+/* DownloadStation2 has a status code for each reason a task failed */
+fn ds2_error_detail(code: u32) -> &'static str {
+    match code {
+        102 => "broken_link",
+        103 => "destination_not_exist",
+        104 => "destination_denied",
+        105 => "disk_full",
+        106 => "quota_reached",
+        107 => "timeout",
+        108 => "exceed_max_file_system_size",
+        109 => "exceed_max_destination_size",
+        110 => "exceed_max_temp_size",
+        111 => "encrypted_name_too_long",
+        112 => "name_too_long",
+        113 => "torrent_duplicate",
+        114 => "file_not_exist",
+        115 => "required_premium_account",
+        116 => "not_supported_type",
+        117 => "try_it_later",
+        118 => "task_encryption",
+        119 => "missing_python",
+        120 => "private_video",
+        _ => "",
+    }
+}
 
-    let res = syno_do(&url)?;
+/* JSON-encode a list of strings the way DownloadStation2 expects it */
+fn ds2_list(items: &[&str]) -> String {
+    serde_json::to_string(items).unwrap_or_default()
+}
 
-    let json = serde_json::from_str::<TaskListResponse>(&res)?;
+/* the uri values for the legacy create method, which splits them at commas */
+fn legacy_batches(remotes: &[&str]) -> Vec<String> {
+    let mut batches = vec![];
+    let mut batch: Vec<&str> = vec![];
+    for remote in remotes {
+        if remote.contains(',') {
+            if !batch.is_empty() {
+                batches.push(batch.join(","));
+                batch.clear();
+            }
+            batches.push(String::from(*remote));
+        } else {
+            batch.push(remote);
+        }
+    }
+    if !batch.is_empty() {
+        batches.push(batch.join(","));
+    }
+    batches
+}
 
-    let iter = json.data.tasks.iter().map(|t| Task {
+fn make_task(t: &TaskResponse) -> Task {
+    Task {
         id: String::from(&t.id),
         title: String::from(&t.title),
-        status: String::from(&t.status),
+        status: match &t.status {
+            TaskResponseStatus::Text(status) => String::from(status),
+            TaskResponseStatus::Code(code) => String::from(ds2_status_text(*code)),
+        },
         size: t.size,
         size_downloaded: t.additional.transfer.size_downloaded,
         size_uploaded: t.additional.transfer.size_uploaded,
@@ -168,105 +357,558 @@ pub fn syno_list(cfg: &Config, s: &Session) -> Result<Vec<Task>, Box<dyn error::
             0 => 0 as f64,
             _ => t.additional.transfer.size_uploaded as f64 / t.size as f64,
         },
-    });
+        uri: String::from(&t.additional.detail.uri),
+        destination: String::from(&t.additional.detail.destination),
+        create_time: t.additional.detail.create_time,
+        completed_time: t.additional.detail.completed_time,
+        seed_elapsed: t.additional.detail.seedelapsed,
+        error_detail: match (&t.status_extra, &t.status) {
+            (Some(extra), _) if !extra.error_detail.is_empty() => extra.error_detail.clone(),
+            (_, TaskResponseStatus::Code(code)) => String::from(ds2_error_detail(*code)),
+            _ => String::new(),
+        },
+    }
+}
+
+fn make_agent(cfg: &ClientConfig) -> Result<Agent, Error> {
+    let tls = match &cfg.cacert {
+        Some(path) => TlsConfig::builder()
+            .root_certs(RootCerts::new_with_certs(&load_certificates(path)?))
+            .build(),
+        None => TlsConfig::builder().disable_verification(true).build(),
+    };
 
-    Ok(iter.rev().collect())
+    Ok(Agent::config_builder()
+        .tls_config(tls)
+        .timeout_connect(Some(cfg.connect_timeout))
+        .timeout_global(Some(cfg.request_timeout))
+        .build()
+        .new_agent())
 }
 
-pub fn syno_list_tasks(cfg: &Config, s: &Session) -> Result<(), Box<dyn error::Error>> {
-    match syno_list(cfg, s) {
-        Ok(s) => Ok(s
-            .into_iter()
-            .for_each(|t| println!("Task ID {0} status {1} titled {2}", t.id, t.status, t.title))),
-        Err(e) => {
-            println!("Failed to parse server response: {}", e);
-            Err(e)
+/* make sure we talk to the NAS whose certificate was pinned */
+fn check_fingerprint(cfg: &ClientConfig, base: &Url) -> Result<(), Error> {
+    let expected = match &cfg.fingerprint {
+        Some(fp) if base.scheme() == "https" => fp,
+        _ => return Ok(()),
+    };
+
+    let actual = fingerprint(&peer_certificates(base, cfg.connect_timeout)?[0]);
+    match same_fingerprint(expected, &actual) {
+        true => Ok(()),
+        false => Err(Error::Tls(format!(
+            "Certificate fingerprint {} does not match the pinned {}",
+            actual, expected
+        ))),
+    }
+}
+
+/* A multipart/form-data request body and its content type */
+struct Form {
+    content_type: String,
+    body: Vec<u8>,
+}
+
+impl Form {
+    /* text fields first, DSM expects the file at the end */
+    fn new(fields: &[(&str, String)], file_field: &str, file_name: &str, data: &[u8]) -> Form {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let boundary = format!("----synodl{:x}", nanos);
+
+        let mut body = vec![];
+        for (name, value) in fields {
+            body.extend(format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                boundary, name, value
+            ).bytes());
+        }
+        body.extend(format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n",
+            boundary, file_field, file_name.replace('"', "")
+        ).bytes());
+        body.extend(data);
+        body.extend(format!("\r\n--{}--\r\n", boundary).bytes());
+
+        Form {
+            content_type: format!("multipart/form-data; boundary={}", boundary),
+            body,
         }
     }
 }
 
-pub fn syno_download(
-    cfg: &Config,
-    s: &Session,
-    remote: &String,
-) -> Result<(), Box<dyn error::Error>> {
-    let mut url = Url::parse(&cfg.url)?.join("/webapi/DownloadStation/task.cgi")?;
-    url.query_pairs_mut()
-        .clear()
-        .append_pair("api", "SYNO.DownloadStation.Task")
-        .append_pair("version", "2")
-        .append_pair("method", "create")
-        .append_pair("uri", remote)
-        .append_pair("_sid", &s.sid);
-
-    syno_do(&url)?;
-    Ok(())
-}
-
-pub fn syno_resume_all(cfg: &Config, s: &Session) -> Result<(), Box<dyn error::Error>> {
-    match syno_list(cfg, s) {
-        Ok(tasks) => {
-            let mut url = Url::parse(&cfg.url)?.join("/webapi/DownloadStation/task.cgi")?;
-            url.query_pairs_mut()
-                .clear()
-                .append_pair("api", "SYNO.DownloadStation.Task")
-                .append_pair("version", "1")
-                .append_pair("method", "resume");
-
-            url.set_query(Some(
-                &(url.query().unwrap().to_owned()
-                    + "&id="
-                    + &tasks
-                        .iter()
-                        .filter_map(|t| match t.status.contains("error") {
-                            true => Some(t.id.clone()),
-                            false => None,
-                        })
-                        // FIXME you will get HTTP error code 414 ("URL too long") if this is unbounded
-                        //       but rather than just hard-coding a limit that works (300, via testing works)
-                        //       we should iterate over chunks and send them all in
-                        .take(300)
-                        .collect::<Vec<_>>()
-                        .join(",")),
-            ));
-            url.query_pairs_mut().append_pair("_sid", &s.sid);
-
-            println!("Query is: ${0}", url);
-            syno_do(&url)?;
+fn fetch(agent: &Agent, url: &Url, form: Option<&Form>) -> Result<String, Error> {
+    let mut res = match form {
+        None => agent.get(url.as_str()).call()?,
+        Some(form) => agent
+            .post(url.as_str())
+            .header("Content-Type", &form.content_type)
+            .send(&form.body[..])?,
+    };
+    Ok(res.body_mut().read_to_string()?)
+}
+
+fn check_response(url: &Url, res: String) -> Result<String, Error> {
+    let syno = match serde_json::from_str::<SynoResponse>(&res) {
+        Ok(syno) => syno,
+        Err(_) => return Err(Error::InvalidResponse(res)),
+    };
+
+    match syno.success {
+        true => Ok(res),
+        false => {
+            let api = url
+                .query_pairs()
+                .find(|(k, _)| k == "api")
+                .map(|(_, v)| v.into_owned())
+                .unwrap_or_default();
+            Err(Error::Api {
+                api,
+                code: syno.error.map(|e| e.code).unwrap_or(100),
+            })
+        }
+    }
+}
+
+impl SynoClient {
+    /// Logs in to DownloadStation and works out which task API to use.
+    pub fn login(cfg: &ClientConfig) -> Result<SynoClient, Error> {
+        let transport = match &cfg.traffic {
+            Traffic::Live => Transport::Live,
+            Traffic::Record(dir) => Transport::Record(Recorder::new(dir)?),
+            Traffic::Replay(dir) => Transport::Replay(Replayer::load(dir)?),
+        };
+        let base = Url::parse(&cfg.url)?;
+        if let Traffic::Live | Traffic::Record(_) = cfg.traffic {
+            check_fingerprint(cfg, &base)?;
+        }
+
+        let mut client = SynoClient {
+            base,
+            agent: make_agent(cfg)?,
+            transport,
+            session: Session {
+                sid: String::new(),
+                task_api: TaskApi::Legacy,
+            },
+        };
+
+        let mut url = client.base.join("/webapi/auth.cgi")?;
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("api", "SYNO.API.Auth")
+            .append_pair("version", "2")
+            .append_pair("method", "login")
+            .append_pair("account", &cfg.user)
+            .append_pair("passwd", &cfg.password)
+            .append_pair("session", "DownloadStation")
+            .append_pair("format", "sid");
+
+        let res = client.request(&url)?;
+        let s = serde_json::from_str::<SessionResponse>(&res)?;
+
+        client.session.sid = s.data.sid;
+        client.session.task_api = client.detect_task_api();
+        Ok(client)
+    }
+
+    fn request(&self, url: &Url) -> Result<String, Error> {
+        self.send(url, None)
+    }
+
+    /* recordings only keep the URL of uploads, not the file */
+    fn send(&self, url: &Url, form: Option<&Form>) -> Result<String, Error> {
+        let res = match &self.transport {
+            Transport::Live => fetch(&self.agent, url, form)?,
+            Transport::Record(recorder) => {
+                let res = fetch(&self.agent, url, form)?;
+                recorder.save(url, &res)?;
+                res
+            }
+            Transport::Replay(replayer) => replayer.answer(url)?,
+        };
+        check_response(url, res)
+    }
+
+    fn detect_task_api(&self) -> TaskApi {
+        let mut url = match self.base.join("/webapi/query.cgi") {
+            Ok(url) => url,
+            Err(_) => return TaskApi::Legacy,
+        };
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("api", "SYNO.API.Info")
+            .append_pair("version", "1")
+            .append_pair("method", "query")
+            .append_pair("query", "SYNO.DownloadStation2.Task");
+
+        let res = match self.request(&url) {
+            Ok(res) => res,
+            Err(_) => return TaskApi::Legacy,
+        };
+
+        match serde_json::from_str::<ApiInfoResponse>(&res) {
+            Ok(info) => match info.data.get("SYNO.DownloadStation2.Task") {
+                Some(api) if api.max_version >= 2 && api.path == "entry.cgi" => {
+                    TaskApi::DownloadStation2
+                }
+                _ => TaskApi::Legacy,
+            },
+            Err(_) => TaskApi::Legacy,
+        }
+    }
+
+    fn task_url(&self, method: &str, legacy_version: &str) -> Result<Url, Error> {
+        let (path, api, version) = match self.session.task_api {
+            TaskApi::Legacy => (
+                "/webapi/DownloadStation/task.cgi",
+                "SYNO.DownloadStation.Task",
+                legacy_version,
+            ),
+            TaskApi::DownloadStation2 => ("/webapi/entry.cgi", "SYNO.DownloadStation2.Task", "2"),
+        };
+        let mut url = self.base.join(path)?;
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("api", api)
+            .append_pair("version", version)
+            .append_pair("method", method);
+        Ok(url)
+    }
+
+    /// Lists all download tasks, most recently created first.
+    pub fn list(&self) -> Result<Vec<Task>, Error> {
+        let mut tasks = vec![];
+
+        loop {
+            let mut url = self.task_url("list", "2")?;
+            {
+                let mut query = url.query_pairs_mut();
+                query
+                    .append_pair("offset", &tasks.len().to_string())
+                    .append_pair("limit", &PAGE_SIZE.to_string());
+                match self.session.task_api {
+                    TaskApi::Legacy => query.append_pair("additional", "detail,transfer"),
+                    TaskApi::DownloadStation2 => {
+                        query.append_pair("additional", &ds2_list(&["detail", "transfer"]))
+                    }
+                };
+                query.append_pair("_sid", &self.session.sid);
+            }
+
+            let res = self.request(&url)?;
+            let json = serde_json::from_str::<TaskListResponse>(&res)?;
+
+            let received = json.data.tasks.len();
+            tasks.extend(json.data.tasks.iter().map(make_task));
+
+            if received == 0 || json.data.offset + received >= json.data.total {
+                break;
+            }
+        }
+
+        tasks.reverse();
+        Ok(tasks)
+    }
+
+    /* DownloadStation2 does not fall back to the default destination on its own */
+    fn ds2_default_destination(&self) -> Result<String, Error> {
+        let mut url = self.base.join("/webapi/entry.cgi")?;
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("api", "SYNO.DownloadStation2.Settings.Location")
+            .append_pair("version", "1")
+            .append_pair("method", "get")
+            .append_pair("_sid", &self.session.sid);
+
+        let res = self.request(&url)?;
+        let json = serde_json::from_str::<LocationResponse>(&res)?;
+        Ok(json.data.default_destination)
+    }
+
+    /// Creates a download task for the given URL or magnet link.
+    pub fn download(&self, remote: &str) -> Result<(), Error> {
+        self.download_many(&[remote])
+    }
+
+    /// Creates a download task for each of the given URLs in a single request.
+    ///
+    /// The legacy API takes the URLs as one comma separated list, so URLs
+    /// containing a comma are sent in requests of their own there.
+    pub fn download_many(&self, remotes: &[&str]) -> Result<(), Error> {
+        if remotes.is_empty() {
             return Ok(());
         }
+        match self.session.task_api {
+            TaskApi::Legacy => {
+                for uri in legacy_batches(remotes) {
+                    self.create_task(&[("uri", uri)])?;
+                }
+                Ok(())
+            }
+            TaskApi::DownloadStation2 => {
+                let destination = self.ds2_default_destination()?;
+                self.create_task(&[
+                    ("type", String::from("\"url\"")),
+                    ("url", ds2_list(remotes)),
+                    ("destination", serde_json::to_string(&destination)?),
+                    ("create_list", String::from("false")),
+                ])
+            }
+        }
+    }
 
-        Err(e) => {
-            println!("Failed to parse server response: {}", e);
-            return Err(e);
+    fn create_task(&self, params: &[(&str, String)]) -> Result<(), Error> {
+        let mut url = self.task_url("create", "2")?;
+        {
+            let mut query = url.query_pairs_mut();
+            for (name, value) in params {
+                query.append_pair(name, value);
+            }
+            query.append_pair("_sid", &self.session.sid);
         }
+
+        self.request(&url)?;
+        Ok(())
     }
-}
 
-pub fn syno_delete(cfg: &Config, s: &Session, t: &Task) -> Result<(), Box<dyn error::Error>> {
-    let mut url = Url::parse(&cfg.url)?.join("/webapi/DownloadStation/task.cgi")?;
-    url.query_pairs_mut()
-        .clear()
-        .append_pair("api", "SYNO.DownloadStation.Task")
-        .append_pair("version", "1")
-        .append_pair("method", "delete")
-        .append_pair("id", &t.id)
-        .append_pair("_sid", &s.sid);
+    /// Creates a download task from the contents of a .torrent or .nzb file.
+    pub fn upload(&self, file_name: &str, data: &[u8]) -> Result<(), Error> {
+        let (fields, file_field) = match self.session.task_api {
+            TaskApi::Legacy => (vec![], "file"),
+            TaskApi::DownloadStation2 => (
+                vec![
+                    ("type", String::from("\"file\"")),
+                    ("file", ds2_list(&["torrent"])),
+                    ("destination", serde_json::to_string(&self.ds2_default_destination()?)?),
+                    ("create_list", String::from("false")),
+                ],
+                "torrent",
+            ),
+        };
+
+        let mut url = self.task_url("create", "2")?;
+        url.query_pairs_mut().append_pair("_sid", &self.session.sid);
+
+        self.send(&url, Some(&Form::new(&fields, file_field, file_name, data)))?;
+        Ok(())
+    }
+
+    /// Resumes all tasks in an error state, returns how many there were.
+    pub fn resume_all(&self) -> Result<usize, Error> {
+        let tasks = self.list()?;
+        let ids = tasks
+            .iter()
+            .filter_map(|t| match t.status.contains("error") {
+                true => Some(t.id.as_str()),
+                false => None,
+            })
+            // FIXME you will get HTTP error code 414 ("URL too long") if this is unbounded
+            //       but rather than just hard-coding a limit that works (300, via testing works)
+            //       we should iterate over chunks and send them all in
+            .take(300)
+            .collect::<Vec<_>>();
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        let mut url = self.task_url("resume", "1")?;
+        {
+            let mut query = url.query_pairs_mut();
+            match self.session.task_api {
+                TaskApi::Legacy => query.append_pair("id", &ids.join(",")),
+                TaskApi::DownloadStation2 => query.append_pair("id", &ds2_list(&ids)),
+            };
+            query.append_pair("_sid", &self.session.sid);
+        }
+
+        self.request(&url)?;
+        Ok(ids.len())
+    }
+
+    /// Fetches the current state of a single task.
+    pub fn info(&self, id: &str) -> Result<Task, Error> {
+        let method = match self.session.task_api {
+            TaskApi::Legacy => "getinfo",
+            TaskApi::DownloadStation2 => "get",
+        };
+        let mut url = self.task_url(method, "1")?;
+        {
+            let mut query = url.query_pairs_mut();
+            match self.session.task_api {
+                TaskApi::Legacy => query
+                    .append_pair("id", id)
+                    .append_pair("additional", "detail,transfer"),
+                TaskApi::DownloadStation2 => query
+                    .append_pair("id", &ds2_list(&[id]))
+                    .append_pair("additional", &ds2_list(&["detail", "transfer"])),
+            };
+            query.append_pair("_sid", &self.session.sid);
+        }
+
+        let res = self.request(&url)?;
+        let json = serde_json::from_str::<TaskInfoResponse>(&res)?;
+
+        match json.data.tasks.first() {
+            Some(t) => Ok(make_task(t)),
+            None => Err(Error::TaskNotFound(String::from(id))),
+        }
+    }
+
+    fn task_action(&self, method: &str, id: &str) -> Result<(), Error> {
+        let mut url = self.task_url(method, "1")?;
+        {
+            let mut query = url.query_pairs_mut();
+            match self.session.task_api {
+                TaskApi::Legacy => query.append_pair("id", id),
+                TaskApi::DownloadStation2 => query.append_pair("id", &ds2_list(&[id])),
+            };
+            query.append_pair("_sid", &self.session.sid);
+        }
+
+        self.request(&url)?;
+        Ok(())
+    }
+
+    /// Pauses the task with the given ID.
+    pub fn pause(&self, id: &str) -> Result<(), Error> {
+        self.task_action("pause", id)
+    }
+
+    /// Resumes the task with the given ID.
+    pub fn resume(&self, id: &str) -> Result<(), Error> {
+        self.task_action("resume", id)
+    }
+
+    /// Deletes the task with the given ID, keeping downloaded files.
+    pub fn delete(&self, id: &str) -> Result<(), Error> {
+        let mut url = self.task_url("delete", "1")?;
+        {
+            let mut query = url.query_pairs_mut();
+            match self.session.task_api {
+                TaskApi::Legacy => query.append_pair("id", id),
+                TaskApi::DownloadStation2 => query
+                    .append_pair("id", &ds2_list(&[id]))
+                    .append_pair("force_complete", "false"),
+            };
+            query.append_pair("_sid", &self.session.sid);
+        }
+
+        self.request(&url)?;
+        Ok(())
+    }
+
+    /* the speed limits only exist in the legacy API, DSM 7 still serves it */
+    fn info_url(&self, method: &str) -> Result<Url, Error> {
+        let mut url = self.base.join("/webapi/DownloadStation/info.cgi")?;
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("api", "SYNO.DownloadStation.Info")
+            .append_pair("version", "1")
+            .append_pair("method", method);
+        Ok(url)
+    }
+
+    /// Fetches the global transfer rate limits.
+    pub fn limits(&self) -> Result<Limits, Error> {
+        let mut url = self.info_url("getconfig")?;
+        url.query_pairs_mut().append_pair("_sid", &self.session.sid);
+
+        let res = self.request(&url)?;
+        let json = serde_json::from_str::<ConfigResponse>(&res)?;
+        Ok(Limits {
+            bt_download: json.data.bt_max_download,
+            bt_upload: json.data.bt_max_upload,
+            http_download: json.data.http_max_download,
+            ftp_download: json.data.ftp_max_download,
+        })
+    }
+
+    /// Changes the global transfer rate limits.
+    pub fn set_limits(&self, limits: &Limits) -> Result<(), Error> {
+        let mut url = self.info_url("setserverconfig")?;
+        url.query_pairs_mut()
+            .append_pair("bt_max_download", &limits.bt_download.to_string())
+            .append_pair("bt_max_upload", &limits.bt_upload.to_string())
+            .append_pair("http_max_download", &limits.http_download.to_string())
+            .append_pair("ftp_max_download", &limits.ftp_download.to_string())
+            .append_pair("_sid", &self.session.sid);
+
+        self.request(&url)?;
+        Ok(())
+    }
 
-    syno_do(&url)?;
-    Ok(())
+    /// Ends the session.
+    pub fn logout(&self) -> Result<(), Error> {
+        let mut url = self.base.join("/webapi/auth.cgi")?;
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("api", "SYNO.API.Auth")
+            .append_pair("version", "1")
+            .append_pair("method", "logout")
+            .append_pair("session", "DownloadStation")
+            .append_pair("_sid", &self.session.sid);
+
+        self.request(&url)?;
+        Ok(())
+    }
 }
 
-pub fn syno_logout(cfg: &Config, s: &Session) -> Result<(), Box<dyn error::Error>> {
-    let mut url = Url::parse(&cfg.url)?.join("/webapi/auth.cgi")?;
-    url.query_pairs_mut()
-        .clear()
-        .append_pair("api", "SYNO.API.Auth")
-        .append_pair("version", "1")
-        .append_pair("method", "logout")
-        .append_pair("session", "DownloadStation")
-        .append_pair("_sid", &s.sid);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ds2_status_codes() {
+        assert_eq!(ds2_status_text(2), "downloading");
+        assert_eq!(ds2_status_text(3), "paused");
+        assert_eq!(ds2_status_text(5), "finished");
+        assert_eq!(ds2_status_text(8), "seeding");
+        assert_eq!(ds2_status_text(105), "error");
+    }
+
+    #[test]
+    fn legacy_uri_lists() {
+        assert_eq!(legacy_batches(&["a", "b"]), ["a,b"]);
+        assert_eq!(legacy_batches(&["a", "b?x=1,2", "c", "d"]), ["a", "b?x=1,2", "c,d"]);
+        assert_eq!(legacy_batches(&["a,b", "c,d"]), ["a,b", "c,d"]);
+        assert_eq!(legacy_batches(&[""]), [""]);
+        assert!(legacy_batches(&[]).is_empty());
+    }
+
+    #[test]
+    fn parse_ds2_task_list() {
+        let res = r#"{"success": true, "data": {"offset": 0, "total": 1, "task": [
+            {"id": "dbid_1", "title": "debian.iso", "status": 2, "size": 100,
+             "additional": {"transfer": {"size_downloaded": 50, "size_uploaded": 0,
+                                         "speed_download": 10, "speed_upload": 0}}}]}}"#;
+        let json = serde_json::from_str::<TaskListResponse>(res).unwrap();
+        assert_eq!(json.data.tasks.len(), 1);
+        assert!(matches!(json.data.tasks[0].status, TaskResponseStatus::Code(2)));
+    }
 
-    syno_do(&url)?;
-    Ok(())
+    #[test]
+    fn parse_legacy_task_list() {
+        let res = r#"{"success": true, "data": {"offset": 0, "tasks": [
+            {"id": "dbid_1", "title": "debian.iso", "status": "paused", "size": 100,
+             "additional": {"transfer": {"size_downloaded": 50, "size_uploaded": 0,
+                                         "speed_download": 10, "speed_upload": 0}}}]}}"#;
+        let json = serde_json::from_str::<TaskListResponse>(res).unwrap();
+        assert_eq!(json.data.tasks.len(), 1);
+        assert!(matches!(&json.data.tasks[0].status, TaskResponseStatus::Text(s) if s == "paused"));
+    }
+
+    #[test]
+    fn parse_config() {
+        let res = r#"{"success": true, "data": {"bt_max_download": 0, "bt_max_upload": 50,
+            "emule_enabled": false, "http_max_download": 1000, "nzb_max_download": 0,
+            "unzip_service_enabled": false, "default_destination": "downloads"}}"#;
+        let json = serde_json::from_str::<ConfigResponse>(res).unwrap();
+        assert_eq!(json.data.bt_max_upload, 50);
+        assert_eq!(json.data.http_max_download, 1000);
+        assert_eq!(json.data.ftp_max_download, 0);
+    }
 }
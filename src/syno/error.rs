@@ -0,0 +1,132 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{error, fmt};
+
+/// Errors reported by the DownloadStation client.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The configured URL is not valid.
+    InvalidUrl(String),
+    /// The NAS could not be reached or answered with an HTTP error.
+    Http(String),
+    /// The NAS answered with something that is not a valid API response.
+    InvalidResponse(String),
+    /// The API rejected the request with the given error code.
+    Api { api: String, code: u32 },
+    /// There is no task with the given ID.
+    TaskNotFound(String),
+    /// Recording or replaying API traffic failed.
+    Recording(String),
+    /// The NAS certificate could not be fetched or did not match.
+    Tls(String),
+}
+
+impl Error {
+    /// The API error code, if the error was reported by the API.
+    pub fn code(&self) -> Option<u32> {
+        match self {
+            Error::Api { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+fn api_error_text(api: &str, code: u32) -> &'static str {
+    match (api, code) {
+        (_, 100) => "Unknown error",
+        (_, 101) => "Invalid parameter",
+        (_, 102) => "The requested API does not exist",
+        (_, 103) => "The requested method does not exist",
+        (_, 104) => "The requested version does not support the functionality",
+        (_, 105) => "The logged in session does not have permission",
+        (_, 106) => "Session timeout",
+        (_, 107) => "Session interrupted by duplicate login",
+        ("SYNO.API.Auth", 400) => "No such account or incorrect password",
+        ("SYNO.API.Auth", 401) => "Account disabled",
+        ("SYNO.API.Auth", 402) => "Permission denied",
+        ("SYNO.API.Auth", 403) => "2-step verification code required",
+        ("SYNO.API.Auth", 404) => "Failed to authenticate 2-step verification code",
+        (_, 400) => "File upload failed",
+        (_, 401) => "Max number of tasks reached",
+        (_, 402) => "Destination denied",
+        (_, 403) => "Destination does not exist",
+        (_, 404) => "Invalid task id",
+        (_, 405) => "Invalid task action",
+        (_, 406) => "No default destination",
+        (_, 407) => "Set destination failed",
+        (_, 408) => "File does not exist",
+        _ => "API request failed",
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidUrl(e) => write!(f, "Invalid URL: {}", e),
+            Error::Http(e) => write!(f, "{}", e),
+            Error::InvalidResponse(e) => write!(f, "Failed to parse server response: {}", e),
+            Error::Api { api, code } => {
+                write!(f, "{} (error {})", api_error_text(api, *code), code)
+            }
+            Error::TaskNotFound(id) => write!(f, "No such task: {}", id),
+            Error::Recording(e) => write!(f, "{}", e),
+            Error::Tls(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Error {
+        Error::InvalidUrl(e.to_string())
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(e: ureq::Error) -> Error {
+        Error::Http(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::InvalidResponse(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_text_depends_on_api() {
+        let auth = Error::Api { api: String::from("SYNO.API.Auth"), code: 400 };
+        assert_eq!(auth.to_string(), "No such account or incorrect password (error 400)");
+
+        let task = Error::Api { api: String::from("SYNO.DownloadStation.Task"), code: 400 };
+        assert_eq!(task.to_string(), "File upload failed (error 400)");
+
+        let ds2 = Error::Api { api: String::from("SYNO.DownloadStation2.Task"), code: 106 };
+        assert_eq!(ds2.to_string(), "Session timeout (error 106)");
+        assert_eq!(ds2.code(), Some(106));
+    }
+}
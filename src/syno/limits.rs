@@ -0,0 +1,46 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+use serde::{Deserialize, Serialize};
+
+/// DownloadStation's global transfer rate limits, in KB/s with 0 meaning
+/// unlimited. HTTP and FTP downloads have no upload to limit.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Limits {
+    pub bt_download: u64,
+    pub bt_upload: u64,
+    pub http_download: u64,
+    pub ftp_download: u64,
+}
+
+impl Limits {
+    /// Applies one download limit to all protocols and the upload limit to
+    /// BitTorrent, leaving out what is `None`.
+    pub fn with(&self, download: Option<u64>, upload: Option<u64>) -> Limits {
+        let mut limits = self.clone();
+        if let Some(n) = download {
+            limits.bt_download = n;
+            limits.http_download = n;
+            limits.ftp_download = n;
+        }
+        if let Some(n) = upload {
+            limits.bt_upload = n;
+        }
+        limits
+    }
+}
@@ -17,4 +17,20 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 */
 
-pub mod api;
+//! DownloadStation web API client.
+
+mod api;
+mod error;
+mod limits;
+mod task;
+mod tls;
+mod traffic;
+
+pub use self::{
+    api::{ClientConfig, SynoClient},
+    error::Error,
+    limits::Limits,
+    task::Task,
+    tls::{fingerprint, peer_certificates},
+    traffic::Traffic,
+};
@@ -0,0 +1,75 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use serde::{Deserialize, Serialize};
+
+/// A download task as reported by DownloadStation.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Task {
+    /// Task ID, used to refer to the task in later requests.
+    pub id: String,
+    pub title: String,
+    /// Status as named by DownloadStation, e.g. `downloading` or `seeding`.
+    pub status: String,
+    /// Total size in bytes.
+    pub size: u64,
+    pub size_downloaded: u64,
+    pub size_uploaded: u64,
+    /// Current download speed in bytes per second.
+    pub speed_download: u64,
+    /// Current upload speed in bytes per second.
+    pub speed_upload: u64,
+    /// Downloaded fraction of the total size, between 0 and 1.
+    pub percent_dn: f64,
+    /// Uploaded amount relative to the total size (the share ratio).
+    pub percent_up: f64,
+    /// Where the task downloads from: a URL, a magnet link or, for uploaded
+    /// files, usually the file name.
+    #[serde(default)]
+    pub uri: String,
+    /// Shared folder the task downloads to, e.g. `downloads/iso`.
+    #[serde(default)]
+    pub destination: String,
+    /// When the task was created, in seconds since the epoch, 0 if unknown.
+    #[serde(default)]
+    pub create_time: u64,
+    /// When the download completed, in seconds since the epoch, 0 if it has
+    /// not or DownloadStation does not say.
+    #[serde(default)]
+    pub completed_time: u64,
+    /// Seconds the task has been seeding.
+    #[serde(default)]
+    pub seed_elapsed: u64,
+    /// Why DownloadStation gave up on the task, e.g. `disk_full` or
+    /// `broken_link`, empty if it did not say.
+    #[serde(default)]
+    pub error_detail: String,
+}
+
+impl Task {
+    /// Whether the download is complete, seeding or not.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status.as_str(), "finished" | "seeding")
+    }
+
+    /// Whether DownloadStation gave up on the task.
+    pub fn is_failed(&self) -> bool {
+        self.status.contains("error")
+    }
+}
@@ -0,0 +1,196 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{
+    convert::TryFrom,
+    fs,
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use ring::digest::{digest, SHA256};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring::default_provider, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConnection, DigitallySignedStruct, SignatureScheme,
+};
+use ureq::tls::{parse_pem, Certificate, PemItem};
+use url::Url;
+
+use super::error::Error;
+
+/* Accepts whatever the server presents; we only want to look at it */
+#[derive(Debug)]
+struct AcceptAny(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAny {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn tls_error<E: std::fmt::Display>(e: E) -> Error {
+    Error::Tls(e.to_string())
+}
+
+/// Connects to the server behind `url` and returns the certificate chain it
+/// presents, server certificate first, in DER encoding.
+pub fn peer_certificates(url: &Url, timeout: Duration) -> Result<Vec<Vec<u8>>, Error> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| Error::InvalidUrl(String::from(url.as_str())))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let provider = Arc::new(default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAny(provider)))
+        .with_no_client_auth();
+    let name = ServerName::try_from(String::from(host)).map_err(tls_error)?;
+    let mut conn = ClientConnection::new(Arc::new(config), name).map_err(tls_error)?;
+
+    let addr = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| Error::Http(e.to_string()))?
+        .next()
+        .ok_or_else(|| Error::Http(format!("Cannot resolve {}", host)))?;
+    let mut sock =
+        TcpStream::connect_timeout(&addr, timeout).map_err(|e| Error::Http(e.to_string()))?;
+    sock.set_read_timeout(Some(timeout)).map_err(tls_error)?;
+    sock.set_write_timeout(Some(timeout)).map_err(tls_error)?;
+
+    while conn.is_handshaking() {
+        conn.complete_io(&mut sock).map_err(tls_error)?;
+    }
+
+    match conn.peer_certificates() {
+        Some(chain) if !chain.is_empty() => Ok(chain.iter().map(|c| c.to_vec()).collect()),
+        _ => Err(Error::Tls(String::from("The server sent no certificate"))),
+    }
+}
+
+/// The SHA-256 fingerprint of a DER encoded certificate, as colon-separated
+/// hex bytes.
+pub fn fingerprint(der: &[u8]) -> String {
+    digest(&SHA256, der)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/* fingerprints are compared without separators and regardless of case */
+pub(crate) fn same_fingerprint(a: &str, b: &str) -> bool {
+    let normalize = |s: &str| -> String {
+        s.chars()
+            .filter(|c| c.is_ascii_hexdigit())
+            .map(|c| c.to_ascii_uppercase())
+            .collect()
+    };
+    normalize(a) == normalize(b)
+}
+
+/* reads certificates from a PEM file or a single DER encoded one */
+pub(crate) fn load_certificates(path: &Path) -> Result<Vec<Certificate<'static>>, Error> {
+    let data = fs::read(path).map_err(|e| Error::Tls(format!("{}: {}", path.display(), e)))?;
+
+    if !data.starts_with(b"-----BEGIN") {
+        return Ok(vec![Certificate::from_der(&data).to_owned()]);
+    }
+
+    let mut certs = vec![];
+    for item in parse_pem(&data) {
+        if let PemItem::Certificate(cert) = item.map_err(tls_error)? {
+            certs.push(cert);
+        }
+    }
+
+    match certs.is_empty() {
+        true => Err(Error::Tls(format!("{}: no certificate found", path.display()))),
+        false => Ok(certs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_format() {
+        let fp = fingerprint(b"hello");
+        assert_eq!(fp.len(), 32 * 3 - 1);
+        assert!(fp.starts_with("2C:F2:4D:BA:5F:B0:A3:0E"));
+    }
+
+    #[test]
+    fn compare_fingerprints() {
+        assert!(same_fingerprint("2C:F2:4D", "2cf24d"));
+        assert!(!same_fingerprint("2C:F2:4D", "2C:F2:4E"));
+    }
+
+    #[test]
+    fn load_der_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nas.der");
+        fs::write(&path, b"not really a certificate").unwrap();
+        assert_eq!(load_certificates(&path).unwrap().len(), 1);
+
+        let path = dir.path().join("empty.pem");
+        fs::write(&path, b"-----BEGIN NOTHING-----\n-----END NOTHING-----\n").unwrap();
+        assert!(load_certificates(&path).is_err());
+        assert!(load_certificates(&dir.path().join("missing")).is_err());
+    }
+}
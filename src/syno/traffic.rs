@@ -0,0 +1,274 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use super::Error;
+
+const REDACTED: &str = "REDACTED";
+
+/// What to do with the traffic between client and NAS.
+#[derive(Clone, Debug, Default)]
+pub enum Traffic {
+    /// Talk to the NAS.
+    #[default]
+    Live,
+    /// Talk to the NAS and save every request and response in a directory.
+    Record(PathBuf),
+    /// Do not talk to the NAS at all, answer from a recording instead.
+    Replay(PathBuf),
+}
+
+/* One request and its response, as stored in a recording */
+#[derive(Deserialize, Serialize)]
+struct Exchange {
+    request: String,
+    response: String,
+}
+
+fn recording_error(path: &Path, e: impl ToString) -> Error {
+    Error::Recording(format!("{}: {}", path.display(), e.to_string()))
+}
+
+fn param(url: &Url, name: &str) -> String {
+    url.query_pairs()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.into_owned())
+        .unwrap_or_default()
+}
+
+/* Requests are told apart by the API and method they call */
+fn key(url: &Url) -> String {
+    format!("{}-{}", param(url, "api"), param(url, "method"))
+}
+
+fn redact_request(url: &Url) -> String {
+    let mut redacted = url.clone();
+    redacted.query_pairs_mut().clear().extend_pairs(url.query_pairs().map(|(k, v)| {
+        match k.as_ref() {
+            "passwd" | "_sid" => (k, REDACTED.into()),
+            _ => (k, v),
+        }
+    }));
+    match redacted.query() {
+        Some(q) => format!("{}?{}", redacted.path(), q),
+        None => String::from(redacted.path()),
+    }
+}
+
+fn redact_response(res: &str) -> String {
+    match serde_json::from_str::<Value>(res) {
+        Ok(mut json) => match json.pointer_mut("/data/sid") {
+            Some(sid) => {
+                *sid = Value::from(REDACTED);
+                json.to_string()
+            }
+            None => String::from(res),
+        },
+        Err(_) => String::from(res),
+    }
+}
+
+/* The number a recorded exchange is stored under, files are named NNNN-api-method.json */
+fn number(path: &Path) -> Option<u32> {
+    let name = path.file_name()?.to_str()?;
+    name.split('-').next()?.parse().ok()
+}
+
+fn recordings(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).map_err(|e| recording_error(dir, e))? {
+        let path = entry.map_err(|e| recording_error(dir, e))?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+pub struct Recorder {
+    dir: PathBuf,
+    count: Mutex<u32>,
+}
+
+impl Recorder {
+    pub fn new(dir: &Path) -> Result<Recorder, Error> {
+        fs::create_dir_all(dir).map_err(|e| recording_error(dir, e))?;
+
+        /* keep numbering after earlier sessions, e.g. after a re-login */
+        let count = recordings(dir)?.iter().filter_map(|p| number(p)).max().unwrap_or(0);
+        Ok(Recorder {
+            dir: PathBuf::from(dir),
+            count: Mutex::new(count),
+        })
+    }
+
+    /* Other recorders may write to the same directory, e.g. one per NAS with
+     * --all, so a number someone else took is skipped instead of overwritten. */
+    pub fn save(&self, url: &Url, res: &str) -> Result<(), Error> {
+        let mut count = self.count.lock().unwrap_or_else(|e| e.into_inner());
+
+        let exchange = Exchange {
+            request: redact_request(url),
+            response: redact_response(res),
+        };
+        let json = serde_json::to_string_pretty(&exchange)?;
+        loop {
+            *count += 1;
+            let path = self.dir.join(format!("{:04}-{}.json", count, key(url)));
+            let file = OpenOptions::new().write(true).create_new(true).open(&path);
+            match file {
+                Ok(mut f) => {
+                    return f
+                        .write_all(json.as_bytes())
+                        .map_err(|e| recording_error(&path, e))
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(recording_error(&path, e)),
+            }
+        }
+    }
+}
+
+pub struct Replayer {
+    responses: Mutex<HashMap<String, VecDeque<String>>>,
+}
+
+impl Replayer {
+    pub fn load(dir: &Path) -> Result<Replayer, Error> {
+        /* 10000 comes after 9999, not after 1000 */
+        let mut files = recordings(dir)?;
+        files.sort_by_key(|p| (number(p), p.clone()));
+
+        let mut responses: HashMap<String, VecDeque<String>> = HashMap::new();
+        for path in files {
+            let content = fs::read_to_string(&path).map_err(|e| recording_error(&path, e))?;
+            let exchange = serde_json::from_str::<Exchange>(&content)
+                .map_err(|e| recording_error(&path, e))?;
+            let url = Url::parse("http://localhost")
+                .and_then(|base| base.join(&exchange.request))
+                .map_err(|e| recording_error(&path, e))?;
+            responses.entry(key(&url)).or_default().push_back(exchange.response);
+        }
+
+        Ok(Replayer {
+            responses: Mutex::new(responses),
+        })
+    }
+
+    /* Answers requests in the order they were recorded; once only one
+     * response is left for a request it is repeated for ever after. */
+    pub fn answer(&self, url: &Url) -> Result<String, Error> {
+        let mut responses = self.responses.lock().unwrap_or_else(|e| e.into_inner());
+        let key = key(url);
+        match responses.get_mut(&key) {
+            Some(queue) if queue.len() > 1 => Ok(queue.pop_front().unwrap_or_default()),
+            Some(queue) if queue.len() == 1 => Ok(queue[0].clone()),
+            _ => Err(Error::Recording(format!("No recorded response for {}", key))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_password_and_session() {
+        let url = Url::parse("https://nas:5001/webapi/auth.cgi?api=SYNO.API.Auth&account=admin&passwd=secret&_sid=abc").unwrap();
+        assert_eq!(redact_request(&url),
+                   "/webapi/auth.cgi?api=SYNO.API.Auth&account=admin&passwd=REDACTED&_sid=REDACTED");
+    }
+
+    #[test]
+    fn redact_session_in_response() {
+        let res = r#"{"data":{"sid":"abc123"},"success":true}"#;
+        assert_eq!(redact_response(res), r#"{"data":{"sid":"REDACTED"},"success":true}"#);
+
+        let res = r#"{"data":{"tasks":[]},"success":true}"#;
+        assert_eq!(redact_response(res), res);
+    }
+
+    #[test]
+    fn replay_in_order_and_repeat_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = Recorder::new(dir.path()).unwrap();
+        let list = Url::parse("http://nas/webapi/entry.cgi?api=SYNO.DownloadStation2.Task&method=list").unwrap();
+        let pause = Url::parse("http://nas/webapi/entry.cgi?api=SYNO.DownloadStation2.Task&method=pause").unwrap();
+        recorder.save(&list, "first").unwrap();
+        recorder.save(&pause, "paused").unwrap();
+        recorder.save(&list, "second").unwrap();
+
+        /* a second session in the same directory adds to the recording */
+        let recorder = Recorder::new(dir.path()).unwrap();
+        recorder.save(&list, "third").unwrap();
+
+        let replayer = Replayer::load(dir.path()).unwrap();
+        assert_eq!(replayer.answer(&list).unwrap(), "first");
+        assert_eq!(replayer.answer(&list).unwrap(), "second");
+        assert_eq!(replayer.answer(&list).unwrap(), "third");
+        assert_eq!(replayer.answer(&list).unwrap(), "third");
+        assert_eq!(replayer.answer(&pause).unwrap(), "paused");
+
+        let delete = Url::parse("http://nas/webapi/entry.cgi?api=SYNO.DownloadStation2.Task&method=delete").unwrap();
+        assert!(replayer.answer(&delete).is_err());
+    }
+
+    #[test]
+    fn recorders_share_a_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = Recorder::new(dir.path()).unwrap();
+        let office = Recorder::new(dir.path()).unwrap();
+        let list = Url::parse("http://nas/webapi/entry.cgi?api=SYNO.DownloadStation2.Task&method=list").unwrap();
+        home.save(&list, "home").unwrap();
+        office.save(&list, "office").unwrap();
+        home.save(&list, "home again").unwrap();
+
+        let replayer = Replayer::load(dir.path()).unwrap();
+        assert_eq!(replayer.answer(&list).unwrap(), "home");
+        assert_eq!(replayer.answer(&list).unwrap(), "office");
+        assert_eq!(replayer.answer(&list).unwrap(), "home again");
+    }
+
+    #[test]
+    fn replay_past_9999() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = Recorder::new(dir.path()).unwrap();
+        *recorder.count.lock().unwrap() = 9998;
+        let list = Url::parse("http://nas/webapi/entry.cgi?api=SYNO.DownloadStation2.Task&method=list").unwrap();
+        recorder.save(&list, "9999").unwrap();
+        recorder.save(&list, "10000").unwrap();
+        recorder.save(&list, "10001").unwrap();
+
+        let replayer = Replayer::load(dir.path()).unwrap();
+        assert_eq!(replayer.answer(&list).unwrap(), "9999");
+        assert_eq!(replayer.answer(&list).unwrap(), "10000");
+        assert_eq!(replayer.answer(&list).unwrap(), "10001");
+    }
+}
@@ -0,0 +1,345 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{convert::TryFrom, ops::Range};
+
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use url::Url;
+
+/* lists and dictionaries nested deeper than this are not a torrent */
+const MAX_DEPTH: usize = 64;
+
+/* a bencoded value, strings are left as bytes */
+#[derive(Debug, PartialEq)]
+enum Value<'a> {
+    Int(i64),
+    Bytes(&'a [u8]),
+    List(Vec<Value<'a>>),
+    Dict(Vec<(&'a [u8], Value<'a>)>)
+}
+
+impl<'a> Value<'a> {
+    fn get(&self, key: &str) -> Option<&Value<'a>> {
+        match self {
+            Value::Dict(entries) => entries.iter()
+                .find(|(k, _)| *k == key.as_bytes())
+                .map(|(_, v)| v),
+            _ => None
+        }
+    }
+
+    fn int(&self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            _ => None
+        }
+    }
+
+    fn text(&self) -> Option<String> {
+        match self {
+            Value::Bytes(b) => Some(String::from_utf8_lossy(b).into_owned()),
+            _ => None
+        }
+    }
+
+    fn list(&self) -> &[Value<'a>] {
+        match self {
+            Value::List(items) => items,
+            _ => &[]
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Decoder<'a> {
+    fn peek(&self) -> Result<u8, String> {
+        self.data.get(self.pos).copied().ok_or_else(|| String::from("unexpected end of file"))
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        match self.peek()? {
+            b if b == c => {
+                self.pos += 1;
+                Ok(())
+            },
+            b => Err(format!("expected '{}' at byte {}, found '{}'", c as char, self.pos,
+                             b as char))
+        }
+    }
+
+    /* digits up to the given terminator */
+    fn number(&mut self, end: u8) -> Result<i64, String> {
+        let start = self.pos;
+        while self.peek()? != end {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.data[start..self.pos]).unwrap_or_default();
+        self.pos += 1;
+        text.parse().map_err(|_| format!("invalid number at byte {}", start))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let start = self.pos;
+        let len = self.number(b':')?;
+        let end = usize::try_from(len).ok()
+            .and_then(|len| self.pos.checked_add(len))
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| format!("invalid string length at byte {}", start))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value<'a>, String> {
+        if depth > MAX_DEPTH {
+            return Err(String::from("nested too deeply"));
+        }
+        match self.peek()? {
+            b'i' => {
+                self.pos += 1;
+                Ok(Value::Int(self.number(b'e')?))
+            },
+            b'l' => {
+                self.pos += 1;
+                let mut items = vec![];
+                while self.peek()? != b'e' {
+                    items.push(self.value(depth + 1)?);
+                }
+                self.pos += 1;
+                Ok(Value::List(items))
+            },
+            b'd' => {
+                let (entries, _) = self.dict(depth, "")?;
+                Ok(Value::Dict(entries))
+            },
+            b'0'..=b'9' => Ok(Value::Bytes(self.bytes()?)),
+            b => Err(format!("unexpected '{}' at byte {}", b as char, self.pos))
+        }
+    }
+
+    /* a dictionary, along with where the value of the given key starts and ends */
+    #[allow(clippy::type_complexity)]
+    fn dict(&mut self, depth: usize, key: &str)
+            -> Result<(Vec<(&'a [u8], Value<'a>)>, Option<Range<usize>>), String> {
+        self.expect(b'd')?;
+        let mut entries = vec![];
+        let mut span = None;
+        while self.peek()? != b'e' {
+            let k = self.bytes()?;
+            let start = self.pos;
+            let v = self.value(depth + 1)?;
+            if k == key.as_bytes() {
+                span = Some(start..self.pos);
+            }
+            entries.push((k, v));
+        }
+        self.pos += 1;
+        Ok((entries, span))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/* RFC 4648 base32, as used by old magnet links */
+fn base32(text: &str) -> Option<Vec<u8>> {
+    let mut bits: u64 = 0;
+    let mut n = 0;
+    let mut out = vec![];
+    for c in text.bytes() {
+        let v = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None
+        };
+        bits = (bits << 5) | v as u64;
+        n += 5;
+        if n >= 8 {
+            n -= 8;
+            out.push((bits >> n) as u8);
+        }
+    }
+    Some(out)
+}
+
+/* one of the files a torrent downloads */
+#[derive(Debug, PartialEq)]
+pub struct File {
+    /* relative to the download directory, with / between directories */
+    pub path: String,
+    pub length: u64
+}
+
+/* what synodl needs to know about a .torrent file */
+#[derive(Debug, PartialEq)]
+pub struct Torrent {
+    pub name: String,
+    /* total size of all files in bytes, 0 if a magnet link does not say */
+    pub length: u64,
+    /* SHA-1 of the info dictionary, in lower case hex */
+    pub info_hash: String,
+    /* empty for magnet links, the files are only known once the peers tell */
+    pub files: Vec<File>
+}
+
+fn length(v: &Value) -> u64 {
+    v.get("length").and_then(Value::int).unwrap_or_default().max(0) as u64
+}
+
+impl Torrent {
+    pub fn parse(data: &[u8]) -> Result<Torrent, String> {
+        let mut decoder = Decoder { data, pos: 0 };
+        let (entries, span) = decoder.dict(0, "info")
+            .map_err(|e| format!("Not a torrent file: {}", e))?;
+        let span = span.ok_or("Not a torrent file: no info dictionary")?;
+        let info = entries.into_iter()
+            .find(|(k, _)| *k == b"info")
+            .map(|(_, v)| v)
+            .filter(|v| matches!(v, Value::Dict(_)))
+            .ok_or("Not a torrent file: no info dictionary")?;
+
+        let name = info.get("name.utf-8").or_else(|| info.get("name"))
+            .and_then(Value::text)
+            .unwrap_or_default();
+        let files: Vec<File> = match info.get("files") {
+            None => vec![File { path: name.clone(), length: length(&info) }],
+            Some(files) => files.list().iter().map(|f| {
+                let parts = f.get("path.utf-8").or_else(|| f.get("path"))
+                    .map(Value::list).unwrap_or_default();
+                let parts: Vec<String> = parts.iter().filter_map(Value::text).collect();
+                File { path: parts.join("/"), length: length(f) }
+            }).collect()
+        };
+
+        Ok(Torrent {
+            name,
+            length: files.iter().map(|f| f.length).fold(0, u64::saturating_add),
+            info_hash: hex(digest(&SHA1_FOR_LEGACY_USE_ONLY, &data[span]).as_ref()),
+            files
+        })
+    }
+
+    /* as much as a magnet link tells: the hash, and perhaps name and size */
+    pub fn from_magnet(uri: &str) -> Option<Torrent> {
+        let info_hash = magnet_hash(uri)?;
+        let url = Url::parse(uri).ok()?;
+        let param = |key: &str| url.query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.into_owned());
+
+        Some(Torrent {
+            name: param("dn").unwrap_or_default(),
+            length: param("xl").and_then(|n| n.parse().ok()).unwrap_or_default(),
+            info_hash,
+            files: vec![]
+        })
+    }
+}
+
+/* the BitTorrent info-hash of a magnet link, in lower case hex */
+pub fn magnet_hash(uri: &str) -> Option<String> {
+    let url = Url::parse(uri).ok().filter(|u| u.scheme() == "magnet")?;
+    let hash = url.query_pairs()
+        .filter(|(k, _)| k == "xt")
+        .find_map(|(_, v)| v.strip_prefix("urn:btih:").map(String::from))?;
+    match hash.len() {
+        40 if hash.chars().all(|c| c.is_ascii_hexdigit()) => Some(hash.to_ascii_lowercase()),
+        32 => base32(&hash).map(|b| hex(&b)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINGLE: &[u8] = b"d8:announce14:http://tracker4:infod6:lengthi5e4:name5:a.iso\
+                            12:piece lengthi16384e6:pieces0:ee";
+
+    #[test]
+    fn single_file() {
+        assert_eq!(Torrent::parse(SINGLE).unwrap(), Torrent {
+            name: String::from("a.iso"),
+            length: 5,
+            info_hash: String::from("c1599dd742faa9d633b4fb96d49c5abb02798eef"),
+            files: vec![File { path: String::from("a.iso"), length: 5 }]
+        });
+    }
+
+    #[test]
+    fn several_files() {
+        let data = b"d4:infod5:filesld6:lengthi3e4:pathl1:aeed6:lengthi4e4:pathl3:sub1:beee\
+                     4:name3:diree";
+        let t = Torrent::parse(data).unwrap();
+        assert_eq!((t.name.as_str(), t.length), ("dir", 7));
+        assert_eq!(t.info_hash, "250b5c8167aee8a605d3d013b21bc5f7b33f8ba4");
+        assert_eq!(t.files, [File { path: String::from("a"), length: 3 },
+                             File { path: String::from("sub/b"), length: 4 }]);
+    }
+
+    #[test]
+    fn broken_files() {
+        assert!(Torrent::parse(b"").is_err());
+        assert!(Torrent::parse(b"<html>").is_err());
+        assert!(Torrent::parse(b"d4:infoi5ee").is_err());
+        assert!(Torrent::parse(b"d4:infod4:name99:xee").is_err());
+        assert!(Torrent::parse(b"d8:announce3:fooe").is_err());
+        assert!(Torrent::parse(&[b'l'; 1000]).is_err());
+
+        /* sizes that add up to more than fits */
+        let file = "d6:lengthi9223372036854775807e4:pathl1:aee";
+        let huge = format!("d4:infod5:filesl{0}{0}{0}e4:name1:x12:piece lengthi1e6:pieces0:ee",
+                           file);
+        assert_eq!(Torrent::parse(huge.as_bytes()).unwrap().length, u64::MAX);
+    }
+
+    #[test]
+    fn magnet_links() {
+        let hash = "c1599dd742faa9d633b4fb96d49c5abb02798eef";
+        assert_eq!(magnet_hash("magnet:?xt=urn:btih:C1599DD742FAA9D633B4FB96D49C5ABB02798EEF&dn=a")
+                       .unwrap(), hash);
+        assert_eq!(magnet_hash("magnet:?dn=a&xt=urn:btih:YFMZ3V2C7KU5MM5U7OLNJHC2XMBHTDXP")
+                       .unwrap(), hash);
+        assert_eq!(magnet_hash("magnet:?xt=urn:btih:1234"), None);
+        assert_eq!(magnet_hash("https://example.com/?xt=urn:btih:c1599dd742faa9d633b4fb96d49c5abb02798eef"),
+                   None);
+    }
+
+    #[test]
+    fn torrent_from_magnet() {
+        let t = Torrent::from_magnet("magnet:?xt=urn:btih:YFMZ3V2C7KU5MM5U7OLNJHC2XMBHTDXP\
+                                      &dn=Debian%2012&xl=658505728&tr=udp%3A%2F%2Ft").unwrap();
+        assert_eq!(t, Torrent {
+            name: String::from("Debian 12"),
+            length: 658505728,
+            info_hash: String::from("c1599dd742faa9d633b4fb96d49c5abb02798eef"),
+            files: vec![]
+        });
+
+        let t = Torrent::from_magnet("magnet:?xt=urn:btih:c1599dd742faa9d633b4fb96d49c5abb02798eef")
+            .unwrap();
+        assert_eq!((t.name.as_str(), t.length), ("", 0));
+        assert_eq!(Torrent::from_magnet("magnet:?dn=a"), None);
+    }
+}
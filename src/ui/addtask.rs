@@ -22,17 +22,23 @@ use tui::{
     style::{Color, Style},
     widgets::{Block, Borders, Clear, Paragraph, Wrap},
     text::{Span, Spans},
-    layout::{Alignment, Margin},
+    layout::{Alignment, Constraint, Layout, Margin},
     Frame
 };
 use crate::{
+    preview::describe,
     ui::util::{centered_rect_relative},
     App
 };
 
 
 pub fn add_task<B: Backend>(f: &mut Frame<B>, app: &App) {
-    let area = centered_rect_relative(80, 50, f.size());
+    /* taller while there is a preview to show below the URL */
+    let height = match app.preview {
+        Some(_) => 70,
+        None => 50
+    };
+    let area = centered_rect_relative(80, height, f.size());
     let window = Block::default()
         .title("Add download task")
         .title_alignment(Alignment::Center)
@@ -42,10 +48,16 @@ pub fn add_task<B: Backend>(f: &mut Frame<B>, app: &App) {
     f.render_widget(Clear, area);
     f.render_widget(window, area);
 
+    let rects = Layout::default()
+        .constraints([Constraint::Length(1),
+                      Constraint::Length(3),
+                      Constraint::Length(1),
+                      Constraint::Min(0)].as_ref())
+        .split(area.inner(&Margin{vertical: 1, horizontal: 2}));
+
     let prompt = Block::default()
-        .title("Enter URL:");
-    let inner = area.inner(&Margin{vertical: 1, horizontal: 2});
-    f.render_widget(prompt, inner);
+        .title("Enter URL or path of a torrent file:");
+    f.render_widget(prompt, rects[0]);
 
     let text = Spans::from(vec![
         Span::raw(&app.input),
@@ -55,6 +67,14 @@ pub fn add_task<B: Backend>(f: &mut Frame<B>, app: &App) {
     let textarea = Paragraph::new(text)
         .style(Style::default().bg(Color::Cyan).fg(Color::Black))
         .wrap(Wrap { trim: true });
-    let area = inner.inner(&Margin{vertical: 1, horizontal: 0});
-    f.render_widget(textarea, area);
+    f.render_widget(textarea, rects[1]);
+
+    /* only magnet links and torrent files can be looked at before adding */
+    let lines = match &app.preview {
+        Some(Ok(torrent)) => describe(torrent),
+        Some(Err(e)) => vec![e.clone()],
+        None => vec![]
+    };
+    let preview: Vec<Spans> = lines.into_iter().map(Spans::from).collect();
+    f.render_widget(Paragraph::new(preview), rects[3]);
 }
@@ -25,18 +25,11 @@ use tui::{
     Frame
 };
 use crate::{
-    ui::util::{centered_rect_abs},
+    ui::util::{centered_rect_abs, highlight},
     App
 };
 
 
-fn highlight(b: bool) -> Style {
-    match b {
-        true => Style::default(),
-        false => Style::default().fg(Color::Black).bg(Color::White)
-    }
-}
-
 /* minimal size: 21x5 */
 pub fn ask_delete<B: Backend>(f: &mut Frame<B>, app: &App) {
     let area = centered_rect_abs(21, 5, f.size());
@@ -0,0 +1,70 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+use tui::{
+    backend::Backend,
+    style::{Color, Style},
+    widgets::{Block, Borders, Clear, Paragraph},
+    layout::{Layout, Constraint, Alignment, Margin, Direction},
+    Frame
+};
+use crate::{
+    ui::util::{centered_rect_abs, highlight},
+    App
+};
+
+
+/* minimal size: 24x6 */
+pub fn ask_duplicate<B: Backend>(f: &mut Frame<B>, app: &App) {
+    let title = app.ui.duplicate_of.as_deref().unwrap_or_default();
+    let width = title.chars().count().clamp(20, 60) as u16 + 4;
+    let area = centered_rect_abs(width, 6, f.size());
+    let window = Block::default()
+        .title("Duplicate")
+        .title_alignment(Alignment::Center)
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Red).fg(Color::White));
+
+    f.render_widget(Clear, area);
+    f.render_widget(window, area);
+
+    let rects = Layout::default()
+        .constraints([Constraint::Length(1),
+                      Constraint::Length(1),
+                      Constraint::Length(1),
+                      Constraint::Length(1)].as_ref())
+        .split(area.inner(&Margin{vertical: 1, horizontal: 2}));
+
+    f.render_widget(Paragraph::new("Added before as"), rects[0]);
+    f.render_widget(Paragraph::new(title), rects[1]);
+
+    let selection = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(6),
+                Constraint::Length(2),
+                Constraint::Length(16)].as_ref())
+        .split(rects[3].inner(&Margin{vertical: 0, horizontal: 1 }));
+
+    let no = Paragraph::new("[ No ]")
+        .style(highlight(app.ui.duplicate_yes_selected));
+    let yes = Paragraph::new("[ Add anyway ]")
+        .style(highlight(!app.ui.duplicate_yes_selected));
+
+    f.render_widget(no, selection[0]);
+    f.render_widget(yes, selection[2]);
+}
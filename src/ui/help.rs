@@ -35,13 +35,16 @@ pub fn show_help<B: Backend>(f: &mut Frame<B>) {
         make_row("A", String::from("Add download task")),
         make_row("D", String::from("Delete selected task")),
         make_row("I", String::from("Show task details")),
+        make_row("L", String::from("Speed limits")),
+        make_row("P", String::from("Pause/resume task")),
         make_row("Q", String::from("Quit")),
-        make_row("R", String::from("Refresh list"))
+        make_row("R", String::from("Refresh list")),
+        make_row("S", String::from("Switch NAS profile"))
     ];
 
     let text = vec![
         Spans::from(Span::raw(format!("This is synodl {}", VERSION))),
-        Spans::from(Span::raw(format!("{}", URL)))
+        Spans::from(Span::raw(URL))
     ];
 
     let area = centered_rect_abs(33, items.len() as u16 + 7, f.size());
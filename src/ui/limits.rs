@@ -0,0 +1,83 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+use tui::{
+    backend::Backend,
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Cell, Clear, List, ListItem, ListState, Row, Table},
+    layout::{Alignment, Constraint, Layout, Margin},
+    Frame
+};
+use crate::{
+    ui::util::{centered_rect_abs, limit_text},
+    App
+};
+
+/* name, download and upload limit in KB/s */
+pub const PRESETS: [(&str, u64, u64); 4] = [
+    ("Unlimited", 0, 0),
+    ("Fast", 10000, 2000),
+    ("Medium", 2000, 500),
+    ("Slow", 500, 100)
+];
+
+/* minimal size: 44x11 */
+pub fn show_limits<B: Backend>(f: &mut Frame<B>, app: &App) {
+    let area = centered_rect_abs(44, 11, f.size());
+    let title = match app.profiles.get(app.profile) {
+        Some(name) if app.profiles.len() > 1 => format!("Speed limits of {}", name),
+        _ => String::from("Speed limits")
+    };
+    let window = Block::default()
+        .title(title)
+        .title_alignment(Alignment::Center)
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Blue).fg(Color::White));
+
+    f.render_widget(Clear, area);
+    f.render_widget(window, area);
+
+    let rects = Layout::default()
+        .constraints([Constraint::Length(4),
+                      Constraint::Length(1),
+                      Constraint::Length(PRESETS.len() as u16)].as_ref())
+        .split(area.inner(&Margin{vertical: 1, horizontal: 2}));
+
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let limits = &app.limits;
+    let current = Table::new([
+        Row::new([Cell::from("BitTorrent"), Cell::from(limit_text(limits.bt_download)),
+                  Cell::from(limit_text(limits.bt_upload))]),
+        Row::new([Cell::from("HTTP"), Cell::from(limit_text(limits.http_download))]),
+        Row::new([Cell::from("FTP"), Cell::from(limit_text(limits.ftp_download))])
+    ])
+        .header(Row::new(["", "Download", "Upload"]).style(bold))
+        .widths(&[Constraint::Length(12), Constraint::Length(12), Constraint::Length(12)]);
+    f.render_widget(current, rects[0]);
+
+    /* in the columns of the table above */
+    let items: Vec<ListItem> = PRESETS.iter().map(|(name, down, up)| {
+        ListItem::new(format!("{:<13}{:<13}{}", name, limit_text(*down), limit_text(*up)))
+    }).collect();
+    let presets = List::new(items)
+        .highlight_style(Style::default().fg(Color::Black).bg(Color::White));
+
+    let mut state = ListState::default();
+    state.select(Some(app.ui.preset_selected));
+    f.render_stateful_widget(presets, rects[2], &mut state);
+}
@@ -19,9 +19,12 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 mod addtask;
 mod delete;
+mod duplicate;
 mod help;
+pub mod limits;
+mod profiles;
 mod taskdetails;
-mod util;
+pub mod util;
 mod widgets;
 
 use tui::{
@@ -33,10 +36,13 @@ use tui::{
 };
 use crate::{
     ui::{help::show_help, taskdetails::show_details, addtask::add_task,
-         delete::ask_delete, util::{speed_text, size_text},
-         widgets::show_error},
-    App, Task, Config, Activity
+         delete::ask_delete, duplicate::ask_duplicate, limits::show_limits,
+         profiles::select_profile, util::{speed_text, size_text}, widgets::show_error},
+    config::Config,
+    backend::owner,
+    App, Activity
 };
+use synodl::syno::Task;
 
 
 struct Summary {
@@ -45,7 +51,7 @@ struct Summary {
 }
 
 impl Summary {
-    fn new(t: &Vec<Task>) -> Summary {
+    fn new(t: &[Task]) -> Summary {
         Summary {
             speed_download: t.iter().fold(0, |acc, e| acc + e.speed_download),
             speed_upload: t.iter().fold(0, |acc, e| acc + e.speed_upload)
@@ -85,28 +91,41 @@ fn show_tasks<B: Backend>(f: &mut Frame<B>, app: &mut App, cfg: &Config) {
         .constraints([Constraint::Min(10), Constraint::Length(1)].as_ref())
         .split(size);
 
-    let header_cells = ["Download task", "Size", "Status", "Prog"]
+    /* the NAS column is only there when tasks come from several devices */
+    let nas_width = match app.aggregate {
+        true => app.profiles.iter().map(|p| p.len()).max().unwrap_or(0).max(3) + 1,
+        false => 0
+    };
+
+    let header_cells = ["NAS", "Download task", "Size", "Status", "Prog"]
         .iter()
+        .skip(if app.aggregate { 0 } else { 1 })
         .map(|h| Cell::from(*h).style(Style::default()));
     let header = Row::new(header_cells)
         .style(Style::default().bg(Color::White).fg(Color::Black));
     let rows = app.tasks.iter().map(|item| {
-        let cells = [
+        let mut cells = vec![
             Cell::from(item.title.as_str()),
             Cell::from(size_text(item.size)),
             Cell::from(item.status.as_str())
                 .style(Style::default().fg(status_color(&item.status))),
             Cell::from(format!("{0:.0}%", 100.0 * item.percent_dn))
         ];
+        if app.aggregate {
+            cells.insert(0, Cell::from(owner(item).unwrap_or_default()));
+        }
         Row::new(cells)
     });
 
-    let columns = [
-        Constraint::Length(size.width - 30),
+    let mut columns = vec![
+        Constraint::Length(size.width.saturating_sub(30 + nas_width as u16)),
         Constraint::Length(10),
         Constraint::Length(11),
         Constraint::Length(4),
     ];
+    if app.aggregate {
+        columns.insert(0, Constraint::Length(nas_width as u16 - 1));
+    }
 
     let tasks = Table::new(rows)
         .header(header)
@@ -117,16 +136,17 @@ fn show_tasks<B: Backend>(f: &mut Frame<B>, app: &mut App, cfg: &Config) {
     let status_row = Block::default()
         .style(Style::default().bg(Color::Blue).fg(Color::White));
 
+    let text = status_text(app, cfg);
     let status_left = Block::default()
-        .title(format!(" {}", status_text(&app, cfg)));
+        .title(format!(" {}", &text));
 
-    let st = status_traffic(&app);
+    let st = status_traffic(app);
     let status_right = Block::default()
         .title(format!("{} ", &st));
 
     let parts = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Length((cfg.url.len() + 2) as u16),
+        .constraints([Constraint::Length((text.len() + 2) as u16),
                       Constraint::Min(1),
                       Constraint::Length((st.len() + 1) as u16)].as_ref())
         .split(rects[1]);
@@ -158,11 +178,23 @@ pub fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App, config: &Config) {
         show_details(f, app);
     }
 
+    if app.ui.select_profile {
+        select_profile(f, app);
+    }
+
+    if app.ui.show_limits {
+        show_limits(f, app);
+    }
+
     if app.ui.ask_for_task_url {
         add_task(f, app);
     }
 
-    if app.error.len() > 0 {
+    if app.ui.duplicate_of.is_some() {
+        ask_duplicate(f, app);
+    }
+
+    if !app.error.is_empty() {
         show_error(f, app);
         app.error = String::from("");
     }
@@ -173,6 +205,11 @@ fn status_text(app: &App, cfg: &Config) -> String {
         Activity::Quit => String::from("Quitting ..."),
         Activity::Refresh => String::from("Refreshing ..."),
         Activity::Submit => String::from("Adding task ..."),
+        Activity::Switch => String::from("Switching NAS ..."),
+        _ if app.aggregate => format!("{} DiskStations", app.profiles.len()),
+        _ if app.profiles.len() > 1 => {
+            format!("{}: {}", app.profiles[app.profile], cfg.url)
+        },
         _ => String::from(&cfg.url)
     }
 }
@@ -204,9 +241,7 @@ mod tests {
         let cfg = Config {
             user: String::from(""),
             url: String::from("http://foo/"),
-            password: None,
-            password_command: None,
-            cacert: None
+            ..Config::default()
         };
 
         assert_eq!(status_text(&app, &cfg), "Quitting ...");
@@ -220,9 +255,7 @@ mod tests {
         let cfg = Config {
             user: String::from(""),
             url: String::from("http://foo/"),
-            password: None,
-            password_command: None,
-            cacert: None
+            ..Config::default()
         };
 
         assert_eq!(status_text(&app, &cfg), "Adding task ...");
@@ -236,9 +269,7 @@ mod tests {
         let cfg = Config {
             user: String::from(""),
             url: String::from("http://foo/"),
-            password: None,
-            password_command: None,
-            cacert: None
+            ..Config::default()
         };
 
         assert_eq!(status_text(&app, &cfg), "Refreshing ...");
@@ -252,14 +283,43 @@ mod tests {
         let cfg = Config {
             user: String::from(""),
             url: String::from("http://foo/"),
-            password: None,
-            password_command: None,
-            cacert: None
+            ..Config::default()
         };
 
         assert_eq!(status_text(&app, &cfg), "http://foo/");
     }
 
+    #[test]
+    fn status_line_shows_profile() {
+        let mut app = App::with_profiles(vec![String::from("home"),
+                                              String::from("office")], 1);
+        app.activity = Activity::Idle;
+
+        let cfg = Config {
+            user: String::from(""),
+            url: String::from("http://foo/"),
+            ..Config::default()
+        };
+
+        assert_eq!(status_text(&app, &cfg), "office: http://foo/");
+    }
+
+    #[test]
+    fn status_line_all_profiles() {
+        let mut app = App::with_profiles(vec![String::from("home"),
+                                              String::from("office")], 1);
+        app.activity = Activity::Idle;
+        app.aggregate = true;
+
+        let cfg = Config {
+            user: String::from(""),
+            url: String::from("http://foo/"),
+            ..Config::default()
+        };
+
+        assert_eq!(status_text(&app, &cfg), "2 DiskStations");
+    }
+
     #[test]
     fn sumary_single_finished_task() {
         let tasks = vec![
@@ -270,10 +330,9 @@ mod tests {
                 size: 1024000,
                 size_downloaded: 1024000,
                 size_uploaded: 1024000,
-                speed_download: 0,
-                speed_upload: 0,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             }
         ];
 
@@ -293,9 +352,9 @@ mod tests {
                 size_downloaded: 1024000,
                 size_uploaded: 1024000,
                 speed_download: 1234,
-                speed_upload: 0,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             }
         ];
 
@@ -314,10 +373,10 @@ mod tests {
                 size: 1024000,
                 size_downloaded: 1024000,
                 size_uploaded: 1024000,
-                speed_download: 0,
                 speed_upload: 2345,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             }
         ];
 
@@ -339,7 +398,8 @@ mod tests {
                 speed_download: 1234,
                 speed_upload: 2345,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             }
         ];
 
@@ -370,7 +430,8 @@ mod tests {
                 speed_download: 100,
                 speed_upload: 200,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             },
             Task {
                 id: String::from("uuid01"),
@@ -380,9 +441,9 @@ mod tests {
                 size_downloaded: 1024000,
                 size_uploaded: 1024000,
                 speed_download: 50,
-                speed_upload: 0,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             },
             Task {
                 id: String::from("uuid01"),
@@ -391,10 +452,10 @@ mod tests {
                 size: 1024000,
                 size_downloaded: 1024000,
                 size_uploaded: 1024000,
-                speed_download: 0,
                 speed_upload: 25,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             },
             Task {
                 id: String::from("uuid01"),
@@ -403,10 +464,9 @@ mod tests {
                 size: 1024000,
                 size_downloaded: 1024000,
                 size_uploaded: 1024000,
-                speed_download: 0,
-                speed_upload: 0,
                 percent_dn: 1.00,
-                percent_up: 1.00
+                percent_up: 1.00,
+                ..Task::default()
             }
         ];
 
@@ -0,0 +1,59 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use tui::{
+    backend::Backend,
+    style::{Color, Style},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState},
+    layout::{Alignment, Margin},
+    Frame
+};
+use crate::{
+    ui::util::{centered_rect_abs},
+    App
+};
+
+
+pub fn select_profile<B: Backend>(f: &mut Frame<B>, app: &App) {
+    let width = app.profiles.iter().map(|p| p.len()).max().unwrap_or(0) + 8;
+    let area = centered_rect_abs(width.max(21) as u16,
+                                 app.profiles.len() as u16 + 2, f.size());
+    let window = Block::default()
+        .title("Switch NAS")
+        .title_alignment(Alignment::Center)
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Blue).fg(Color::White));
+
+    f.render_widget(Clear, area);
+    f.render_widget(window, area);
+
+    let items: Vec<ListItem> = app.profiles.iter().enumerate().map(|(i, p)| {
+        let marker = if i == app.profile { "*" } else { " " };
+        ListItem::new(format!("{} {}", marker, p))
+    }).collect();
+
+    let list = List::new(items)
+        .highlight_style(Style::default().fg(Color::Black).bg(Color::White));
+
+    let mut state = ListState::default();
+    state.select(Some(app.ui.profile_selected));
+
+    let inner = area.inner(&Margin{vertical: 1, horizontal: 2});
+    f.render_stateful_widget(list, inner, &mut state);
+}
@@ -26,11 +26,12 @@ use tui::{
 };
 use crate::{
     ui::util::{centered_rect_abs, make_row, speed_text, size_text},
-    App, Task
+    App
 };
+use synodl::syno::Task;
 
 
-fn make_table(task: &Task) -> Table {
+fn make_table(task: &Task) -> Table<'_> {
     let downloaded = format!("{} ({1:.2})",
                             size_text(task.size_downloaded), task.percent_dn);
     let uploaded = format!("{} ({1:.2})",
@@ -64,12 +65,9 @@ pub fn show_details<B: Backend>(f: &mut Frame<B>, app: &App) {
     f.render_widget(Clear, area);
     f.render_widget(window, area);
 
-    match app.state.selected() {
-        Some(i) => {
-            let table = make_table(&app.tasks[i]);
-            let m = Margin { vertical: 2, horizontal: 3 };
-            f.render_widget(table, area.inner(&m));
-        },
-        None => { }
-    };
+    if let Some(i) = app.state.selected() {
+        let table = make_table(&app.tasks[i]);
+        let m = Margin { vertical: 2, horizontal: 3 };
+        f.render_widget(table, area.inner(&m));
+    }
 }
@@ -18,12 +18,12 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
 use tui::{
-    style::{Modifier, Style},
+    style::{Color, Modifier, Style},
     widgets::{Cell, Row},
     layout::{Layout, Constraint, Direction, Rect}
 };
 use std::cmp::min;
-use byte_unit::Byte;
+use byte_unit::{Byte, UnitType};
 
 // TODO: extract functions to get coordinates
 
@@ -76,7 +76,14 @@ pub fn centered_rect_abs(mut width: u16, mut height: u16, r: Rect) -> Rect {
         ).split(popup_layout[1])[1]
 }
 
-pub fn make_row(label: &str, value: String) -> Row {
+pub fn highlight(b: bool) -> Style {
+    match b {
+        true => Style::default(),
+        false => Style::default().fg(Color::Black).bg(Color::White)
+    }
+}
+
+pub fn make_row(label: &str, value: String) -> Row<'_> {
     Row::new([
         Cell::from(label).style(Style::default().add_modifier(Modifier::BOLD)),
         Cell::from(value)
@@ -84,14 +91,27 @@ pub fn make_row(label: &str, value: String) -> Row {
 }
 
 pub fn size_text(n: u64) -> String {
-    let byte = Byte::from_u64(n);
-    byte.to_string()
+    match n {
+        0..=999 => format!("{} B", n),
+        _ => {
+            let byte = Byte::from_u64(n).get_appropriate_unit(UnitType::Decimal);
+            format!("{:.2}", byte)
+        }
+    }
 }
 
 pub fn speed_text(n: u64) -> String {
     size_text(n) + "/s"
 }
 
+/* DownloadStation takes its speed limits in KB/s */
+pub fn limit_text(n: u64) -> String {
+    match n {
+        0 => String::from("unlimited"),
+        _ => format!("{} KB/s", n)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -137,4 +157,11 @@ mod tests {
         let result = speed_text(50000000);
         assert_eq!(result, "50.00 MB/s");
     }
+
+    #[test]
+    fn limit_text_unlimited()
+    {
+        assert_eq!(limit_text(0), "unlimited");
+        assert_eq!(limit_text(500), "500 KB/s");
+    }
 }
@@ -0,0 +1,311 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{error, io, io::{IsTerminal, Write}, thread, time::{Duration, Instant}};
+
+use crossterm::{execute, terminal::{Clear, ClearType}};
+use synodl::syno::Task;
+
+use crate::{backend::{is_session_error, DownloadBackend}, ui::util::speed_text};
+
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/* DownloadStation creates tasks in the background, so they may take a moment to show up */
+const FIND_TRIES: usize = 5;
+
+const BAR_WIDTH: usize = 30;
+
+fn duration_text(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/* bar, percentage, speed, ETA and title of the tasks we wait for */
+fn progress_line(tasks: &[Task]) -> String {
+    let size: u64 = tasks.iter().map(|t| t.size).sum();
+    let done: u64 = tasks.iter().map(|t| t.size_downloaded.min(t.size)).sum();
+    let speed: u64 = tasks.iter().map(|t| t.speed_download).sum();
+
+    let fraction = match size {
+        0 => 0.0,
+        _ => done as f64 / size as f64
+    };
+    let filled = ((fraction * BAR_WIDTH as f64).round() as usize).min(BAR_WIDTH);
+    let eta = match speed {
+        0 => String::from("--"),
+        _ => duration_text((size - done) / speed)
+    };
+    let title = match tasks {
+        [task] => task.title.clone(),
+        _ => format!("{} tasks", tasks.len())
+    };
+
+    format!("[{}{}] {:5.1}% {:>12}  ETA {:>7}  {}", "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled), 100.0 * fraction, speed_text(speed), eta, title)
+}
+
+pub struct Waiter<'a> {
+    pub out: &'a mut dyn Write,
+    /* redraw the progress line in place instead of printing status changes */
+    pub live: bool,
+    pub interval: Duration,
+    pub timeout: Option<Duration>
+}
+
+impl Waiter<'_> {
+    fn timed_out(&self, start: Instant) -> bool {
+        self.timeout.is_some_and(|timeout| start.elapsed() >= timeout)
+    }
+
+    /* the task list; a session that ran out is renewed and other errors are
+       tried again until the timeout, a long wait should not end on a hiccup */
+    fn list<D, F>(&mut self, client: &mut D, connect: &mut F, start: Instant)
+            -> Result<Vec<Task>, Box<dyn error::Error>>
+            where D: DownloadBackend,
+                  F: FnMut() -> Result<D, Box<dyn error::Error>> {
+        loop {
+            let res = match client.list() {
+                Err(e) if is_session_error(e.as_ref()) => connect().and_then(|new| {
+                    *client = new;
+                    client.list()
+                }),
+                res => res
+            };
+            match res {
+                Ok(tasks) => return Ok(tasks),
+                Err(e) if self.timed_out(start) => return Err(e),
+                Err(e) => {
+                    if self.live {
+                        write!(self.out, "\r")?;
+                        execute!(&mut self.out, Clear(ClearType::UntilNewLine))?;
+                    }
+                    writeln!(self.out, "Cannot list tasks, trying again: {}", e)?;
+                    thread::sleep(self.interval);
+                }
+            }
+        }
+    }
+
+    /* the IDs of the tasks that were not there before */
+    fn find_new<D, F>(&mut self, client: &mut D, connect: &mut F, before: &[String],
+                      expected: usize, start: Instant)
+            -> Result<Vec<String>, Box<dyn error::Error>>
+            where D: DownloadBackend,
+                  F: FnMut() -> Result<D, Box<dyn error::Error>> {
+        let mut ids = vec![];
+        for i in 0..FIND_TRIES {
+            if i > 0 {
+                thread::sleep(self.interval);
+            }
+            ids = self.list(client, connect, start)?.into_iter()
+                .map(|t| t.id)
+                .filter(|id| !before.contains(id))
+                .collect();
+            if ids.len() >= expected {
+                break;
+            }
+        }
+
+        match ids.is_empty() {
+            true => Err(Box::new(io::Error::other("Cannot find the new task in the task list"))),
+            false => Ok(ids)
+        }
+    }
+
+    fn show(&mut self, tasks: &[Task], last: &mut String) -> io::Result<()> {
+        let line = progress_line(tasks);
+        if self.live {
+            write!(self.out, "\r{}", line)?;
+            execute!(&mut self.out, Clear(ClearType::UntilNewLine))?;
+        } else {
+            let statuses: Vec<&str> = tasks.iter().map(|t| t.status.as_str()).collect();
+            let statuses = statuses.join(", ");
+            if statuses != *last {
+                writeln!(self.out, "{} ({})", line, statuses)?;
+                *last = statuses;
+            }
+        }
+        Ok(())
+    }
+
+    /* follows the new tasks until they are finished, returns whether all of them were */
+    pub fn wait<D, F>(&mut self, client: &mut D, mut connect: F, before: &[String],
+                      expected: usize) -> Result<bool, Box<dyn error::Error>>
+            where D: DownloadBackend,
+                  F: FnMut() -> Result<D, Box<dyn error::Error>> {
+        let start = Instant::now();
+        let ids = self.find_new(client, &mut connect, before, expected, start)?;
+        let mut last = String::new();
+
+        let res = loop {
+            let tasks: Vec<Task> = self.list(client, &mut connect, start)?.into_iter()
+                .filter(|t| ids.contains(&t.id))
+                .collect();
+            self.show(&tasks, &mut last)?;
+
+            if tasks.len() < ids.len() {
+                break Err(String::from("A task was deleted while waiting for it"));
+            }
+            if let Some(t) = tasks.iter().find(|t| t.is_failed()) {
+                break Err(format!("Download of {} failed: {}", t.title, t.status));
+            }
+            if tasks.iter().all(Task::is_finished) {
+                break Ok(());
+            }
+            if let Some(timeout) = self.timeout.filter(|_| self.timed_out(start)) {
+                break Err(format!("Gave up waiting after {}", duration_text(timeout.as_secs())));
+            }
+            thread::sleep(self.interval);
+        };
+
+        if self.live {
+            writeln!(self.out)?;
+        }
+        match res {
+            Ok(()) => writeln!(self.out, "Download finished")?,
+            Err(ref e) => writeln!(self.out, "{}", e)?
+        };
+        Ok(res.is_ok())
+    }
+}
+
+/* waits with a progress line on stderr */
+pub fn wait_for_new_tasks<D, F>(client: &mut D, connect: F, before: &[String], expected: usize,
+                                timeout: Option<Duration>)
+        -> Result<bool, Box<dyn error::Error>>
+        where D: DownloadBackend,
+              F: FnMut() -> Result<D, Box<dyn error::Error>> {
+    let mut stderr = io::stderr();
+    let live = stderr.is_terminal();
+    let mut waiter = Waiter { out: &mut stderr, live, interval: POLL_INTERVAL, timeout };
+    waiter.wait(client, connect, before, expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::{make_task, no_login, MemoryBackend};
+
+    fn transfer(id: &str, status: &str, downloaded: u64, speed: u64) -> Task {
+        Task { size: 1000000, size_downloaded: downloaded, speed_download: speed,
+               ..make_task(id, &format!("{}.iso", id), status) }
+    }
+
+    fn wait(tasks: Vec<Task>, timeout: Option<Duration>) -> (bool, String) {
+        let mut backend = MemoryBackend::new(tasks);
+        let mut out = vec![];
+        let mut waiter = Waiter { out: &mut out, live: false, interval: Duration::ZERO,
+                                  timeout };
+        let before = [String::from("dbid_1")];
+        let ok = waiter.wait(&mut backend, no_login, &before, 1).unwrap();
+        (ok, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn progress() {
+        let tasks = [transfer("dbid_2", "downloading", 250000, 1000)];
+        let line = progress_line(&tasks);
+        assert!(line.starts_with("[########----------------------]  25.0% "));
+        assert!(line.ends_with("    1.00 KB/s  ETA 12m 30s  dbid_2.iso"));
+
+        let tasks = [transfer("dbid_2", "waiting", 0, 0), transfer("dbid_3", "waiting", 0, 0)];
+        assert!(progress_line(&tasks).ends_with(" 0.0%        0 B/s  ETA      --  2 tasks"));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(duration_text(59), "59s");
+        assert_eq!(duration_text(61), "1m 01s");
+        assert_eq!(duration_text(7380), "2h 03m");
+    }
+
+    #[test]
+    fn finished_task() {
+        let (ok, out) = wait(vec![transfer("dbid_1", "downloading", 0, 0),
+                                  transfer("dbid_2", "seeding", 1000000, 0)], None);
+        assert!(ok);
+        assert!(out.ends_with("(seeding)\nDownload finished\n"));
+    }
+
+    #[test]
+    fn failed_task() {
+        let (ok, out) = wait(vec![transfer("dbid_2", "error", 0, 0)], None);
+        assert!(!ok);
+        assert!(out.ends_with("Download of dbid_2.iso failed: error\n"));
+    }
+
+    #[test]
+    fn timeout() {
+        let (ok, out) = wait(vec![transfer("dbid_2", "downloading", 0, 0)],
+                             Some(Duration::ZERO));
+        assert!(!ok);
+        assert!(out.ends_with("Gave up waiting after 0s\n"));
+    }
+
+    #[test]
+    fn no_new_task() {
+        let mut backend = MemoryBackend::new(vec![transfer("dbid_1", "waiting", 0, 0)]);
+        let mut out = vec![];
+        let mut waiter = Waiter { out: &mut out, live: false, interval: Duration::ZERO,
+                                  timeout: None };
+        let res = waiter.wait(&mut backend, no_login, &[String::from("dbid_1")], 1);
+        assert_eq!(res.err().unwrap().to_string(), "Cannot find the new task in the task list");
+    }
+
+    #[test]
+    fn list_errors_are_tried_again() {
+        let mut backend = MemoryBackend::new(vec![transfer("dbid_2", "seeding", 1000000, 0)]);
+        backend.hiccups = 2;
+        let mut out = vec![];
+        let mut waiter = Waiter { out: &mut out, live: false, interval: Duration::ZERO,
+                                  timeout: None };
+        assert!(waiter.wait(&mut backend, no_login, &[], 1).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Cannot list tasks, trying again: Connection reset\n\
+                                 Cannot list tasks, trying again: Connection reset\n"));
+        assert!(out.ends_with("Download finished\n"));
+
+        /* not for ever though */
+        let mut backend = MemoryBackend::new(vec![]);
+        backend.offline = true;
+        let mut out = vec![];
+        let mut waiter = Waiter { out: &mut out, live: false, interval: Duration::ZERO,
+                                  timeout: Some(Duration::from_millis(10)) };
+        let res = waiter.wait(&mut backend, no_login, &[], 1);
+        assert_eq!(res.err().unwrap().to_string(), "Connection refused");
+    }
+
+    #[test]
+    fn expired_session_logs_in_again() {
+        let mut backend = MemoryBackend::new(vec![transfer("dbid_2", "downloading", 0, 0)]);
+        let mut out = vec![];
+        let mut waiter = Waiter { out: &mut out, live: false, interval: Duration::ZERO,
+                                  timeout: None };
+        let ids = waiter.find_new(&mut backend, &mut no_login, &[], 1, Instant::now()).unwrap();
+        assert_eq!(ids, ["dbid_2"]);
+
+        backend.expired = true;
+        let connect = || Ok(MemoryBackend::new(vec![transfer("dbid_2", "finished", 1000000, 0)]));
+        assert!(waiter.wait(&mut backend, connect, &[], 1).unwrap());
+        assert!(!backend.expired);
+    }
+}
@@ -0,0 +1,292 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{error, fs, io, path::{Path, PathBuf}};
+
+#[cfg(target_os = "linux")]
+use inotify::{EventMask, Inotify, WatchMask};
+use crate::{backend::{is_session_error, DownloadBackend},
+            duplicate::{already_added, find_file, find_link}};
+
+/* what we do with a file that shows up in the watched directory */
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Upload,
+    Links
+}
+
+fn kind(path: &Path) -> Option<Kind> {
+    match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "torrent" | "nzb" => Some(Kind::Upload),
+        "magnet" | "url" => Some(Kind::Links),
+        _ => None
+    }
+}
+
+/* links in a .magnet file or a Windows .url shortcut ([InternetShortcut] with URL=...) */
+fn links(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with('['))
+        .filter_map(|l| match l.split_once('=') {
+            Some((key, value)) if key.eq_ignore_ascii_case("url") => Some(value),
+            /* other Key=Value lines of a shortcut */
+            Some((key, _)) if !key.contains(':') => None,
+            _ => Some(l)
+        })
+        .map(String::from)
+        .collect()
+}
+
+fn add_file<D: DownloadBackend>(client: &mut D, path: &Path)
+        -> Result<(), Box<dyn error::Error>> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    match kind(path) {
+        Some(Kind::Upload) => {
+            let data = fs::read(path)?;
+            match find_file(&data, &client.list()?) {
+                Some(task) => Err(already_added(task)),
+                None => client.upload(&name, &data)
+            }
+        },
+        Some(Kind::Links) => {
+            let links = links(&fs::read_to_string(path)?);
+            if links.is_empty() {
+                return Err(Box::new(io::Error::other("No link found in the file")));
+            }
+
+            let tasks = client.list()?;
+            let mut errors: Vec<String> = vec![];
+            let mut new: Vec<&str> = vec![];
+            for link in &links {
+                match find_link(link, &tasks) {
+                    Some(task) => errors.push(format!("{}: {}", link, already_added(task))),
+                    None => new.push(link)
+                }
+            }
+            errors.extend(new.iter()
+                .zip(client.add_many(&new))
+                .filter_map(|(link, res)| res.err().map(|e| format!("{}: {}", link, e))));
+            match errors.is_empty() {
+                true => Ok(()),
+                false => Err(Box::new(io::Error::other(errors.join("\n"))))
+            }
+        },
+        None => Ok(())
+    }
+}
+
+/* moves a handled file into DIR/added or DIR/failed without replacing older ones */
+fn file_away(dir: &Path, sub: &str, path: &Path) -> io::Result<PathBuf> {
+    let target_dir = dir.join(sub);
+    fs::create_dir_all(&target_dir)?;
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path.extension().unwrap_or_default().to_string_lossy();
+    let mut target = target_dir.join(path.file_name().unwrap_or_default());
+    let mut n = 1;
+    while target.exists() {
+        target = target_dir.join(format!("{}-{}.{}", stem, n, ext));
+        n += 1;
+    }
+
+    fs::rename(path, &target)?;
+    Ok(target)
+}
+
+pub struct Watcher<'a, D, F> {
+    dir: &'a Path,
+    client: D,
+    connect: F
+}
+
+impl<'a, D, F> Watcher<'a, D, F>
+        where D: DownloadBackend,
+              F: FnMut() -> Result<D, Box<dyn error::Error>> {
+    pub fn new(dir: &'a Path, client: D, connect: F) -> Watcher<'a, D, F> {
+        Watcher { dir, client, connect }
+    }
+
+    fn add(&mut self, path: &Path) -> Result<(), Box<dyn error::Error>> {
+        match add_file(&mut self.client, path) {
+            /* the session may have expired while we were waiting for files */
+            Err(e) if is_session_error(e.as_ref()) => {
+                self.client = (self.connect)()?;
+                add_file(&mut self.client, path)
+            },
+            res => res
+        }
+    }
+
+    /* adds the file if it is one we know, returns where it ended up */
+    pub fn handle(&mut self, path: &Path) -> io::Result<Option<PathBuf>> {
+        if kind(path).is_none() || !path.is_file() {
+            return Ok(None);
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+
+        match self.add(path) {
+            Ok(()) => {
+                println!("Added {}", name);
+                file_away(self.dir, "added", path).map(Some)
+            },
+            Err(e) => {
+                eprintln!("Failed to add {}: {}", name, e);
+                let target = file_away(self.dir, "failed", path)?;
+                let mut note = target.clone().into_os_string();
+                note.push(".error");
+                fs::write(note, format!("{}\n", e))?;
+                Ok(Some(target))
+            }
+        }
+    }
+
+    /* files that were saved while nobody was watching */
+    fn handle_existing(&mut self) -> io::Result<()> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(self.dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            self.handle(&path)?;
+        }
+        Ok(())
+    }
+
+    /* runs until interrupted or the directory goes away */
+    #[cfg(target_os = "linux")]
+    pub fn run(&mut self) -> Result<(), Box<dyn error::Error>> {
+        let mut inotify = Inotify::init()?;
+        inotify.watches().add(self.dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO
+                              | WatchMask::DELETE_SELF | WatchMask::MOVE_SELF
+                              | WatchMask::ONLYDIR)?;
+
+        println!("Watching {} for new files, press Ctrl-C to stop", self.dir.display());
+        self.handle_existing()?;
+
+        let mut buffer = [0; 4096];
+        loop {
+            let mut paths = vec![];
+            for event in inotify.read_events_blocking(&mut buffer)? {
+                if event.mask.intersects(EventMask::DELETE_SELF | EventMask::MOVE_SELF) {
+                    return Err(Box::new(io::Error::other(format!(
+                        "{} was removed", self.dir.display()))));
+                }
+                if let Some(name) = event.name {
+                    paths.push(self.dir.join(name));
+                }
+            }
+
+            for path in paths {
+                self.handle(&path)?;
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn run(&mut self) -> Result<(), Box<dyn error::Error>> {
+        Err(Box::new(io::Error::other("Watching a directory only works on Linux")))
+    }
+
+    pub fn logout(&mut self) -> Result<(), Box<dyn error::Error>> {
+        self.client.logout()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory::{no_login, MemoryBackend};
+
+    #[test]
+    fn kinds() {
+        assert_eq!(kind(Path::new("a/debian.torrent")), Some(Kind::Upload));
+        assert_eq!(kind(Path::new("debian.NZB")), Some(Kind::Upload));
+        assert_eq!(kind(Path::new("debian.magnet")), Some(Kind::Links));
+        assert_eq!(kind(Path::new("debian.url")), Some(Kind::Links));
+        assert_eq!(kind(Path::new("debian.torrent.part")), None);
+        assert_eq!(kind(Path::new("torrent")), None);
+    }
+
+    #[test]
+    fn links_in_files() {
+        assert_eq!(links("magnet:?xt=urn:btih:abc&dn=x\n"), ["magnet:?xt=urn:btih:abc&dn=x"]);
+
+        let shortcut = "[InternetShortcut]\r\nURL=https://example.com/a.iso?x=1\r\nIconIndex=0\r\n";
+        assert_eq!(links(shortcut), ["https://example.com/a.iso?x=1"]);
+
+        assert!(links("# nothing\n\n").is_empty());
+    }
+
+    #[test]
+    fn handle_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        fs::write(path("a.torrent"), "d8:announce0:e").unwrap();
+        fs::write(path("b.magnet"), "magnet:?xt=1\nmagnet:?xt=2\n").unwrap();
+        fs::write(path("c.torrent"), "").unwrap();
+        fs::write(path("d.txt"), "magnet:?xt=3").unwrap();
+        fs::create_dir(path("added")).unwrap();
+        fs::write(path("added/a.torrent"), "older").unwrap();
+
+        let mut watcher = Watcher::new(dir.path(), MemoryBackend::new(vec![]), no_login);
+        watcher.handle_existing().unwrap();
+
+        let titles: Vec<&str> = watcher.client.tasks.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, ["a.torrent", "magnet:?xt=1", "magnet:?xt=2"]);
+
+        assert!(path("added/a-1.torrent").exists());
+        assert!(path("added/b.magnet").exists());
+        assert!(path("failed/c.torrent").exists());
+        assert_eq!(fs::read_to_string(path("failed/c.torrent.error")).unwrap(),
+                   "File upload failed\n");
+        assert!(path("d.txt").exists());
+    }
+
+    #[test]
+    fn partly_failed_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("x.url");
+        fs::write(&file, "magnet:?xt=1\nURL=\n").unwrap();
+
+        let mut watcher = Watcher::new(dir.path(), MemoryBackend::new(vec![]), no_login);
+        let target = watcher.handle(&file).unwrap().unwrap();
+        assert_eq!(target, dir.path().join("failed/x.url"));
+        assert_eq!(fs::read_to_string(dir.path().join("failed/x.url.error")).unwrap(),
+                   ": Invalid parameter\n");
+    }
+
+    #[test]
+    fn duplicate_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("x.url");
+        fs::write(&file, "https://example.com/a.iso\nhttps://example.com/b.iso\n").unwrap();
+
+        let mut watcher = Watcher::new(dir.path(), MemoryBackend::new(vec![]), no_login);
+        watcher.client.add("https://example.com/a.iso#top").unwrap();
+        let target = watcher.handle(&file).unwrap().unwrap();
+        assert_eq!(target, dir.path().join("failed/x.url"));
+        assert_eq!(fs::read_to_string(dir.path().join("failed/x.url.error")).unwrap(),
+                   "https://example.com/a.iso: Already added as \
+                    https://example.com/a.iso#top (dbid_1)\n");
+        assert_eq!(watcher.client.tasks.len(), 2);
+    }
+}
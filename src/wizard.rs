@@ -0,0 +1,297 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use std::{error, fs, io::{self, BufRead, Write}, path::{Path, PathBuf}, time::Duration};
+
+use url::Url;
+
+use synodl::syno::{fingerprint, peer_certificates, ClientConfig, SynoClient};
+
+use crate::config::{validate_url, Config};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type ReadPassword<'a> = &'a mut dyn FnMut(&str) -> io::Result<String>;
+
+/* Asks the questions, either on the terminal or from a script in tests */
+pub struct Prompter<'a> {
+    pub input: &'a mut dyn BufRead,
+    pub output: &'a mut dyn Write,
+    /* reads without echo; without one passwords are read like any answer */
+    pub read_password: Option<ReadPassword<'a>>
+}
+
+impl Prompter<'_> {
+    fn ask(&mut self, question: &str, default: Option<&str>) -> io::Result<String> {
+        loop {
+            match default {
+                Some(d) => write!(self.output, "{} [{}]: ", question, d)?,
+                None => write!(self.output, "{}: ", question)?
+            }
+            self.output.flush()?;
+
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "No more input"));
+            }
+
+            match (line.trim(), default) {
+                ("", Some(d)) => return Ok(String::from(d)),
+                ("", None) => continue,
+                (answer, _) => return Ok(String::from(answer))
+            }
+        }
+    }
+
+    /* one of several single-letter answers */
+    fn choose(&mut self, question: &str, choices: &[char], default: char) -> io::Result<char> {
+        loop {
+            let answer = self.ask(question, Some(&default.to_string()))?;
+            let c = answer.chars().next().unwrap_or(default).to_ascii_lowercase();
+            if choices.contains(&c) {
+                return Ok(c);
+            }
+        }
+    }
+
+    fn confirm(&mut self, question: &str, default: bool) -> io::Result<bool> {
+        let default = if default { 'y' } else { 'n' };
+        Ok(self.choose(question, &['y', 'n'], default)? == 'y')
+    }
+
+    fn password(&mut self, prompt: &str) -> io::Result<String> {
+        match &mut self.read_password {
+            Some(read) => read(prompt),
+            None => self.ask(prompt, None)
+        }
+    }
+
+    fn say(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.output, "{}", text)
+    }
+}
+
+/* the certificate file for "trust", next to the configuration */
+fn cert_path(config: &Path) -> PathBuf {
+    let mut name = config.as_os_str().to_owned();
+    name.push("-cert.der");
+    PathBuf::from(name)
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    /* the mode only applies to new files */
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(content)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    fs::write(path, content)
+}
+
+fn ask_password_source(p: &mut Prompter, cfg: &mut Config)
+        -> Result<String, Box<dyn error::Error>> {
+    p.say("Where should the password come from?")?;
+    p.say("  [s]tored in the configuration file")?;
+    p.say("  [c]ommand that prints it, e.g. a password manager")?;
+    p.say("  [a]sk for it every time")?;
+
+    match p.choose("Password source", &['s', 'c', 'a'], 'a')? {
+        's' => {
+            let password = p.password("Password")?;
+            cfg.password = Some(password.clone());
+            Ok(password)
+        },
+        'c' => loop {
+            cfg.password_command = Some(p.ask("Command", None)?);
+            match cfg.password() {
+                Ok(password) => return Ok(password),
+                Err(e) => p.say(&e.to_string())?
+            }
+        },
+        _ => Ok(p.password("Password (will not be saved)")?)
+    }
+}
+
+/* shows the certificate and asks what to do about it */
+fn ask_certificate(p: &mut Prompter, cfg: &mut Config, path: &Path)
+        -> Result<(), Box<dyn error::Error>> {
+    let chain = peer_certificates(&Url::parse(&cfg.url)?, CONNECT_TIMEOUT)?;
+    let fp = fingerprint(&chain[0]);
+
+    p.say("The DiskStation presented a certificate with the SHA-256 fingerprint")?;
+    p.say(&format!("  {}", fp))?;
+    p.say("Compare it with the one in DSM under Control Panel > Security > Certificate.")?;
+    p.say("  [p]in this fingerprint, synodl refuses to log in if it changes")?;
+    p.say("  [t]rust the authority that issued it, the certificate name must match")?;
+    p.say("     the host in the URL")?;
+    p.say("  [i]gnore certificates altogether")?;
+
+    match p.choose("Certificate", &['p', 't', 'i'], 'p')? {
+        'p' => cfg.fingerprint = Some(fp),
+        't' => {
+            let cert = cert_path(path);
+            /* the last certificate in the chain is the closest to the root */
+            write_private(&cert, &chain[chain.len() - 1])?;
+            cfg.cacert = Some(cert.to_string_lossy().into_owned());
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn init(path: &Path, p: &mut Prompter) -> Result<(), Box<dyn error::Error>> {
+    if path.exists()
+            && !p.confirm(&format!("{} exists, overwrite it?", path.display()), false)? {
+        p.say("Nothing written")?;
+        return Ok(());
+    }
+
+    let url = loop {
+        let url = p.ask("URL of your DiskStation", Some("https://diskstation:5001/"))?;
+        match validate_url("new", &url) {
+            Ok(()) => break url,
+            Err(e) => p.say(&e.to_string())?
+        }
+    };
+    let user = p.ask("User name", None)?;
+
+    let mut cfg = Config { user, url, ..Config::default() };
+    let password = ask_password_source(p, &mut cfg)?;
+
+    p.say(&format!("Connecting to {} ...", cfg.url))?;
+    if cfg.url.starts_with("https:") {
+        ask_certificate(p, &mut cfg, path)?;
+    }
+
+    let mut client_cfg = ClientConfig::new(&cfg.url, &cfg.user, &password);
+    cfg.apply_tls(&mut client_cfg);
+    match SynoClient::login(&client_cfg) {
+        Ok(client) => {
+            let _ = client.logout();
+            p.say("Login successful")?;
+        },
+        Err(e) => {
+            p.say(&format!("Login failed: {}", e))?;
+            if !p.confirm("Save the configuration anyway?", false)? {
+                p.say("Nothing written")?;
+                return Ok(());
+            }
+        }
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_private(path, serde_ini::to_string(&cfg)?.as_bytes())?;
+    p.say(&format!("Configuration written to {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use synodl::fake::FakeServer;
+
+    use super::*;
+    use crate::config::load_config;
+
+    fn run_init(path: &Path, answers: &str) -> String {
+        let mut input = answers.as_bytes();
+        let mut output = vec![];
+        let mut read_password = |_: &str| Ok(String::from("secret"));
+        let mut p = Prompter {
+            input: &mut input,
+            output: &mut output,
+            read_password: Some(&mut read_password)
+        };
+
+        init(path, &mut p).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn stored_password() {
+        let server = FakeServer::start().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("synodl").join("config");
+
+        let out = run_init(&path, &format!("{}\nadmin\ns\n", server.url()));
+        assert!(out.contains("Login successful"));
+
+        let config = load_config(&path).unwrap();
+        assert!(config.warnings.is_empty());
+        let cfg = &config.profiles[0].config;
+        assert_eq!(cfg.url, server.url());
+        assert_eq!(cfg.user, "admin");
+        assert_eq!(cfg.password.as_deref(), Some("secret"));
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn password_command_is_retried() {
+        let server = FakeServer::start().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+
+        let out = run_init(&path, &format!("ftp://nas/\n{}\nadmin\nc\nfalse\necho secret\n",
+                                           server.url()));
+        assert!(out.contains("unsupported scheme ftp"));
+        assert!(out.contains("exited with status 1"));
+
+        let config = load_config(&path).unwrap();
+        let cfg = &config.profiles[0].config;
+        assert_eq!(cfg.password, None);
+        assert_eq!(cfg.password_command.as_deref(), Some("echo secret"));
+    }
+
+    #[test]
+    fn failed_login_writes_nothing() {
+        let server = FakeServer::start().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+
+        let out = run_init(&path, &format!("{}\nnobody\na\n\n", server.url()));
+        assert!(out.contains("Login failed"));
+        assert!(out.ends_with("Nothing written\n"));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn keep_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+        fs::write(&path, "precious").unwrap();
+
+        run_init(&path, "\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "precious");
+    }
+}
@@ -0,0 +1,297 @@
+/*
+
+SynoDL - CLI for Synology's DownloadStation
+Copyright (C) 2015 - 2023  Stefan Ott
+
+This program is free software: you can redistribute it and/or
+modify it under the terms of the GNU General Public License as
+published by the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+*/
+
+use synodl::{
+    fake::{FakeOptions, FakeServer, FakeTask},
+    syno::{ClientConfig, Error, Limits, SynoClient, Traffic},
+};
+
+fn start(ds2: bool) -> FakeServer {
+    let options = FakeOptions { ds2, ..FakeOptions::default() };
+    FakeServer::start_with(options, "127.0.0.1:0").unwrap()
+}
+
+fn login(server: &FakeServer) -> SynoClient {
+    let cfg = ClientConfig::new(server.url(), "admin", "secret");
+    SynoClient::login(&cfg).unwrap()
+}
+
+/* runs a test against both the legacy API and DownloadStation2 */
+fn both_apis<F: Fn(&FakeServer, &SynoClient)>(test: F) {
+    for ds2 in [false, true] {
+        let server = start(ds2);
+        let client = login(&server);
+        test(&server, &client);
+    }
+}
+
+fn api_code<T>(res: Result<T, Error>) -> Option<u32> {
+    match res {
+        Ok(_) => None,
+        Err(e) => e.code(),
+    }
+}
+
+#[test]
+fn login_with_wrong_password() {
+    let server = start(true);
+    let cfg = ClientConfig::new(server.url(), "admin", "wrong");
+    let err = SynoClient::login(&cfg).err().unwrap();
+
+    assert_eq!(err.code(), Some(400));
+    assert_eq!(err.to_string(), "No such account or incorrect password (error 400)");
+    assert_eq!(server.sessions(), 0);
+}
+
+#[test]
+fn login_to_unreachable_server() {
+    let cfg = ClientConfig::new("http://127.0.0.1:1/", "admin", "secret");
+    assert!(matches!(SynoClient::login(&cfg), Err(Error::Http(_))));
+}
+
+#[test]
+fn logout_ends_session() {
+    both_apis(|server, client| {
+        assert_eq!(server.sessions(), 1);
+        client.logout().unwrap();
+        assert_eq!(server.sessions(), 0);
+    });
+}
+
+#[test]
+fn list_tasks_newest_first() {
+    both_apis(|_, client| {
+        let tasks = client.list().unwrap();
+        let ids: Vec<&str> = tasks.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["dbid_5", "dbid_4", "dbid_3", "dbid_2", "dbid_1"]);
+
+        let statuses: Vec<&str> = tasks.iter().map(|t| t.status.as_str()).collect();
+        assert_eq!(statuses, ["error", "finished", "seeding", "paused", "downloading"]);
+
+        assert_eq!(tasks[4].size_downloaded, 158998272);
+        assert_eq!(tasks[2].speed_upload, 83923);
+        assert_eq!(tasks[0].destination, "downloads");
+        assert_eq!(tasks[1].uri, "https://example.com/KNOPPIX 9.1 DVD");
+        assert_eq!(tasks[2].seed_elapsed, 3 * 86400);
+        assert_eq!(tasks[1].completed_time, tasks[1].create_time + 2 * 86400);
+        assert_eq!(tasks[4].completed_time, 0);
+        assert_eq!(tasks[0].error_detail, "timeout");
+        assert_eq!(tasks[1].error_detail, "");
+        assert!(tasks[0].is_failed() && tasks[1].is_finished() && tasks[2].is_finished());
+        assert!(!tasks[3].is_finished() && !tasks[3].is_failed());
+    });
+}
+
+#[test]
+fn list_pages_through_all_tasks() {
+    for ds2 in [false, true] {
+        let mut options = FakeOptions { ds2, page_limit: 2, ..FakeOptions::default() };
+        for i in 6..12 {
+            options.tasks.push(FakeTask::new(&format!("dbid_{}", i), "task", "waiting", 10));
+        }
+        let server = FakeServer::start_with(options, "127.0.0.1:0").unwrap();
+        let client = login(&server);
+
+        let tasks = client.list().unwrap();
+        assert_eq!(tasks.len(), 11);
+        assert_eq!(tasks[0].id, "dbid_11");
+        assert_eq!(tasks[10].id, "dbid_1");
+    }
+}
+
+#[test]
+fn add_task() {
+    both_apis(|server, client| {
+        client.download("https://example.com/files/grml.iso").unwrap();
+
+        let task = server.tasks().pop().unwrap();
+        assert_eq!(task.uri, "https://example.com/files/grml.iso");
+        assert_eq!(task.status, "waiting");
+
+        let tasks = client.list().unwrap();
+        assert_eq!(tasks.len(), 6);
+        assert_eq!(tasks[0].title, "grml.iso");
+    });
+}
+
+#[test]
+fn add_several_tasks() {
+    both_apis(|server, client| {
+        client.download_many(&["https://example.com/a.iso", "magnet:?xt=urn:btih:b"]).unwrap();
+
+        let uris: Vec<String> = server.tasks().into_iter().skip(5).map(|t| t.uri).collect();
+        assert_eq!(uris, ["https://example.com/a.iso", "magnet:?xt=urn:btih:b"]);
+    });
+}
+
+#[test]
+fn upload_torrent_file() {
+    both_apis(|server, client| {
+        client.upload("grml.torrent", b"d8:announce0:e").unwrap();
+
+        let task = server.tasks().pop().unwrap();
+        assert_eq!(task.title, "grml");
+        assert_eq!(task.status, "waiting");
+
+        assert_eq!(api_code(client.upload("empty.torrent", b"")), Some(400));
+        assert_eq!(server.tasks().len(), 6);
+    });
+}
+
+#[test]
+fn add_empty_task() {
+    both_apis(|server, client| {
+        assert_eq!(api_code(client.download("")), Some(101));
+        assert_eq!(server.tasks().len(), 5);
+    });
+}
+
+#[test]
+fn task_progresses_until_finished() {
+    both_apis(|server, client| {
+        let mut task = FakeTask::new("dbid_9", "small.iso", "waiting", 1500000);
+        task.uri = String::from("https://example.com/small.iso");
+        server.put_task(task);
+
+        server.tick();
+        assert_eq!(client.info("dbid_9").unwrap().status, "downloading");
+
+        server.tick();
+        let task = client.info("dbid_9").unwrap();
+        assert_eq!(task.size_downloaded, 1000000);
+        assert!((task.percent_dn - 0.666).abs() < 0.01);
+
+        server.tick();
+        assert_eq!(client.info("dbid_9").unwrap().status, "finished");
+    });
+}
+
+#[test]
+fn pause_and_resume_task() {
+    both_apis(|server, client| {
+        client.pause("dbid_1").unwrap();
+        assert_eq!(server.task("dbid_1").unwrap().status, "paused");
+        assert_eq!(client.info("dbid_1").unwrap().status, "paused");
+
+        client.resume("dbid_1").unwrap();
+        assert_eq!(server.task("dbid_1").unwrap().status, "downloading");
+    });
+}
+
+#[test]
+fn pause_finished_task() {
+    both_apis(|server, client| {
+        assert_eq!(api_code(client.pause("dbid_4")), Some(405));
+        assert_eq!(server.task("dbid_4").unwrap().status, "finished");
+    });
+}
+
+#[test]
+fn resume_complete_task_starts_seeding() {
+    both_apis(|server, client| {
+        client.resume("dbid_5").unwrap();
+        assert_eq!(server.task("dbid_5").unwrap().status, "seeding");
+    });
+}
+
+#[test]
+fn resume_all_resumes_failed_tasks() {
+    both_apis(|server, client| {
+        assert_eq!(client.resume_all().unwrap(), 1);
+        assert_eq!(server.task("dbid_5").unwrap().status, "seeding");
+        assert_eq!(server.task("dbid_2").unwrap().status, "paused");
+
+        /* nothing left to resume, nothing is sent */
+        assert_eq!(client.resume_all().unwrap(), 0);
+    });
+}
+
+#[test]
+fn delete_task() {
+    both_apis(|server, client| {
+        client.delete("dbid_3").unwrap();
+        assert_eq!(server.task("dbid_3"), None);
+        assert_eq!(client.list().unwrap().len(), 4);
+    });
+}
+
+#[test]
+fn unknown_task() {
+    both_apis(|_, client| {
+        assert_eq!(api_code(client.info("dbid_99")), Some(404));
+        assert_eq!(api_code(client.delete("dbid_99")), Some(404));
+        assert_eq!(api_code(client.pause("dbid_99")), Some(404));
+    });
+}
+
+#[test]
+fn change_speed_limits() {
+    both_apis(|server, client| {
+        assert_eq!(client.limits().unwrap(), Limits::default());
+
+        let limits = Limits { bt_download: 2000, bt_upload: 100, http_download: 0,
+                              ftp_download: 500 };
+        client.set_limits(&limits).unwrap();
+        assert_eq!(client.limits().unwrap(), limits);
+        assert_eq!(server.config()["bt_max_upload"], 100);
+        assert_eq!(server.config()["default_destination"], "downloads");
+    });
+}
+
+#[test]
+fn expired_session() {
+    both_apis(|server, client| {
+        server.expire_sessions();
+        let err = client.list().err().unwrap();
+        assert_eq!(err.code(), Some(106));
+        assert_eq!(err.to_string(), "Session timeout (error 106)");
+    });
+}
+
+#[test]
+fn record_and_replay() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = start(true);
+
+    let mut cfg = ClientConfig::new(server.url(), "admin", "secret");
+    cfg.traffic = Traffic::Record(dir.path().to_path_buf());
+    let client = SynoClient::login(&cfg).unwrap();
+    let recorded = client.list().unwrap();
+    client.pause("dbid_1").unwrap();
+    assert_eq!(api_code(client.info("dbid_99")), Some(404));
+    client.logout().unwrap();
+
+    /* nothing secret ends up in the recording */
+    for entry in std::fs::read_dir(dir.path()).unwrap() {
+        let content = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+        assert!(!content.contains("secret"));
+        assert!(!content.contains("fake-sid"));
+    }
+
+    /* replaying works without the server */
+    drop(server);
+    let mut cfg = ClientConfig::new("http://127.0.0.1:1/", "admin", "wrong");
+    cfg.traffic = Traffic::Replay(dir.path().to_path_buf());
+    let client = SynoClient::login(&cfg).unwrap();
+    assert_eq!(client.list().unwrap(), recorded);
+    client.pause("dbid_1").unwrap();
+    assert_eq!(api_code(client.info("dbid_99")), Some(404));
+    assert!(matches!(client.delete("dbid_1"), Err(Error::Recording(_))));
+    client.logout().unwrap();
+}